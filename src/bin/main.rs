@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::Parser;
 use rustyline::error::ReadlineError;
@@ -17,6 +18,27 @@ static HISTORY_FILE: &str = ".yarer_history";
 struct Cli {
     #[arg(short, long)]
     quiet: bool,
+    /// Keeps the divisions between natural numbers exact, as fractions
+    #[arg(short, long)]
+    rational: bool,
+    /// Prints the fractions as decimal numbers
+    #[arg(short, long)]
+    decimals: bool,
+    /// Computes the decimal numbers with arbitrary precision, keeping this number of significant digits
+    #[arg(short, long)]
+    precision: Option<u64>,
+    /// Evaluates with interval arithmetic, widening every variable by this relative tolerance
+    #[arg(long)]
+    interval: Option<f64>,
+    /// The unit of the angles of the trigonometric functions: rad, deg or grad
+    #[arg(short, long, default_value = "rad")]
+    angle: AngleMode,
+    /// The maximum size in bits of the integers and of the fractions, 0 for no limit
+    #[arg(long, default_value_t = 1_000_000)]
+    max_bits: u64,
+    /// The maximum time in seconds of an evaluation, 0 for no limit
+    #[arg(long, default_value_t = 10.)]
+    timeout: f64,
 }
 
 /**
@@ -63,6 +85,19 @@ fn main() -> Result<()> {
     let _ = rl.load_history(local_history);
 
     let session = Session::init();
+    if cli.rational {
+        session.set_numeric_mode(NumericMode::Rational);
+    }
+    if let Some(precision) = cli.precision {
+        session.set_numeric_mode(NumericMode::Decimal { precision, rounding: RoundingMode::HalfEven });
+    }
+    session.set_interval_mode(cli.interval);
+    session.set_angle_mode(cli.angle);
+    session.set_limits(Limits {
+        max_bits: (cli.max_bits > 0).then_some(cli.max_bits),
+        max_time: Duration::try_from_secs_f64(cli.timeout).ok().filter(|t| !t.is_zero()),
+        ..Limits::default()
+    });
     loop {
         let readline = rl.readline("> ");
 
@@ -77,9 +112,19 @@ fn main() -> Result<()> {
 
                 let _ = rl.add_history_entry(line.as_str());
 
+                // ':simplify <expr>' prints the expression simplified, rather than its value
+                if let Some(expr) = line.trim().strip_prefix(":simplify ") {
+                    match session.process(expr).expression() {
+                        Ok(expr) => println!("{}", expr.simplify()),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
+                }
+
                 let mut resolver: RpnResolver = session.process(&line);
 
                 match resolver.resolve() {
+                    Ok(value) if cli.decimals => println!("{:#}", value),
                     Ok(value) => println!("{}", value),
                     Err(e) => println!("Error: {}", e),
                 }
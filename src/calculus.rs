@@ -0,0 +1,291 @@
+use anyhow::anyhow;
+
+use crate::{error::MathError, token::MathFunction};
+
+static SOLVE_ERR: &str = "Runtime error: No root has been found from";
+static INTEGRATE_ERR: &str = "Runtime error: The integral doesn't converge between";
+
+/// The iterations of the methods before giving up, and their relative tolerance
+///
+const MAX_STEPS: usize = 200;
+const TOLERANCE: f64 = 4. * f64::EPSILON;
+
+/// The most subintervals of an integral, and the relative tolerance of its estimated error
+///
+const MAX_INTERVALS: usize = 2000;
+const INTEGRAL_TOLERANCE: f64 = 1e-13;
+
+/// The nodes of the 15 points Gauss–Kronrod rule in [0, 1], and their weights, of which the odd ones
+/// are the nodes of the embedded 7 points Gauss rule
+///
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6, 0.949_107_912_342_758_5, 0.864_864_423_359_769_1, 0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1, 0.405_845_151_377_397_2, 0.207_784_955_007_898_5, 0.,
+];
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22, 0.063_092_092_629_978_55, 0.104_790_010_322_250_2, 0.140_653_259_715_525_9,
+    0.169_004_726_639_267_9, 0.190_350_578_064_785_4, 0.204_432_940_075_298_9, 0.209_482_141_084_727_8,
+];
+const GAUSS_WEIGHTS: [f64; 4] = [0.129_484_966_168_869_7, 0.279_705_391_489_276_7, 0.381_830_050_505_118_9, 0.417_959_183_673_469_4];
+
+/// A real function of a real variable, that evaluates an expression
+///
+pub(crate) type Function<'a> = &'a mut dyn FnMut(f64) -> anyhow::Result<f64>;
+
+/// Evaluates a [`MathFunction`] of an expression of a bound variable, given as a real function:
+/// 'solve(expr, x, guess)' by Newton's method, falling back to the secant method when the
+/// derivative vanishes or the steps don't converge, and 'solve(expr, x, a, b)' by Newton's method
+/// kept inside an interval where the expression changes sign, bisecting it whenever a step would leave it.
+/// 'integrate(expr, x, a, b)' is adaptive Gauss–Kronrod quadrature, and 'deriv(expr, x, at)'
+/// extrapolates the central differences of shrinking steps by the method of Ridders.
+///
+pub(crate) fn function(fun: MathFunction, args: &[f64], f: Function) -> anyhow::Result<f64> {
+    match (fun, args) {
+        (MathFunction::Solve, [guess]) => match newton(f, *guess)? {
+            Some(root) => Ok(root),
+            None => secant(f, *guess)?.ok_or_else(|| anyhow!("{SOLVE_ERR} {guess}.")),
+        },
+        (MathFunction::Solve, [a, b]) => bracketed(f, a.min(*b), a.max(*b)),
+        (MathFunction::Integrate, [a, b]) if a.is_finite() && b.is_finite() => integral(f, *a, *b),
+        (MathFunction::Integrate, [a, b]) => Err(MathError::domain(fun, if a.is_finite() { b } else { a }).into()),
+        (MathFunction::Deriv, [at]) => ridders(f, *at),
+        (MathFunction::Solve, _) => Err(arguments_error(fun, "a guess or the two ends of an interval")),
+        (MathFunction::Integrate, _) => Err(arguments_error(fun, "the two ends of the interval")),
+        _ => Err(arguments_error(fun, "the point")),
+    }
+}
+
+fn arguments_error(fun: MathFunction, rest: &str) -> anyhow::Error {
+    anyhow!("Runtime error: Function '{fun}' takes an expression, its variable, and {rest}.")
+}
+
+/// The derivative by the central difference, with a step that balances the truncation
+/// and the rounding errors
+///
+fn derivative(f: Function, x: f64) -> anyhow::Result<f64> {
+    let h = f64::EPSILON.cbrt() * x.abs().max(1.);
+    Ok((f(x + h)? - f(x - h)?) / (2. * h))
+}
+
+fn converged(step: f64, x: f64) -> bool {
+    step.abs() <= TOLERANCE * x.abs().max(1.)
+}
+
+/// Newton's method from the guess, or [`None`] when it doesn't converge
+///
+fn newton(f: Function, guess: f64) -> anyhow::Result<Option<f64>> {
+    let mut x = guess;
+    for _ in 0..MAX_STEPS {
+        let fx = f(x)?;
+        if fx == 0. {
+            return Ok(Some(x));
+        }
+        let step = fx / derivative(f, x)?;
+        if !step.is_finite() {
+            return Ok(None);
+        }
+        x -= step;
+        if converged(step, x) {
+            return Ok(Some(x));
+        }
+    }
+    Ok(None)
+}
+
+/// The secant method from the guess and a point close to it, or [`None`] when it doesn't converge
+///
+fn secant(f: Function, guess: f64) -> anyhow::Result<Option<f64>> {
+    let (mut previous, mut x) = (guess, guess + 1e-4 * guess.abs().max(1.));
+    let (mut f_previous, mut fx) = (f(previous)?, f(x)?);
+    for _ in 0..MAX_STEPS {
+        if fx == 0. {
+            return Ok(Some(x));
+        }
+        let step = fx * (x - previous) / (fx - f_previous);
+        if !step.is_finite() {
+            return Ok(None);
+        }
+        (previous, f_previous) = (x, fx);
+        x -= step;
+        if converged(step, x) {
+            return Ok(Some(x));
+        }
+        fx = f(x)?;
+    }
+    Ok(None)
+}
+
+/// Newton's method safeguarded by bisection, inside an interval where the function changes sign
+///
+fn bracketed(f: Function, a: f64, b: f64) -> anyhow::Result<f64> {
+    let (mut low, mut high) = (a, b);
+    let (f_low, f_high) = (f(low)?, f(high)?);
+    if f_low == 0. || f_high == 0. {
+        return Ok(if f_low == 0. { low } else { high });
+    }
+    if f_low.signum() == f_high.signum() || f_low.is_nan() || f_high.is_nan() {
+        return Err(MathError::domain(MathFunction::Solve, format!("[{a}, {b}], where it doesn't change sign")).into());
+    }
+    let rising = f_high > 0.;
+    let mut x = (low + high) * 0.5;
+    for _ in 0..MAX_STEPS {
+        let fx = f(x)?;
+        if fx == 0. {
+            return Ok(x);
+        }
+        if (fx > 0.) == rising {
+            high = x;
+        } else {
+            low = x;
+        }
+        let newton = x - fx / derivative(f, x)?;
+        let next = if newton > low && newton < high { newton } else { (low + high) * 0.5 };
+        if converged(next - x, next) || converged(high - low, x) {
+            return Ok(next);
+        }
+        x = next;
+    }
+    Ok(x)
+}
+
+/// The 15 points Gauss–Kronrod estimate of the integral between a and b, the estimate of its error
+/// as the difference from the 7 points Gauss rule, and the integral of the absolute value
+///
+fn kronrod(f: Function, a: f64, b: f64) -> anyhow::Result<(f64, f64, f64)> {
+    let (center, half) = ((a + b) * 0.5, (b - a) * 0.5);
+    let (mut kronrod, mut gauss, mut absolute) = (0., 0., 0.);
+    for (i, (node, weight)) in KRONROD_NODES.iter().zip(KRONROD_WEIGHTS).enumerate() {
+        let values = if *node == 0. { [f(center)?, 0.] } else { [f(center - half * node)?, f(center + half * node)?] };
+        kronrod += weight * (values[0] + values[1]);
+        absolute += weight * (values[0].abs() + values[1].abs());
+        if i % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[i / 2] * (values[0] + values[1]);
+        }
+    }
+    Ok((kronrod * half, ((kronrod - gauss) * half).abs(), absolute * half.abs()))
+}
+
+/// The integral between a and b, bisecting the subinterval of the largest error
+/// until the sum of the errors is within the tolerance
+///
+fn integral(f: Function, a: f64, b: f64) -> anyhow::Result<f64> {
+    let (value, error, absolute) = kronrod(f, a, b)?;
+    let mut intervals = vec![(a, b, value, error, absolute)];
+    while intervals.len() < MAX_INTERVALS {
+        let (total, error, absolute) = intervals.iter().fold((0., 0., 0.), |(v, e, s), i| (v + i.2, e + i.3, s + i.4));
+        if !total.is_finite() {
+            return Ok(total);
+        }
+        if error <= INTEGRAL_TOLERANCE * absolute.max(f64::MIN_POSITIVE) {
+            return Ok(total);
+        }
+        let worst = intervals.iter().enumerate().max_by(|x, y| x.1 .3.total_cmp(&y.1 .3)).map_or(0, |(i, _)| i);
+        let (low, high, ..) = intervals.swap_remove(worst);
+        let middle = (low + high) * 0.5;
+        for (start, end) in [(low, middle), (middle, high)] {
+            let (value, error, absolute) = kronrod(f, start, end)?;
+            intervals.push((start, end, value, error, absolute));
+        }
+    }
+    Err(anyhow!("{INTEGRATE_ERR} {a} and {b}."))
+}
+
+/// The derivative at a point by the method of Ridders: the central differences of steps shrinking
+/// by a constant factor are extrapolated to a zero step in a Neville tableau, stopping when the
+/// error of the higher orders grows. The first step is cut down while it leaves the domain.
+///
+fn ridders(f: Function, at: f64) -> anyhow::Result<f64> {
+    const SHRINK: f64 = 1.4;
+    const ROUNDS: usize = 10;
+    let mut step = 0.1 * at.abs().max(1.);
+    let mut tableau = [[0.; ROUNDS]; ROUNDS];
+    for _ in 0..MAX_STEPS {
+        tableau[0][0] = (f(at + step)? - f(at - step)?) / (2. * step);
+        if tableau[0][0].is_finite() {
+            break;
+        }
+        step *= 0.1;
+    }
+    let (mut result, mut error) = (tableau[0][0], f64::INFINITY);
+    for i in 1..ROUNDS {
+        step /= SHRINK;
+        tableau[0][i] = (f(at + step)? - f(at - step)?) / (2. * step);
+        let mut factor = SHRINK * SHRINK;
+        for j in 1..=i {
+            tableau[j][i] = (tableau[j - 1][i] * factor - tableau[j - 1][i - 1]) / (factor - 1.);
+            factor *= SHRINK * SHRINK;
+            let estimate = (tableau[j][i] - tableau[j - 1][i]).abs().max((tableau[j][i] - tableau[j - 1][i - 1]).abs());
+            if estimate <= error {
+                (result, error) = (tableau[j][i], estimate);
+            }
+        }
+        if (tableau[i][i] - tableau[i - 1][i - 1]).abs() >= 2. * error {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(expr: fn(f64) -> f64, args: &[f64]) -> f64 {
+        function(MathFunction::Solve, args, &mut |x| Ok(expr(x))).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-14 * expected.abs().max(1.), "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_solve() {
+        // the classic example of Wallis
+        let wallis = |x: f64| x.powi(3) - 2. * x - 5.;
+        assert_close(solve(wallis, &[2.]), 2.094_551_481_542_326_5);
+        assert_close(solve(wallis, &[0., 3.]), 2.094_551_481_542_326_5);
+        assert_close(solve(f64::cos, &[1.]), std::f64::consts::FRAC_PI_2);
+        assert_close(solve(f64::cos, &[4., 1.]), std::f64::consts::FRAC_PI_2);
+        assert_close(solve(|x| x * x - 2., &[0., 2.]), std::f64::consts::SQRT_2);
+        // a step function, where Newton's method can't help
+        assert_close(solve(|x| if x < 0.3 { -1. } else { 1. }, &[0., 1.]), 0.3);
+        // the derivative vanishes at the guess
+        assert_close(solve(|x| x * x - 4., &[0.]).abs(), 2.);
+
+        let fails = |expr: fn(f64) -> f64, args: &[f64]| function(MathFunction::Solve, args, &mut |x| Ok(expr(x))).is_err();
+        assert!(fails(|x| x * x + 1., &[1.]));
+        assert!(fails(|x| x * x + 1., &[-1., 1.]));
+        assert!(fails(|x| x, &[]));
+    }
+
+    #[test]
+    fn test_integrate() {
+        let integrate = |expr: fn(f64) -> f64, args: &[f64]| function(MathFunction::Integrate, args, &mut |x| Ok(expr(x)));
+        let assert_close = |actual: f64, expected: f64| assert!((actual - expected).abs() <= 1e-12 * expected.abs().max(1.), "{actual} != {expected}");
+        let pi = std::f64::consts::PI;
+        assert_close(integrate(|x| x.sin().powi(2), &[0., pi]).unwrap(), pi / 2.);
+        assert_close(integrate(|x| x * x, &[3., 0.]).unwrap(), -9.);
+        assert_close(integrate(|x| (-x * x).exp(), &[-10., 10.]).unwrap(), pi.sqrt());
+        assert_close(integrate(f64::sin, &[0., 2. * pi]).unwrap(), 0.);
+        assert_close(integrate(f64::exp, &[1., 1.]).unwrap(), 0.);
+        // the singularities at the ends are never evaluated
+        assert!((integrate(|x| 1. / x.sqrt(), &[0., 1.]).unwrap() - 2.).abs() < 1e-10);
+        assert_close(integrate(|x| x.abs(), &[-1., 2.]).unwrap(), 2.5);
+
+        assert!(integrate(|x| x, &[0., f64::INFINITY]).is_err());
+        assert!(integrate(|x| x, &[0.]).is_err());
+    }
+
+    #[test]
+    fn test_deriv() {
+        let deriv = |expr: fn(f64) -> f64, at: f64| function(MathFunction::Deriv, &[at], &mut |x| Ok(expr(x))).unwrap();
+        let assert_near = |actual: f64, expected: f64| assert!((actual - expected).abs() <= 1e-11 * expected.abs().max(1.), "{actual} != {expected}");
+        assert_near(deriv(|x| x.powi(3), 2.), 12.);
+        assert_near(deriv(f64::sin, 1.), 1f64.cos());
+        assert_near(deriv(f64::exp, 10.), 10f64.exp());
+        assert_near(deriv(f64::ln, 1e-3), 1e3);
+        assert_near(deriv(|x| x * x, 1e6), 2e6);
+
+        assert!(function(MathFunction::Deriv, &[1., 2.], &mut |x| Ok(x)).is_err());
+    }
+}
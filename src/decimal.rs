@@ -0,0 +1,451 @@
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, Context, One, Signed, ToPrimitive, Zero};
+use num::BigInt;
+
+use crate::token::{MathFunction, Number, Operator};
+
+/// Extra digits carried by every intermediate computation, and dropped from the result
+///
+const GUARD_DIGITS: u64 = 10;
+
+/// The upper bound of the iterations of a series or of a Newton's method
+///
+const MAX_ITERATIONS: usize = 10_000;
+
+/// Converts a [Number] to a [`BigDecimal`]. A [`Number::DecimalNumber`] is converted from its
+/// shortest representation, so that '0.1' stays exactly 0.1. Not a number and infinity
+/// can't be converted and give back [`None`].
+///
+pub(crate) fn from_number(n: &Number, ctx: &Context) -> Option<BigDecimal> {
+    match n {
+        Number::NaturalNumber(v) => Some(BigDecimal::from(v.clone())),
+        Number::Rational(v) => Some(div(
+            &BigDecimal::from(v.numer().clone()),
+            &BigDecimal::from(v.denom().clone()),
+            ctx,
+        )),
+        Number::DecimalNumber(v) if v.is_finite() => BigDecimal::from_str(&v.to_string()).ok(),
+        Number::BigDecimal(v) => Some(v.clone()),
+        Number::DecimalNumber(_) | Number::Complex(_) | Number::Quantity(_) | Number::Matrix(_) | Number::Interval(_) | Number::Uncertain(_) => None,
+    }
+}
+
+/// Evaluates a binary [Operator] between two numbers, at least one of which is a
+/// [`Number::BigDecimal`], rounding the result to the precision of the context.
+/// Returns [`None`] when the operation can't give back a [`BigDecimal`].
+///
+pub(crate) fn arithmetic(op: Operator, left: &Number, right: &Number, ctx: &Context) -> Option<Number> {
+    let a = from_number(left, ctx)?;
+    let b = from_number(right, ctx)?;
+
+    let result = match op {
+        Operator::Add => ctx.round_decimal(a + b),
+        Operator::Sub => ctx.round_decimal(a - b),
+        Operator::Mul => ctx.round_decimal(a * b),
+        Operator::Div if b.is_zero() => return None,
+        Operator::Div => div(&a, &b, ctx),
+        Operator::Pow => pow(&a, &b, ctx)?,
+        _ => return None,
+    };
+    Some(Number::BigDecimal(result.normalized()))
+}
+
+/// Evaluates a [`MathFunction`] with the precision of the context.
+/// Returns [`None`] outside of the domain of the function.
+///
+pub(crate) fn function(fun: MathFunction, x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let result = match fun {
+        MathFunction::Sin => sin(x, ctx),
+        MathFunction::Cos => cos(x, ctx),
+        MathFunction::Tan => tan(x, ctx)?,
+        MathFunction::ASin => asin(x, ctx)?,
+        MathFunction::ACos => acos(x, ctx)?,
+        MathFunction::ATan => atan(x, ctx),
+        MathFunction::Ln => ln(x, ctx)?,
+        MathFunction::Log => log10(x, ctx)?,
+        MathFunction::Abs => x.abs(),
+        MathFunction::Sqrt => x.sqrt_with_context(ctx)?,
+        MathFunction::Exp => exp(x, ctx),
+        MathFunction::Log2 => log2(x, ctx)?,
+        MathFunction::Sinh | MathFunction::Cosh | MathFunction::Tanh => hyperbolic(fun, x, ctx),
+        MathFunction::ASinh | MathFunction::ACosh | MathFunction::ATanh => inverse_hyperbolic(fun, x, ctx)?,
+        MathFunction::Cbrt => cbrt(x, ctx),
+        _ => return None,
+    };
+    Some(result.normalized())
+}
+
+/// The context of the intermediate computations: the precision of `ctx`, plus
+/// [`GUARD_DIGITS`] and `extra` digits
+///
+fn working(ctx: &Context, extra: u64) -> Context {
+    ctx.with_prec(ctx.precision().get() + GUARD_DIGITS + extra)
+        .unwrap_or_else(|| ctx.clone())
+}
+
+/// The smallest value that still matters with the precision of `ctx`: 10^-precision
+///
+pub(crate) fn epsilon(ctx: &Context) -> BigDecimal {
+    BigDecimal::new(BigInt::one(), i64::try_from(ctx.precision().get()).unwrap_or(i64::MAX))
+}
+
+/// The exponent of the most significant digit of `x`, i.e. 2 for 123.4
+///
+fn magnitude(x: &BigDecimal) -> i64 {
+    let (_, scale) = x.as_bigint_and_exponent();
+    i64::try_from(x.digits()).unwrap_or(i64::MAX) - 1 - scale
+}
+
+fn div(a: &BigDecimal, b: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let wctx = working(ctx, 0);
+    ctx.round_decimal(a * b.inverse_with_context(&wctx))
+}
+
+/// Pi, by the Machin's formula: pi/4 = 4 atan(1/5) - atan(1/239)
+///
+pub(crate) fn pi(ctx: &Context) -> BigDecimal {
+    let wctx = working(ctx, 0);
+    let a = atan_series(&div(&BigDecimal::one(), &BigDecimal::from(5), &wctx), &wctx);
+    let b = atan_series(&div(&BigDecimal::one(), &BigDecimal::from(239), &wctx), &wctx);
+    ctx.round_decimal(a * BigDecimal::from(16) - b * BigDecimal::from(4))
+}
+
+/// The Euler's number
+///
+pub(crate) fn e(ctx: &Context) -> BigDecimal {
+    exp(&BigDecimal::one(), ctx)
+}
+
+/// e^x = (e^(x/2^k))^(2^k). Halving x k times makes the Taylor series converge quickly,
+/// while each of the k squarings costs a bit of precision.
+///
+pub(crate) fn exp(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let threshold = BigDecimal::new(BigInt::one(), 2);
+    let mut r = x.clone();
+    let mut halvings: u64 = 0;
+    while r.abs() > threshold {
+        r = r.half();
+        halvings += 1;
+    }
+
+    let wctx = working(ctx, halvings / 3 + 1);
+    let eps = epsilon(&wctx);
+    let r = wctx.round_decimal(r);
+
+    let mut sum = BigDecimal::one();
+    let mut term = BigDecimal::one();
+    for n in 1..MAX_ITERATIONS {
+        term = div(&wctx.round_decimal(term * &r), &BigDecimal::from(n as u64), &wctx);
+        if term.abs() < eps {
+            break;
+        }
+        sum += &term;
+    }
+
+    for _ in 0..halvings {
+        sum = wctx.round_decimal(sum.square());
+    }
+    ctx.round_decimal(sum)
+}
+
+/// The natural logarithm. With x = m * 10^k and 1 <= m < 10, ln(x) = ln(m) + k ln(10)
+///
+pub(crate) fn ln(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    if !x.is_positive() {
+        return None;
+    }
+    let k = magnitude(x);
+    let wctx = working(ctx, u64::try_from(k.unsigned_abs().to_string().len()).unwrap_or(0));
+
+    let (digits, scale) = x.as_bigint_and_exponent();
+    let m = BigDecimal::new(digits, scale + k);
+
+    let mut result = ln_newton(&m, &wctx);
+    if k != 0 {
+        result += ln_newton(&BigDecimal::from(10), &wctx) * BigDecimal::from(k);
+    }
+    Some(ctx.round_decimal(result))
+}
+
+/// The logarithm in base 10
+///
+pub(crate) fn log10(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let wctx = working(ctx, 0);
+    Some(div(&ln(x, &wctx)?, &ln_newton(&BigDecimal::from(10), &wctx), ctx))
+}
+
+/// The logarithm in base 2
+///
+fn log2(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let wctx = working(ctx, 0);
+    Some(div(&ln(x, &wctx)?, &ln_newton(&BigDecimal::from(2), &wctx), ctx))
+}
+
+/// sinh(x) = (e^x - e^-x)/2, cosh(x) = (e^x + e^-x)/2 and tanh(x) = sinh(x)/cosh(x)
+///
+fn hyperbolic(fun: MathFunction, x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    // e^x - e^-x cancels out the digits of a small x
+    let wctx = working(ctx, u64::try_from((-magnitude(x)).max(0)).unwrap_or(0));
+    let ex = exp(x, &wctx);
+    let e_x = div(&BigDecimal::one(), &ex, &wctx);
+    match fun {
+        MathFunction::Sinh => ctx.round_decimal((ex - e_x).half()),
+        MathFunction::Cosh => ctx.round_decimal((ex + e_x).half()),
+        _ => div(&(&ex - &e_x), &(ex + e_x), ctx),
+    }
+}
+
+/// asinh(x) = ln(x + sqrt(x^2 + 1)), acosh(x) = ln(x + sqrt(x^2 - 1)) for x >= 1,
+/// and atanh(x) = ln((1 + x) / (1 - x)) / 2 for -1 < x < 1
+///
+fn inverse_hyperbolic(fun: MathFunction, x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    if fun == MathFunction::ASinh && x.is_negative() {
+        // the odd symmetry avoids the cancellation of x + sqrt(x^2 + 1)
+        return inverse_hyperbolic(fun, &-x, ctx).map(|y| -y);
+    }
+    let wctx = working(ctx, u64::try_from((-magnitude(x)).max(0)).unwrap_or(0));
+    let arg = match fun {
+        MathFunction::ASinh => x + (x.square() + BigDecimal::one()).sqrt_with_context(&wctx)?,
+        MathFunction::ACosh if *x >= BigDecimal::one() => x + (x.square() - BigDecimal::one()).sqrt_with_context(&wctx)?,
+        MathFunction::ATanh if x.abs() < BigDecimal::one() => {
+            return Some(ctx.round_decimal(ln(&div(&(BigDecimal::one() + x), &(BigDecimal::one() - x), &wctx), &wctx)?.half()));
+        }
+        _ => return None,
+    };
+    Some(ctx.round_decimal(ln(&arg, &wctx)?))
+}
+
+/// The real cube root, as e^(ln(|x|)/3) with the sign of x
+///
+fn cbrt(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let wctx = working(ctx, 0);
+    let Some(ln) = ln(&x.abs(), &wctx) else { return BigDecimal::zero() };
+    let root = ctx.round_decimal(exp(&div(&ln, &BigDecimal::from(3), &wctx), &wctx));
+    if x.is_negative() { -root } else { root }
+}
+
+/// The natural logarithm of a small positive number with the Halley's method
+/// y' = y + 2 (m - e^y) / (m + e^y), starting from the f64 approximation
+///
+fn ln_newton(m: &BigDecimal, ctx: &Context) -> BigDecimal {
+    // the last digits are noise: the iterations stop a bit before them
+    let eps = epsilon(&ctx.with_prec(ctx.precision().get().saturating_sub(GUARD_DIGITS / 2)).unwrap_or_else(|| ctx.clone()));
+    let mut y = BigDecimal::from_str(&m.to_f64().map_or(0., f64::ln).to_string()).unwrap_or_default();
+
+    for _ in 0..MAX_ITERATIONS {
+        let ey = exp(&y, ctx);
+        let delta = div(&((m - &ey) * BigDecimal::from(2)), &(m + ey), ctx);
+        y = ctx.round_decimal(y + &delta);
+        if delta.abs() < eps {
+            break;
+        }
+    }
+    y
+}
+
+/// The power a^b. Integer exponents are computed by repeated squaring, the others as e^(b ln(a)).
+/// Returns [`None`] for a negative base with a fractional exponent and for 0 with a negative exponent.
+///
+pub(crate) fn pow(a: &BigDecimal, b: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    if b.is_integer() {
+        if let Some(n) = b.to_i64() {
+            return powi(a, n, ctx);
+        }
+    }
+    if a.is_zero() {
+        return b.is_positive().then(BigDecimal::zero);
+    }
+    let wctx = working(ctx, u64::try_from(magnitude(b).max(0)).unwrap_or(0));
+    Some(ctx.round_decimal(exp(&wctx.round_decimal(b * ln(a, &wctx)?), &wctx)))
+}
+
+fn powi(a: &BigDecimal, n: i64, ctx: &Context) -> Option<BigDecimal> {
+    if n < 0 && a.is_zero() {
+        return None;
+    }
+    let mut e = n.unsigned_abs();
+    let wctx = working(ctx, u64::from(64 - e.leading_zeros()));
+    let mut base = a.clone();
+    let mut result = BigDecimal::one();
+    while e > 0 {
+        if e & 1 == 1 {
+            result = wctx.round_decimal(result * &base);
+        }
+        base = wctx.round_decimal(base.square());
+        e >>= 1;
+    }
+    if n < 0 {
+        result = result.inverse_with_context(&wctx);
+    }
+    Some(ctx.round_decimal(result))
+}
+
+/// Brings x into [-pi, pi], with enough extra digits to keep the precision for large values of x
+///
+fn reduce_angle(x: &BigDecimal, ctx: &Context) -> (BigDecimal, Context) {
+    let wctx = working(ctx, u64::try_from(magnitude(x).max(0)).unwrap_or(0));
+    let two_pi = pi(&wctx) * BigDecimal::from(2);
+    let turns = div(x, &two_pi, &wctx).round(0);
+    (wctx.round_decimal(x - turns * two_pi), wctx)
+}
+
+pub(crate) fn sin(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let (r, wctx) = reduce_angle(x, ctx);
+    let eps = epsilon(&wctx);
+    let r2 = wctx.round_decimal(r.square());
+
+    let mut sum = r.clone();
+    let mut term = r;
+    for k in 1..MAX_ITERATIONS as u64 {
+        term = -div(&wctx.round_decimal(term * &r2), &BigDecimal::from((2 * k) * (2 * k + 1)), &wctx);
+        if term.abs() < eps {
+            break;
+        }
+        sum += &term;
+    }
+    ctx.round_decimal(sum)
+}
+
+pub(crate) fn cos(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let (r, wctx) = reduce_angle(x, ctx);
+    let eps = epsilon(&wctx);
+    let r2 = wctx.round_decimal(r.square());
+
+    let mut sum = BigDecimal::one();
+    let mut term = BigDecimal::one();
+    for k in 1..MAX_ITERATIONS as u64 {
+        term = -div(&wctx.round_decimal(term * &r2), &BigDecimal::from((2 * k - 1) * (2 * k)), &wctx);
+        if term.abs() < eps {
+            break;
+        }
+        sum += &term;
+    }
+    ctx.round_decimal(sum)
+}
+
+fn tan(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let wctx = working(ctx, 0);
+    let c = cos(x, &wctx);
+    (!c.is_zero()).then(|| div(&sin(x, &wctx), &c, ctx))
+}
+
+/// atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))): the argument is halved until the series converges quickly
+///
+pub(crate) fn atan(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let wctx = working(ctx, 0);
+    if x.abs() > BigDecimal::one() {
+        let half_pi = pi(&wctx).half();
+        let a = atan(&div(&BigDecimal::one(), &x.abs(), &wctx), &wctx);
+        let result = ctx.round_decimal(half_pi - a);
+        return if x.is_negative() { -result } else { result };
+    }
+
+    let threshold = BigDecimal::new(BigInt::one(), 1);
+    let mut y = x.clone();
+    let mut doublings: u32 = 0;
+    while y.abs() > threshold {
+        let root = (BigDecimal::one() + y.square()).sqrt_with_context(&wctx).unwrap_or_default();
+        y = div(&y, &(BigDecimal::one() + root), &wctx);
+        doublings += 1;
+    }
+    ctx.round_decimal(atan_series(&y, &wctx) * BigDecimal::from(1u64 << doublings))
+}
+
+/// atan(x) = x - x^3/3 + x^5/5 - ... for a small x
+///
+fn atan_series(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let eps = epsilon(ctx);
+    let x2 = ctx.round_decimal(x.square());
+
+    let mut sum = x.clone();
+    let mut power = x.clone();
+    for n in 1..MAX_ITERATIONS as u64 {
+        power = -ctx.round_decimal(power * &x2);
+        let term = div(&power, &BigDecimal::from(2 * n + 1), ctx);
+        if term.abs() < eps {
+            break;
+        }
+        sum += term;
+    }
+    sum
+}
+
+/// asin(x) = atan(x / sqrt(1 - x^2)), for -1 <= x <= 1
+///
+fn asin(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let wctx = working(ctx, 0);
+    match x.abs().cmp(&BigDecimal::one()) {
+        std::cmp::Ordering::Greater => None,
+        std::cmp::Ordering::Equal => {
+            let half_pi = ctx.round_decimal(pi(&wctx).half());
+            Some(if x.is_negative() { -half_pi } else { half_pi })
+        }
+        std::cmp::Ordering::Less => {
+            let root = (BigDecimal::one() - x.square()).sqrt_with_context(&wctx)?;
+            Some(atan(&div(x, &root, &wctx), ctx))
+        }
+    }
+}
+
+/// acos(x) = pi/2 - asin(x), for -1 <= x <= 1
+///
+fn acos(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let wctx = working(ctx, 0);
+    Some(ctx.round_decimal(pi(&wctx).half() - asin(x, &wctx)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+
+    use bigdecimal::RoundingMode;
+
+    use super::*;
+
+    fn context(digits: u64) -> Context {
+        Context::new(NonZeroU64::new(digits).unwrap(), RoundingMode::HalfEven)
+    }
+
+    fn decimal(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_constants() {
+        let ctx = context(50);
+        assert_eq!(pi(&ctx), decimal("3.1415926535897932384626433832795028841971693993751"));
+        assert_eq!(e(&ctx), decimal("2.7182818284590452353602874713526624977572470937000"));
+    }
+
+    #[test]
+    fn test_transcendental_functions() {
+        let ctx = context(40);
+        assert_eq!(ln(&decimal("10"), &ctx).unwrap(), decimal("2.302585092994045684017991454684364207601"));
+        assert_eq!(sin(&decimal("1"), &ctx), decimal("0.8414709848078965066525023216302989996226"));
+        assert_eq!(cos(&decimal("100"), &ctx), decimal("0.8623188722876839341019385139508425355101"));
+        assert_eq!(atan(&decimal("2"), &ctx), decimal("1.107148717794090503017065460178537040070"));
+        assert_eq!(exp(&decimal("-20"), &ctx), decimal("2.061153622438557827965940380155820976376E-9"));
+        assert_eq!(ln(&decimal("-1"), &ctx), None);
+    }
+
+    #[test]
+    fn test_hyperbolic_functions() {
+        let ctx = context(30);
+        assert_eq!(function(MathFunction::Sinh, &decimal("1"), &ctx).unwrap(), decimal("1.17520119364380145688238185060"));
+        assert_eq!(function(MathFunction::Tanh, &decimal("0.5"), &ctx).unwrap(), decimal("0.462117157260009758502318483644"));
+        assert_eq!(function(MathFunction::ASinh, &decimal("-1"), &ctx).unwrap(), decimal("-0.881373587019543025232609324980"));
+        assert_eq!(function(MathFunction::ACosh, &decimal("0.5"), &ctx), None);
+        assert_eq!(function(MathFunction::Log2, &decimal("1024"), &ctx).unwrap(), decimal("10"));
+        assert_eq!(function(MathFunction::Cbrt, &decimal("-3.375"), &ctx).unwrap(), decimal("-1.5"));
+    }
+
+    #[test]
+    fn test_pow() {
+        let ctx = context(30);
+        assert_eq!(pow(&decimal("2"), &decimal("0.5"), &ctx).unwrap(), decimal("1.41421356237309504880168872421"));
+        assert_eq!(pow(&decimal("1.5"), &decimal("-2"), &ctx).unwrap(), decimal("0.444444444444444444444444444444"));
+        assert_eq!(pow(&decimal("-2"), &decimal("0.5"), &ctx), None);
+        assert_eq!(pow(&decimal("0"), &decimal("-1"), &ctx), None);
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    cmp::Ordering,
+    f64::consts::{PI, SQRT_2},
+};
+
+use crate::{
+    error::MathError,
+    special::{erfc, lgamma},
+    token::MathFunction,
+};
+
+/// The coefficients of the rational approximations of the normal quantile, by Acklam
+///
+const ACKLAM_A: [f64; 6] = [-39.696_830_286_653_76, 220.946_098_424_520_5, -275.928_510_446_968_7, 138.357_751_867_269, -30.664_798_066_147_16, 2.506_628_277_459_239];
+const ACKLAM_B: [f64; 5] = [-54.476_098_798_224_06, 161.585_836_858_040_9, -155.698_979_859_886_6, 66.801_311_887_719_72, -13.280_681_552_885_72];
+const ACKLAM_C: [f64; 6] = [-0.007_784_894_002_430_293, -0.322_396_458_041_136_5, -2.400_758_277_161_838, -2.549_732_539_343_734, 4.374_664_141_464_968, 2.938_163_982_698_783];
+const ACKLAM_D: [f64; 4] = [0.007_784_695_709_041_462, 0.322_467_129_070_039_8, 2.445_134_137_142_996, 3.754_408_661_907_416];
+
+/// The steps of the continued fractions, and the smallest value that they divide by
+///
+const MAX_STEPS: u32 = 1_000;
+const TINY: f64 = 1e-300;
+
+/// Whether a [`MathFunction`] is a probability distribution
+///
+pub(crate) fn is_distribution(fun: MathFunction) -> bool {
+    matches!(
+        fun,
+        MathFunction::NormPdf
+            | MathFunction::NormCdf
+            | MathFunction::NormInv
+            | MathFunction::BinomPdf
+            | MathFunction::BinomCdf
+            | MathFunction::PoissonPdf
+            | MathFunction::TCdf
+            | MathFunction::Chi2Cdf
+            | MathFunction::Uniform
+    )
+}
+
+/// Evaluates a probability distribution of real arguments. The parameters out of their domain,
+/// as a standard deviation that isn't positive or a probability beyond 1, are a [`MathError::Domain`]
+/// instead of not a number. Their relative error is about 1e-14, and grows to 1e-12 in the far tails
+/// and for the sample sizes of the thousands.
+///
+#[allow(clippy::float_cmp)] // the exact bounds of the probabilities
+pub(crate) fn function(fun: MathFunction, args: &[f64]) -> Result<f64, MathError> {
+    let domain = |value: f64| MathError::domain(fun, value);
+    let integer = |v: f64| v.fract() == 0.;
+    let probability = |p: f64| (0. ..=1.).contains(&p);
+    let positive = |v: f64| v > 0.;
+    let natural = |v: f64| v >= 0. && integer(v);
+    let res = match (fun, args) {
+        (MathFunction::NormPdf | MathFunction::NormCdf | MathFunction::NormInv, [_, _, sigma]) if !positive(*sigma) => return Err(domain(*sigma)),
+        (MathFunction::NormPdf, [x, mu, sigma]) => {
+            let z = (x - mu) / sigma;
+            (-z * z / 2.).exp() / (sigma * (2. * PI).sqrt())
+        }
+        (MathFunction::NormCdf, [x, mu, sigma]) => erfc(-(x - mu) / (sigma * SQRT_2)) / 2.,
+        (MathFunction::NormInv, [p, ..]) if !probability(*p) => return Err(domain(*p)),
+        (MathFunction::NormInv, [p, mu, sigma]) => mu + sigma * normal_quantile(*p),
+
+        (MathFunction::BinomPdf | MathFunction::BinomCdf, [_, n, _]) if !natural(*n) => return Err(domain(*n)),
+        (MathFunction::BinomPdf | MathFunction::BinomCdf, [_, _, p]) if !probability(*p) => return Err(domain(*p)),
+        (MathFunction::BinomPdf, [k, ..]) if !integer(*k) => return Err(domain(*k)),
+        (MathFunction::BinomPdf, [k, n, _]) if *k < 0. || k > n => 0.,
+        (MathFunction::BinomPdf, [k, n, p]) if *p == 0. || *p == 1. => {
+            // all of the trials fail, or all of them succeed
+            let certain = if *p == 0. { 0. } else { *n };
+            f64::from(u8::from(*k == certain))
+        }
+        (MathFunction::BinomPdf, [k, n, p]) => {
+            (lgamma(n + 1.) - lgamma(k + 1.) - lgamma(n - k + 1.) + k * p.ln() + (n - k) * (-p).ln_1p()).exp()
+        }
+        (MathFunction::BinomCdf, [k, ..]) if !k.is_nan() && *k < 0. => 0.,
+        (MathFunction::BinomCdf, [k, n, _]) if k >= n => 1.,
+        (MathFunction::BinomCdf, [_, _, p]) if *p == 0. || *p == 1. => 1. - p,
+        (MathFunction::BinomCdf, [k, n, p]) => {
+            let k = k.floor();
+            beta_regularized(n - k, k + 1., 1. - p)
+        }
+
+        (MathFunction::PoissonPdf, [_, lambda]) if !positive(*lambda) => return Err(domain(*lambda)),
+        (MathFunction::PoissonPdf, [k, _]) if !integer(*k) => return Err(domain(*k)),
+        (MathFunction::PoissonPdf, [k, _]) if *k < 0. => 0.,
+        (MathFunction::PoissonPdf, [k, lambda]) => (k * lambda.ln() - lambda - lgamma(k + 1.)).exp(),
+
+        (MathFunction::TCdf, [_, nu]) if !positive(*nu) => return Err(domain(*nu)),
+        (MathFunction::TCdf, [t, _]) if t.is_nan() => return Err(domain(*t)),
+        (MathFunction::TCdf, [t, nu]) => {
+            // the tail beyond |t| is I(nu / (nu + t^2); nu/2, 1/2) / 2
+            let tail = beta_regularized(nu / 2., 0.5, nu / (nu + t * t)) / 2.;
+            if *t > 0. { 1. - tail } else { tail }
+        }
+        (MathFunction::Chi2Cdf, [_, k]) if !positive(*k) => return Err(domain(*k)),
+        (MathFunction::Chi2Cdf, [x, _]) if x.is_nan() => return Err(domain(*x)),
+        (MathFunction::Chi2Cdf, [x, k]) => gamma_regularized(k / 2., x / 2.),
+
+        (MathFunction::Uniform, [_, a, b]) if a.partial_cmp(b) != Some(Ordering::Less) => return Err(domain(*b)),
+        (MathFunction::Uniform, [x, a, b]) => ((x - a) / (b - a)).clamp(0., 1.),
+        _ => return Err(domain(args.first().copied().unwrap_or(f64::NAN))),
+    };
+    if res.is_nan() {
+        return Err(domain(args.first().copied().unwrap_or(f64::NAN)));
+    }
+    Ok(res)
+}
+
+/// The quantile of the standard normal distribution, with the approximation of Acklam
+/// refined by a step of Halley's method
+///
+#[allow(clippy::float_cmp)] // the infinite quantiles of 0 and 1
+fn normal_quantile(p: f64) -> f64 {
+    if p == 0. || p == 1. {
+        return if p == 0. { f64::NEG_INFINITY } else { f64::INFINITY };
+    }
+    let horner = |coefficients: &[f64], x: f64| coefficients.iter().fold(0., |acc, c| acc * x + c);
+    let low = 0.024_25;
+    let x = if p < low || p > 1. - low {
+        let q = (-2. * p.min(1. - p).ln()).sqrt();
+        let x = horner(&ACKLAM_C, q) / (horner(&ACKLAM_D, q) * q + 1.);
+        if p < low { x } else { -x }
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        horner(&ACKLAM_A, r) * q / (horner(&ACKLAM_B, r) * r + 1.)
+    };
+    let e = erfc(-x / SQRT_2) / 2. - p;
+    let u = e * (2. * PI).sqrt() * (x * x / 2.).exp();
+    x - u / (1. + x * u / 2.)
+}
+
+/// The regularized incomplete beta function I(x; a, b), with its continued fraction
+/// on the side of x where it converges quickly
+///
+fn beta_regularized(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0. || x >= 1. {
+        return if x <= 0. { 0. } else { 1. };
+    }
+    let front = (lgamma(a + b) - lgamma(a) - lgamma(b) + a * x.ln() + b * (-x).ln_1p()).exp();
+    if x < (a + 1.) / (a + b + 2.) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1. - front * beta_fraction(b, a, 1. - x) / b
+    }
+}
+
+/// The continued fraction of the incomplete beta function, by the method of Lentz
+///
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    let nonzero = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let mut upper = 1.;
+    let mut lower = 1. / nonzero(1. - (a + b) * x / (a + 1.));
+    let mut res = lower;
+    for step in 1..=MAX_STEPS {
+        let step = f64::from(step);
+        // the even step, and the odd one
+        let even = step * (b - step) * x / ((a + 2. * step - 1.) * (a + 2. * step));
+        lower = 1. / nonzero(1. + even * lower);
+        upper = nonzero(1. + even / upper);
+        res *= lower * upper;
+        let odd = -(a + step) * (a + b + step) * x / ((a + 2. * step) * (a + 2. * step + 1.));
+        lower = 1. / nonzero(1. + odd * lower);
+        upper = nonzero(1. + odd / upper);
+        let delta = lower * upper;
+        res *= delta;
+        if (delta - 1.).abs() < 1e-16 {
+            break;
+        }
+    }
+    res
+}
+
+/// The regularized lower incomplete gamma function P(a, x), with its series below a + 1
+/// and the continued fraction of its complement above
+///
+fn gamma_regularized(a: f64, x: f64) -> f64 {
+    if x <= 0. {
+        return 0.;
+    }
+    if x.is_infinite() {
+        return 1.;
+    }
+    let front = (a * x.ln() - x - lgamma(a)).exp();
+    if x < a + 1. {
+        let (mut term, mut sum, mut shape) = (1. / a, 1. / a, a);
+        while term.abs() > sum.abs() * 1e-17 {
+            shape += 1.;
+            term *= x / shape;
+            sum += term;
+        }
+        return front * sum;
+    }
+    let nonzero = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let mut denominator = x + 1. - a;
+    let (mut upper, mut lower) = (1. / TINY, 1. / denominator);
+    let mut res = lower;
+    for step in 1..=MAX_STEPS {
+        let step = f64::from(step);
+        let numerator = -step * (step - a);
+        denominator += 2.;
+        lower = 1. / nonzero(numerator * lower + denominator);
+        upper = nonzero(denominator + numerator / upper);
+        let delta = lower * upper;
+        res *= delta;
+        if (delta - 1.).abs() < 1e-16 {
+            break;
+        }
+    }
+    1. - front * res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(fun: MathFunction, args: &[f64]) -> f64 {
+        function(fun, args).unwrap()
+    }
+
+    fn close(value: f64, expected: f64) {
+        assert!((value - expected).abs() <= 1e-13 * expected.abs().max(1e-300), "{value} != {expected}");
+    }
+
+    #[test]
+    fn test_normal() {
+        close(eval(MathFunction::NormPdf, &[0., 0., 1.]), 0.398_942_280_401_432_7);
+        close(eval(MathFunction::NormCdf, &[1.96, 0., 1.]), 0.975_002_104_851_779_6);
+        close(eval(MathFunction::NormCdf, &[-10., 0., 1.]), 7.619_853_024_160_526e-24);
+        close(eval(MathFunction::NormInv, &[0.975, 0., 1.]), 1.959_963_984_540_054);
+        close(eval(MathFunction::NormInv, &[1e-10, 100., 15.]), 100. - 15. * 6.361_340_902_404_056);
+        assert!(function(MathFunction::NormPdf, &[0., 0., 0.]).is_err());
+        assert!(function(MathFunction::NormInv, &[1.5, 0., 1.]).is_err());
+    }
+
+    #[test]
+    fn test_discrete() {
+        close(eval(MathFunction::BinomPdf, &[3., 10., 0.5]), 15. / 128.);
+        close(eval(MathFunction::BinomCdf, &[3., 10., 0.5]), 176. / 1024.);
+        assert_eq!(eval(MathFunction::BinomPdf, &[11., 10., 0.5]), 0.);
+        assert_eq!(eval(MathFunction::BinomPdf, &[0., 10., 0.]), 1.);
+        close(eval(MathFunction::PoissonPdf, &[2., 3.]), 4.5 * (-3f64).exp());
+        assert!(function(MathFunction::BinomPdf, &[1.5, 10., 0.5]).is_err());
+        assert!(function(MathFunction::BinomCdf, &[1., 10., -0.5]).is_err());
+        assert!(function(MathFunction::PoissonPdf, &[1., 0.]).is_err());
+    }
+
+    #[test]
+    fn test_continuous() {
+        close(eval(MathFunction::TCdf, &[2., 5.]), 0.949_030_260_585_070_8);
+        close(eval(MathFunction::TCdf, &[-2., 5.]), 1. - 0.949_030_260_585_070_8);
+        assert_eq!(eval(MathFunction::TCdf, &[0., 3.]), 0.5);
+        close(eval(MathFunction::Chi2Cdf, &[3.84, 1.]), 0.949_956_478_751_294_9);
+        close(eval(MathFunction::Chi2Cdf, &[30., 10.]), 0.999_143_358_789_224_7);
+        assert_eq!(eval(MathFunction::Uniform, &[0.25, 0., 2.]), 0.125);
+        assert!(function(MathFunction::Uniform, &[0.25, 2., 2.]).is_err());
+    }
+}
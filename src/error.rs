@@ -0,0 +1,91 @@
+use std::fmt::Display;
+
+use thiserror::Error;
+
+/// The errors of an evaluation that callers may want to tell apart, as they carry the details of
+/// what went wrong. They're returned inside an [`anyhow::Error`], and can be recovered with
+/// [`anyhow::Error::downcast_ref`].
+///
+/// # Examples
+///
+/// ```
+/// # use yarer::{error::MathError, session::Session};
+/// let session = Session::init();
+/// let err = session.process("(-3)!").resolve().unwrap_err();
+///
+/// assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+/// ```
+///
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum MathError {
+    /// The argument is outside the domain of a function or an operator, as in '(-3)!'
+    #[error("Runtime error: '{operation}' is not defined for {value}.")]
+    Domain {
+        /// the function or the operator
+        operation: String,
+        /// the offending argument
+        value: String,
+    },
+    /// An operator or a function gave NaN or an infinity, under a
+    /// [`FloatPolicy`](crate::session::FloatPolicy) that doesn't allow it
+    #[error("Runtime error: '{operation}' gave {value}.")]
+    NonFinite {
+        /// the function or the operator
+        operation: String,
+        /// the non-finite result
+        value: String,
+    },
+    /// The evaluation needs more resources than the [`Limits`](crate::session::Limits) of the session allow
+    #[error("Runtime error: The {limit} exceeds the limit of {max}.")]
+    LimitExceeded {
+        /// what has been limited
+        limit: &'static str,
+        /// the limit
+        max: String,
+    },
+}
+
+impl MathError {
+    pub(crate) fn domain(operation: impl Display, value: impl Display) -> MathError {
+        MathError::Domain { operation: operation.to_string(), value: value.to_string() }
+    }
+}
+
+/// Why a [`Number`](crate::token::Number) can't be converted into a primitive type,
+/// as returned by its [`TryFrom`] implementations
+///
+/// # Examples
+///
+/// ```
+/// # use yarer::{error::ConversionError, session::Session};
+/// let session = Session::init();
+/// let result = session.process("2^100").resolve().unwrap();
+///
+/// assert!(matches!(i32::try_from(&result), Err(ConversionError::OutOfRange { .. })));
+/// ```
+///
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ConversionError {
+    /// The number is too large or too small for the type, as '2^100' for an i32
+    #[error("Conversion error: {value} is out of the range of {target}.")]
+    OutOfRange {
+        /// the number
+        value: String,
+        /// the name of the type
+        target: &'static str,
+    },
+    /// The number has a fractional part, or is not a number at all, as '0/0'
+    #[error("Conversion error: {value} is not an integer.")]
+    NotAnInteger {
+        /// the number
+        value: String,
+    },
+    /// The number is not a plain real number, as a complex number or a matrix
+    #[error("Conversion error: {value} can't be converted into {target}.")]
+    Incompatible {
+        /// the number
+        value: String,
+        /// the name of the type
+        target: &'static str,
+    },
+}
@@ -0,0 +1,362 @@
+use std::cmp::Ordering;
+
+use anyhow::anyhow;
+use bigdecimal::{BigDecimal, Context};
+use num::{BigInt, One, Zero};
+
+use crate::{
+    decimal,
+    error::MathError,
+    integer,
+    matrix::Scalar,
+    token::{MathFunction, Number, Operator},
+};
+
+static CONVERGENCE_ERR: &str = "Runtime error: The iterations of the function don't converge:";
+
+/// The steps of the secant method before giving up, and the rate it starts from
+///
+const MAX_STEPS: usize = 100;
+const DEFAULT_GUESS: f64 = 0.1;
+
+/// Evaluates a [`MathFunction`] of one argument, with the rules of the resolver
+///
+pub(crate) type Unary<'a> = &'a dyn Fn(MathFunction, Number) -> anyhow::Result<Number>;
+
+/// Whether a [`MathFunction`] is a financial function
+///
+pub(crate) fn is_financial(fun: MathFunction) -> bool {
+    matches!(
+        fun,
+        MathFunction::Pmt
+            | MathFunction::IPmt
+            | MathFunction::PPmt
+            | MathFunction::Fv
+            | MathFunction::Pv
+            | MathFunction::NPer
+            | MathFunction::Rate
+            | MathFunction::Npv
+            | MathFunction::Irr
+    )
+}
+
+/// Evaluates a financial function with the conventions of the spreadsheets: the money paid out
+/// is negative, the optional future value `fv` is 0 and the optional `type` is 0 for the payments
+/// at the end of each period, or 1 at the beginning. They're computed with the arithmetic of the
+/// resolver, so that they're exact on the fractions in rational mode and carried out with the
+/// decimal precision in decimal mode, while `rate` and `irr` are solved by the secant method
+/// in floats, or up to the decimal precision.
+///
+pub(crate) fn function(fun: MathFunction, args: &[Number], scalar: Scalar, unary: Unary, ctx: Option<&Context>) -> anyhow::Result<Number> {
+    let (least, most) = match fun {
+        MathFunction::Pmt | MathFunction::Fv | MathFunction::Pv | MathFunction::NPer => (3, 5),
+        MathFunction::IPmt | MathFunction::PPmt => (4, 6),
+        MathFunction::Rate => (3, 6),
+        MathFunction::Npv if args.len() < 2 => return Err(anyhow!("Runtime error: Function '{fun}' needs a rate and the values.")),
+        MathFunction::Npv | MathFunction::Irr => return cash_flows(fun, args, scalar, ctx),
+        _ => return Err(anyhow!("This should never happen!")),
+    };
+    if args.len() < least || args.len() > most {
+        return Err(anyhow!("Runtime error: Function '{fun}' takes from {least} to {most} arguments."));
+    }
+    if let Some(value) = args.iter().find(|a| !is_real(a)) {
+        return Err(MathError::domain(fun, value).into());
+    }
+    let ops = Ops { scalar, ctx };
+    let zero = Number::NaturalNumber(BigInt::zero());
+    let arg = |i: usize| args.get(i).cloned().unwrap_or_else(|| zero.clone());
+    let due = |i: usize| {
+        let due = arg(i);
+        match integer_value(&due) {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(MathError::domain(fun, &due)),
+        }
+    };
+    match fun {
+        MathFunction::Fv => ops.future_value(&arg(0), &arg(1), &arg(2), &arg(3), due(4)?),
+        MathFunction::Pv => {
+            let (r, n, pmt, fv) = (arg(0), arg(1), arg(2), arg(3));
+            let annuity = ops.mul(&pmt, &ops.annuity(&r, &n, due(4)?)?)?;
+            ops.neg(&ops.div(&ops.add(&fv, &annuity)?, &ops.growth(&r, &n)?)?)
+        }
+        MathFunction::Pmt => ops.payment(&arg(0), &arg(1), &arg(2), &arg(3), due(4)?),
+        MathFunction::IPmt | MathFunction::PPmt => {
+            let (r, per, n, pv, fv, due) = (arg(0), arg(1), arg(2), arg(3), arg(4), due(5)?);
+            if !(per >= Number::NaturalNumber(BigInt::one()) && per <= n) {
+                return Err(MathError::domain(fun, per).into());
+            }
+            let pmt = ops.payment(&r, &n, &pv, &fv, due)?;
+            let interest = if due && integer_value(&per) == Some(1) {
+                zero
+            } else {
+                // the interest on the balance left after the previous period
+                let previous = ops.sub(&per, &Number::NaturalNumber(BigInt::one()))?;
+                let interest = ops.mul(&ops.future_value(&r, &previous, &pmt, &pv, due)?, &r)?;
+                if due { ops.div(&interest, &ops.add(&Number::NaturalNumber(BigInt::one()), &r)?)? } else { interest }
+            };
+            if fun == MathFunction::IPmt { Ok(interest) } else { ops.sub(&pmt, &interest) }
+        }
+        MathFunction::NPer => {
+            let (r, pmt, pv, fv) = (arg(0), arg(1), arg(2), arg(3));
+            if is_zero(&r) {
+                return ops.neg(&ops.div(&ops.add(&pv, &fv)?, &pmt)?);
+            }
+            let one = Number::NaturalNumber(BigInt::one());
+            let pmt = if due(4)? { ops.mul(&pmt, &ops.add(&one, &r)?)? } else { pmt };
+            let ratio = ops.div(&ops.sub(&pmt, &ops.mul(&fv, &r)?)?, &ops.add(&pmt, &ops.mul(&pv, &r)?)?)?;
+            if ratio.partial_cmp(&zero) != Some(Ordering::Greater) {
+                return Err(MathError::domain(fun, ratio).into());
+            }
+            ops.div(&unary(MathFunction::Ln, ratio)?, &unary(MathFunction::Ln, ops.add(&one, &r)?)?)
+        }
+        _ => {
+            let (n, pmt, pv, fv, due) = (arg(0), arg(1), arg(2), arg(3), due(4)?);
+            let guess = args.get(5).cloned().unwrap_or(Number::DecimalNumber(DEFAULT_GUESS));
+            // the rate at which the payments and the present value add up to the future value
+            let residual = |r: &Number| ops.sub(&fv, &ops.future_value(r, &n, &pmt, &pv, due)?);
+            ops.solve(fun, residual, &guess)
+        }
+    }
+}
+
+/// The net present value of the cash flows at the end of each period, `npv(rate, ...)`,
+/// and their internal rate of return, `irr(...)`, the rate at which it's 0. A matrix argument
+/// counts as all of its elements, so that 'irr([-100, 60, 60])' is about 0.131.
+///
+fn cash_flows(fun: MathFunction, args: &[Number], scalar: Scalar, ctx: Option<&Context>) -> anyhow::Result<Number> {
+    let mut values: Vec<Number> = args
+        .iter()
+        .flat_map(|n| match n {
+            Number::Matrix(m) => m.elements().to_vec(),
+            n => vec![n.clone()],
+        })
+        .collect();
+    if let Some(value) = values.iter().find(|a| !is_real(a)) {
+        return Err(MathError::domain(fun, value).into());
+    }
+    let ops = Ops { scalar, ctx };
+    if fun == MathFunction::Npv {
+        let rate = values.remove(0);
+        let one = Number::NaturalNumber(BigInt::one());
+        let discount = ops.add(&one, &rate)?;
+        // the first value is discounted by one period
+        let present = ops.npv(&rate, &values)?;
+        return ops.div(&present, &discount);
+    }
+    if values.len() < 2 {
+        return Err(anyhow!("Runtime error: Function '{fun}' needs at least two values."));
+    }
+    let residual = |r: &Number| ops.npv(r, &values);
+    ops.solve(fun, residual, &Number::DecimalNumber(DEFAULT_GUESS))
+}
+
+fn is_real(n: &Number) -> bool {
+    matches!(n, Number::NaturalNumber(_) | Number::DecimalNumber(_) | Number::Rational(_) | Number::BigDecimal(_))
+}
+
+fn is_zero(n: &Number) -> bool {
+    n.partial_cmp(&Number::NaturalNumber(BigInt::zero())) == Some(Ordering::Equal)
+}
+
+fn integer_value(n: &Number) -> Option<i64> {
+    integer::to_integer(n).and_then(|i| i64::try_from(i).ok())
+}
+
+/// The arithmetic of the resolver, on the references
+///
+struct Ops<'a> {
+    scalar: Scalar<'a>,
+    ctx: Option<&'a Context>,
+}
+
+impl Ops<'_> {
+    fn add(&self, a: &Number, b: &Number) -> anyhow::Result<Number> {
+        (self.scalar)(Operator::Add, a.clone(), b.clone())
+    }
+
+    fn sub(&self, a: &Number, b: &Number) -> anyhow::Result<Number> {
+        (self.scalar)(Operator::Sub, a.clone(), b.clone())
+    }
+
+    fn mul(&self, a: &Number, b: &Number) -> anyhow::Result<Number> {
+        (self.scalar)(Operator::Mul, a.clone(), b.clone())
+    }
+
+    fn div(&self, a: &Number, b: &Number) -> anyhow::Result<Number> {
+        (self.scalar)(Operator::Div, a.clone(), b.clone())
+    }
+
+    fn neg(&self, a: &Number) -> anyhow::Result<Number> {
+        self.mul(a, &Number::NaturalNumber(BigInt::from(-1)))
+    }
+
+    fn abs(&self, a: &Number) -> anyhow::Result<Number> {
+        if *a < Number::NaturalNumber(BigInt::zero()) { self.neg(a) } else { Ok(a.clone()) }
+    }
+
+    /// How much 1 grows in n periods: (1 + r)^n
+    ///
+    fn growth(&self, r: &Number, n: &Number) -> anyhow::Result<Number> {
+        (self.scalar)(Operator::Pow, self.add(&Number::NaturalNumber(BigInt::one()), r)?, n.clone())
+    }
+
+    /// How much a payment of 1 per period grows in n periods: (1 + r*type) * ((1 + r)^n - 1) / r,
+    /// or just n when the rate is 0
+    ///
+    fn annuity(&self, r: &Number, n: &Number, due: bool) -> anyhow::Result<Number> {
+        if is_zero(r) {
+            return Ok(n.clone());
+        }
+        let one = Number::NaturalNumber(BigInt::one());
+        let res = self.div(&self.sub(&self.growth(r, n)?, &one)?, r)?;
+        if due { self.mul(&res, &self.add(&one, r)?) } else { Ok(res) }
+    }
+
+    /// -(pv * (1 + r)^n + pmt * annuity)
+    ///
+    fn future_value(&self, r: &Number, n: &Number, pmt: &Number, pv: &Number, due: bool) -> anyhow::Result<Number> {
+        let grown = self.mul(pv, &self.growth(r, n)?)?;
+        self.neg(&self.add(&grown, &self.mul(pmt, &self.annuity(r, n, due)?)?)?)
+    }
+
+    /// The payment that brings pv to fv in n periods: -(pv * (1 + r)^n + fv) / annuity
+    ///
+    fn payment(&self, r: &Number, n: &Number, pv: &Number, fv: &Number, due: bool) -> anyhow::Result<Number> {
+        let grown = self.add(&self.mul(pv, &self.growth(r, n)?)?, fv)?;
+        self.neg(&self.div(&grown, &self.annuity(r, n, due)?)?)
+    }
+
+    /// The sum of the values discounted by (1 + r)^i, from i = 0, by Horner's rule
+    ///
+    fn npv(&self, r: &Number, values: &[Number]) -> anyhow::Result<Number> {
+        let discount = self.add(&Number::NaturalNumber(BigInt::one()), r)?;
+        values
+            .iter()
+            .rev()
+            .try_fold(Number::NaturalNumber(BigInt::zero()), |acc, v| self.add(v, &self.div(&acc, &discount)?))
+    }
+
+    /// A root of the residual by the secant method, from the guess and from a point close to it.
+    /// The rates are floats, or decimals with the precision of the context, as the exact fractions
+    /// wouldn't converge: it stops as soon as a step is below the precision. The tolerance is absolute,
+    /// so that the rates that run away towards infinity don't converge.
+    ///
+    fn solve(&self, fun: MathFunction, residual: impl Fn(&Number) -> anyhow::Result<Number>, guess: &Number) -> anyhow::Result<Number> {
+        let (in_mode, tolerance) = match self.ctx {
+            Some(ctx) => (
+                decimal::from_number(guess, ctx).map(Number::BigDecimal),
+                Number::BigDecimal(decimal::epsilon(ctx) * BigDecimal::from(100)),
+            ),
+            None => (Some(Number::DecimalNumber(guess.to_f64_lossy())), Number::DecimalNumber(1e-13)),
+        };
+        let mut previous = in_mode.ok_or_else(|| MathError::domain(fun, guess))?;
+        let mut rate = self.add(&previous, &Number::DecimalNumber(1e-4))?;
+        let (mut previous_residual, mut rate_residual) = (residual(&previous)?, residual(&rate)?);
+        let not_converging = |rate: &Number| anyhow!("{CONVERGENCE_ERR} '{fun}' reached {rate}.");
+
+        for _ in 0..MAX_STEPS {
+            let slope = self.sub(&rate_residual, &previous_residual)?;
+            if is_zero(&rate_residual) {
+                return Ok(rate);
+            }
+            if is_zero(&slope) {
+                return Err(not_converging(&rate));
+            }
+            let step = self.div(&self.mul(&rate_residual, &self.sub(&rate, &previous)?)?, &slope)?;
+            let mut next = self.sub(&rate, &step)?;
+            let minus_one = Number::NaturalNumber(BigInt::from(-1));
+            if next <= minus_one {
+                // the money can't shrink by more than all of it: halves the way to -1 instead
+                next = self.div(&self.add(&rate, &minus_one)?, &Number::NaturalNumber(BigInt::from(2)))?;
+            }
+            if !next.to_f64_lossy().is_finite() {
+                return Err(not_converging(&rate));
+            }
+            if self.abs(&step)? <= tolerance {
+                return Ok(next);
+            }
+            previous_residual = std::mem::replace(&mut rate_residual, residual(&next)?);
+            previous = std::mem::replace(&mut rate, next);
+        }
+        Err(not_converging(&rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::RoundingMode;
+    use num::ToPrimitive;
+
+    use super::*;
+
+    fn float(op: Operator, a: Number, b: Number) -> anyhow::Result<Number> {
+        let ctx = Context::new(std::num::NonZeroU64::new(30).unwrap(), RoundingMode::HalfEven);
+        if matches!(a, Number::BigDecimal(_)) || matches!(b, Number::BigDecimal(_)) {
+            return Ok(decimal::arithmetic(op, &a, &b, &ctx).unwrap());
+        }
+        let (a, b) = (a.to_f64_lossy(), b.to_f64_lossy());
+        Ok(Number::DecimalNumber(match op {
+            Operator::Add => a + b,
+            Operator::Sub => a - b,
+            Operator::Mul => a * b,
+            Operator::Div => a / b,
+            _ => a.powf(b),
+        }))
+    }
+
+    fn ln(_: MathFunction, x: Number) -> anyhow::Result<Number> {
+        Ok(Number::DecimalNumber(x.to_f64_lossy().ln()))
+    }
+
+    fn eval(fun: MathFunction, args: &[f64]) -> f64 {
+        let args: Vec<Number> = args.iter().map(|&x| Number::DecimalNumber(x)).collect();
+        function(fun, &args, &float, &ln, None).unwrap().to_f64_lossy()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-9 * expected.abs().max(1.), "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_annuities() {
+        // the values of the spreadsheets
+        assert_close(eval(MathFunction::Pmt, &[0.08 / 12., 10., 10_000.]), -1_037.032_089_359_152);
+        assert_close(eval(MathFunction::Pmt, &[0.08 / 12., 10., 10_000., 0., 1.]), -1_030.164_327_177_966);
+        assert_close(eval(MathFunction::Pmt, &[0., 10., 1_000.]), -100.);
+        assert_close(eval(MathFunction::Fv, &[0.06 / 12., 10., -200., -500., 1.]), 2_581.403_374_060_18);
+        assert_close(eval(MathFunction::Pv, &[0.08 / 12., 240., 500.]), -59_777.145_851_188);
+        assert_close(eval(MathFunction::NPer, &[0.01, -100., -1_000., 10_000., 1.]), 59.673_865_674_294_6);
+        assert_close(eval(MathFunction::IPmt, &[0.1 / 12., 1., 36., 8_000.]), -66.666_666_666_666_7);
+        assert_close(eval(MathFunction::IPmt, &[0.1, 3., 3., 8_000.]), -292.447_129_909_366);
+        assert_close(eval(MathFunction::PPmt, &[0.1 / 12., 1., 24., 2_000.]), -75.623_186_008_366_4);
+        assert_close(eval(MathFunction::IPmt, &[0.1, 1., 3., 8_000., 0., 1.]), 0.);
+        assert_close(eval(MathFunction::Rate, &[48., -200., 8_000.]), 0.007_701_472_488_202);
+        assert_close(eval(MathFunction::Rate, &[4., -200., 1_000., 0., 0., 0.5]), -0.083_645_417_466_151);
+
+        let error = |fun, args: &[f64]| {
+            let args: Vec<Number> = args.iter().map(|&x| Number::DecimalNumber(x)).collect();
+            function(fun, &args, &float, &ln, None).is_err()
+        };
+        assert!(error(MathFunction::Pmt, &[0.1, 10.]));
+        assert!(error(MathFunction::Pmt, &[0.1, 10., 100., 0., 2.]));
+        assert!(error(MathFunction::IPmt, &[0.1, 4., 3., 8_000.]));
+    }
+
+    #[test]
+    fn test_cash_flows() {
+        assert_close(eval(MathFunction::Npv, &[0.1, -10_000., 3_000., 4_200., 6_800.]), 1_188.443_412_335_42);
+        assert_close(eval(MathFunction::Irr, &[-70_000., 12_000., 15_000., 18_000., 21_000., 26_000.]), 0.086_630_948_036_532);
+        assert_close(eval(MathFunction::Irr, &[-100., 60., 60.]), 0.130_662_386_291_807);
+        assert!(function(MathFunction::Irr, &[Number::DecimalNumber(1.)], &float, &ln, None).is_err());
+
+        let ctx = Context::new(std::num::NonZeroU64::new(30).unwrap(), RoundingMode::HalfEven);
+        let flows: Vec<Number> = [-100, 60, 60].iter().map(|&v| Number::NaturalNumber(BigInt::from(v))).collect();
+        let Number::BigDecimal(irr) = function(MathFunction::Irr, &flows, &float, &ln, Some(&ctx)).unwrap() else {
+            panic!("Not a decimal");
+        };
+        // (sqrt(69) - 7) / 10
+        assert_eq!(irr.round(25).to_f64().unwrap(), 0.130_662_386_291_807_5);
+        assert_eq!(irr.round(25).to_string(), "0.1306623862918074852584263");
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::anyhow;
+use bigdecimal::RoundingMode;
+use num::{bigint::Sign, BigInt, BigRational, FromPrimitive, Integer, One, Signed, ToPrimitive, Zero};
+
+use crate::{error::MathError, number_theory, token::{MathFunction, Number}};
+
+static TOO_LARGE_ERR: &str = "Runtime error: The result is too large.";
+
+/// The value of a number that is an integer: an exact one, or a float without a fractional part
+///
+pub(crate) fn to_integer(n: &Number) -> Option<BigInt> {
+    match n {
+        Number::NaturalNumber(v) => Some(v.clone()),
+        Number::Rational(v) if v.is_integer() => Some(v.to_integer()),
+        Number::DecimalNumber(v) if v.fract() == 0. => BigInt::from_f64(*v),
+        Number::BigDecimal(v) if v.is_integer() => Some(v.with_scale(0).into_bigint_and_exponent().0),
+        _ => None,
+    }
+}
+
+/// Evaluates 'floor', 'ceil', 'round', 'trunc', 'fract' and 'sign' over a real number.
+/// The integers, the fractions and the arbitrary precision decimals give back exact results,
+/// and so does a finite float, but for its fractional part.
+/// Returns [`None`] for the other functions and the other numbers.
+///
+pub(crate) fn rounding(fun: MathFunction, n: &Number) -> Option<Number> {
+    let res = match (fun, n) {
+        (MathFunction::Floor | MathFunction::Ceil | MathFunction::Round | MathFunction::Trunc, Number::NaturalNumber(_)) => n.clone(),
+        (MathFunction::Fract, Number::NaturalNumber(_)) => Number::NaturalNumber(BigInt::zero()),
+        (MathFunction::Sign, Number::NaturalNumber(v)) => Number::NaturalNumber(v.signum()),
+        (_, Number::Rational(r)) => match fun {
+            MathFunction::Floor => Number::from_rational(r.floor()),
+            MathFunction::Ceil => Number::from_rational(r.ceil()),
+            MathFunction::Round => Number::from_rational(r.round()),
+            MathFunction::Trunc => Number::from_rational(r.trunc()),
+            MathFunction::Fract => Number::from_rational(r.fract()),
+            MathFunction::Sign => Number::from_rational(r.signum()),
+            _ => return None,
+        },
+        (_, Number::BigDecimal(d)) => {
+            let integer = |mode| Number::NaturalNumber(d.with_scale_round(0, mode).into_bigint_and_exponent().0);
+            match fun {
+                MathFunction::Floor => integer(RoundingMode::Floor),
+                MathFunction::Ceil => integer(RoundingMode::Ceiling),
+                MathFunction::Round => integer(RoundingMode::HalfUp),
+                MathFunction::Trunc => integer(RoundingMode::Down),
+                MathFunction::Fract => Number::BigDecimal(d - d.with_scale_round(0, RoundingMode::Down)),
+                MathFunction::Sign => Number::NaturalNumber(BigInt::from(sign(d.sign()))),
+                _ => return None,
+            }
+        }
+        (_, Number::DecimalNumber(v)) if v.is_finite() => {
+            let integer = |x: f64| BigInt::from_f64(x).map(Number::NaturalNumber);
+            match fun {
+                MathFunction::Floor => integer(v.floor())?,
+                MathFunction::Ceil => integer(v.ceil())?,
+                MathFunction::Round => integer(v.round())?,
+                MathFunction::Trunc => integer(v.trunc())?,
+                MathFunction::Fract => Number::DecimalNumber(v.fract()),
+                MathFunction::Sign if *v == 0. => Number::NaturalNumber(BigInt::zero()),
+                MathFunction::Sign => integer(v.signum())?,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some(res)
+}
+
+fn sign(s: Sign) -> i8 {
+    match s {
+        Sign::Minus => -1,
+        Sign::NoSign => 0,
+        Sign::Plus => 1,
+    }
+}
+
+/// The exact k-th root of an integer or of a fraction, when it is a perfect k-th power:
+/// the root of 27/8 of index 3 is 3/2. An odd root of a negative number is negative.
+///
+pub(crate) fn root(n: &Number, k: u32) -> Option<Number> {
+    let exact = |v: &BigInt| {
+        if k == 0 || (v.is_negative() && k % 2 == 0) {
+            return None;
+        }
+        let r = v.nth_root(k);
+        (num::pow(r.clone(), k as usize) == *v).then_some(r)
+    };
+    match n {
+        Number::NaturalNumber(v) => exact(v).map(Number::NaturalNumber),
+        Number::Rational(r) => {
+            Some(Number::from_rational(BigRational::new(exact(r.numer())?, exact(r.denom())?)))
+        }
+        _ => None,
+    }
+}
+
+/// Evaluates a [`MathFunction`] of integer arguments exactly: `gcd`, `lcm`, `mod_pow`, `modinv`, `ncr` and `npr`
+///
+pub(crate) fn function(fun: MathFunction, args: &[BigInt]) -> anyhow::Result<Number> {
+    let domain = |value: &BigInt| MathError::domain(fun, value);
+    let res = match (fun, args) {
+        (MathFunction::Gcd, [a, b]) => a.gcd(b),
+        (MathFunction::Lcm, [a, b]) => a.lcm(b),
+        (MathFunction::ModPow, [_, _, m]) | (MathFunction::ModInv, [_, m]) if m.is_zero() => return Err(domain(m).into()),
+        (MathFunction::ModPow, [_, e, _]) if e.is_negative() => return Err(domain(e).into()),
+        (MathFunction::ModPow, [b, e, m]) => b.modpow(e, m),
+        (MathFunction::ModInv, [a, m]) => number_theory::mod_inverse(a, m).ok_or_else(|| domain(a))?,
+        (MathFunction::NCr | MathFunction::NPr, [n, k]) if n.is_negative() || k.is_negative() => {
+            return Err(domain(if n.is_negative() { n } else { k }).into())
+        }
+        (MathFunction::NCr | MathFunction::NPr, [n, k]) if k > n => BigInt::zero(),
+        (MathFunction::NPr, [n, k]) => product(&(n - k + 1), count(k)?, 1),
+        (MathFunction::NCr, [n, k]) => {
+            let k = k.min(&(n - k)).clone();
+            product(&(n - &k + 1), count(&k)?, 1) / product(&BigInt::one(), count(&k)?, 1)
+        }
+        _ => return Err(anyhow!("This should never happen!")),
+    };
+    Ok(Number::NaturalNumber(res))
+}
+
+fn count(k: &BigInt) -> anyhow::Result<u64> {
+    k.to_u64().ok_or_else(|| anyhow!(TOO_LARGE_ERR))
+}
+
+/// The product of 'lo', 'lo + step', 'lo + 2*step', ... of `count` factors, splitting them in two
+/// halves of similar size, so that the big multiplications are between numbers of similar length
+///
+pub(crate) fn product(lo: &BigInt, count: u64, step: u64) -> BigInt {
+    if count <= 16 {
+        return (0..count).fold(BigInt::one(), |acc, i| acc * (lo + i * step));
+    }
+    let half = count / 2;
+    product(lo, half, step) * product(&(lo + half * step), count - half, step)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+
+    fn natural(n: i64) -> Number {
+        Number::NaturalNumber(BigInt::from(n))
+    }
+
+    fn exact(fun: MathFunction, args: &[i64]) -> Number {
+        function(fun, &args.iter().map(|&a| BigInt::from(a)).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_rounding() {
+        let r = Number::Rational(BigRational::new(BigInt::from(-7), BigInt::from(2)));
+        assert_eq!(rounding(MathFunction::Floor, &r), Some(natural(-4)));
+        assert_eq!(rounding(MathFunction::Round, &r), Some(natural(-4)));
+        assert_eq!(rounding(MathFunction::Trunc, &r), Some(natural(-3)));
+        assert_eq!(rounding(MathFunction::Fract, &r), Some(Number::Rational(BigRational::new(BigInt::from(-1), BigInt::from(2)))));
+        assert_eq!(rounding(MathFunction::Sign, &r), Some(natural(-1)));
+
+        let d = Number::BigDecimal(BigDecimal::from_str("2.71").unwrap());
+        assert_eq!(rounding(MathFunction::Ceil, &d), Some(natural(3)));
+        assert_eq!(rounding(MathFunction::Fract, &d), Some(Number::BigDecimal(BigDecimal::from_str("0.71").unwrap())));
+
+        assert_eq!(rounding(MathFunction::Floor, &Number::DecimalNumber(1e20)), Some(Number::NaturalNumber(BigInt::from(10).pow(20))));
+        assert_eq!(rounding(MathFunction::Floor, &Number::DecimalNumber(f64::NAN)), None);
+    }
+
+    #[test]
+    fn test_root() {
+        assert_eq!(root(&natural(-27), 3), Some(natural(-3)));
+        assert_eq!(root(&natural(26), 3), None);
+        assert_eq!(root(&natural(-16), 4), None);
+        let r = Number::Rational(BigRational::new(BigInt::from(27), BigInt::from(8)));
+        assert_eq!(root(&r, 3), Some(Number::Rational(BigRational::new(BigInt::from(3), BigInt::from(2)))));
+    }
+
+    #[test]
+    fn test_function() {
+        assert_eq!(exact(MathFunction::Gcd, &[12, -18]), natural(6));
+        assert_eq!(exact(MathFunction::Lcm, &[4, 6]), natural(12));
+        assert_eq!(exact(MathFunction::ModPow, &[4, 13, 497]), natural(445));
+        assert_eq!(exact(MathFunction::NCr, &[52, 5]), natural(2_598_960));
+        assert_eq!(exact(MathFunction::NPr, &[10, 3]), natural(720));
+        assert_eq!(exact(MathFunction::NCr, &[3, 5]), natural(0));
+        assert!(function(MathFunction::ModPow, &[BigInt::from(2), BigInt::from(3), BigInt::zero()]).is_err());
+        assert_eq!(exact(MathFunction::ModInv, &[3, 11]), natural(4));
+        assert!(function(MathFunction::ModInv, &[BigInt::from(6), BigInt::from(9)]).is_err());
+        assert_eq!(product(&BigInt::one(), 20, 1), BigInt::from(2_432_902_008_176_640_000u64));
+    }
+}
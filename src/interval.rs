@@ -0,0 +1,467 @@
+use std::{cmp::Ordering, f64::consts::PI, fmt::Display};
+
+use anyhow::anyhow;
+use num::{BigInt, FromPrimitive, ToPrimitive, Zero};
+
+use crate::token::{MathFunction, Number, Operator};
+
+static BOUNDS_ERR: &str = "Runtime error: The bounds of an interval must be two real numbers, the lower one first.";
+static DOMAIN_ERR: &str = "Runtime error: The interval is outside the domain of the function";
+static OPERAND_ERR: &str = "Runtime error: An interval can only be combined with a real number.";
+static POWER_ERR: &str = "Runtime error: A negative interval can only be raised to an integer power.";
+
+/// A closed interval of real numbers `[lo, hi]`, that is guaranteed to contain the exact
+/// result of the computation.
+///
+/// Every bound is rounded outward: down for the lower bound and up for the upper one.
+/// The bounds of '+', '-', '*' and '/' are widened only when the operation is inexact, while
+/// the elementary functions are widened by one ulp, assuming they are accurate to one ulp.
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Interval {
+    lo: f64,
+    hi: f64,
+}
+
+impl Interval {
+    /// Builds the interval `[lo, hi]`, or returns [`None`] if `lo` exceeds `hi` or any bound is not a number
+    ///
+    #[must_use]
+    pub fn new(lo: f64, hi: f64) -> Option<Interval> {
+        (lo <= hi).then_some(Interval { lo, hi })
+    }
+
+    /// The smallest interval of floats that surely contains a value that has been rounded once
+    ///
+    #[must_use]
+    pub fn enclose(v: f64) -> Interval {
+        Interval { lo: next_down(v), hi: next_up(v) }
+    }
+
+    /// The lower bound
+    ///
+    #[must_use]
+    pub fn lo(&self) -> f64 {
+        self.lo
+    }
+
+    /// The upper bound
+    ///
+    #[must_use]
+    pub fn hi(&self) -> f64 {
+        self.hi
+    }
+
+    /// The middle point of the interval
+    ///
+    #[must_use]
+    pub fn mid(&self) -> f64 {
+        self.lo / 2. + self.hi / 2.
+    }
+
+    /// The width of the interval, rounded up
+    ///
+    #[must_use]
+    pub fn width(&self) -> f64 {
+        next_up(self.hi - self.lo)
+    }
+
+    /// Checks if the value belongs to the interval
+    ///
+    #[must_use]
+    pub fn contains(&self, v: f64) -> bool {
+        self.lo <= v && v <= self.hi
+    }
+
+    /// Rounds the bounds outward, or gives back not a number
+    ///
+    fn outward(lo: f64, hi: f64) -> Number {
+        Interval::new(next_down(lo), next_up(hi)).map_or(Number::DecimalNumber(f64::NAN), Number::Interval)
+    }
+
+    /// Applies a non-decreasing function to both bounds
+    ///
+    fn map(&self, f: fn(f64) -> f64) -> Number {
+        Interval::outward(f(self.lo), f(self.hi))
+    }
+
+    /// The part of the interval within `[lo, hi]`, or a domain error if there's nothing in common
+    ///
+    fn clip(&self, lo: f64, hi: f64, fun: MathFunction) -> anyhow::Result<Interval> {
+        Interval::new(self.lo.max(lo), self.hi.min(hi))
+            .ok_or_else(|| anyhow!("{} '{}'.", DOMAIN_ERR, fun.to_string().to_lowercase()))
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+/// Sees a real [Number] as the smallest [Interval] that contains it
+///
+pub(crate) fn from_number(n: &Number) -> Option<Interval> {
+    let exact = |v: f64, back: bool| if back { Interval { lo: v, hi: v } } else { Interval::enclose(v) };
+    match n {
+        Number::Interval(x) => Some(*x),
+        Number::NaturalNumber(v) => {
+            let f = v.to_f64()?;
+            Some(exact(f, BigInt::from_f64(f).as_ref() == Some(v)))
+        }
+        Number::DecimalNumber(v) if v.is_nan() => None,
+        // a float literal is very likely the rounding of a decimal number, as 0.1
+        Number::DecimalNumber(v) => Some(exact(*v, v.fract() == 0. || v.is_infinite())),
+        Number::Rational(v) => v.to_f64().map(Interval::enclose),
+        Number::BigDecimal(v) => {
+            let f = v.to_f64()?;
+            Some(exact(f, v.is_integer() && f.fract() == 0.))
+        }
+        Number::Complex(_) | Number::Quantity(_) | Number::Matrix(_) | Number::Uncertain(_) => None,
+    }
+}
+
+/// Builds an interval out of its bounds: 'interval(1.9, 2.1)', or the literal '[1.9, 2.1]' in interval mode
+///
+pub(crate) fn from_bounds(lo: &Number, hi: &Number) -> anyhow::Result<Number> {
+    match (from_number(lo), from_number(hi)) {
+        (Some(lo), Some(hi)) => Interval::new(lo.lo, hi.hi).map(Number::Interval).ok_or_else(|| anyhow!(BOUNDS_ERR)),
+        _ => Err(anyhow!(BOUNDS_ERR)),
+    }
+}
+
+/// Widens a value by a relative tolerance: 'x' becomes '[x - |x|*t, x + |x|*t]'
+///
+pub(crate) fn with_tolerance(n: &Number, tolerance: f64) -> Option<Number> {
+    let x = from_number(n)?;
+    let delta = Interval { lo: tolerance.abs(), hi: tolerance.abs() };
+    let spread = mul(abs(x), delta);
+    Some(Number::Interval(Interval { lo: plus(x.lo, -spread.hi).0, hi: plus(x.hi, spread.hi).1 }))
+}
+
+/// Evaluates a binary [Operator] between two numbers, at least one of which is a [`Number::Interval`]
+///
+pub(crate) fn arithmetic(op: Operator, left: &Number, right: &Number) -> anyhow::Result<Number> {
+    let (Some(a), Some(b)) = (from_number(left), from_number(right)) else {
+        return Err(anyhow!(OPERAND_ERR));
+    };
+    let res = match op {
+        Operator::Add => Number::Interval(Interval { lo: plus(a.lo, b.lo).0, hi: plus(a.hi, b.hi).1 }),
+        Operator::Sub => Number::Interval(Interval { lo: plus(a.lo, -b.hi).0, hi: plus(a.hi, -b.lo).1 }),
+        Operator::Mul => Number::Interval(mul(a, b)),
+        Operator::Div => div(a, b),
+        Operator::Pow => pow(a, b, right)?,
+        _ => return Err(anyhow!("Runtime error: Operator '{op}' can't be applied to an interval.")),
+    };
+    Ok(res)
+}
+
+/// The least float greater than a value, as `f64::next_up`, that needs a later Rust than the crate
+///
+fn next_up(v: f64) -> f64 {
+    if v.is_nan() || v == f64::INFINITY {
+        return v;
+    }
+    if v == 0. {
+        // the smallest subnormal, after both the zeros
+        return f64::from_bits(1);
+    }
+    let bits = v.to_bits();
+    f64::from_bits(if v > 0. { bits + 1 } else { bits - 1 })
+}
+
+/// The greatest float less than a value, as `f64::next_down`
+///
+fn next_down(v: f64) -> f64 {
+    -next_up(-v)
+}
+
+/// Rounds a result outward by the sign of its exact error, known to be `sign(err)`:
+/// an exact result is kept as it is
+///
+fn rounded(v: f64, err: f64) -> (f64, f64) {
+    if !v.is_finite() || (v != 0. && !v.is_normal()) {
+        // overflow or underflow: the error is not exact any more
+        (next_down(v), next_up(v))
+    } else if err > 0. {
+        (v, next_up(v))
+    } else if err < 0. {
+        (next_down(v), v)
+    } else {
+        (v, v)
+    }
+}
+
+/// The bounds of a sum, with the exact error of the 2Sum algorithm
+///
+fn plus(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    rounded(s, (a - (s - bb)) + (b - bb))
+}
+
+/// The bounds of a product, where zero times anything (infinity too) is exactly zero,
+/// with the exact error given by a fused multiply-add
+///
+fn times(a: f64, b: f64) -> (f64, f64) {
+    if a == 0. || b == 0. {
+        return (0., 0.);
+    }
+    let p = a * b;
+    rounded(p, a.mul_add(b, -p))
+}
+
+/// The bounds of a quotient, with the sign of the exact remainder
+///
+fn quotient(a: f64, b: f64) -> (f64, f64) {
+    if a == 0. || b.is_infinite() {
+        return (a / b, a / b);
+    }
+    let q = a / b;
+    rounded(q, -q.mul_add(b, -a) / b)
+}
+
+fn hull(values: [f64; 4]) -> (f64, f64) {
+    values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)))
+}
+
+fn mul(a: Interval, b: Interval) -> Interval {
+    let p = [times(a.lo, b.lo), times(a.lo, b.hi), times(a.hi, b.lo), times(a.hi, b.hi)];
+    Interval { lo: hull(p.map(|(lo, _)| lo)).0, hi: hull(p.map(|(_, hi)| hi)).1 }
+}
+
+/// Divides by an interval: when it contains zero, the result is the whole real line
+///
+fn div(a: Interval, b: Interval) -> Number {
+    if b.contains(0.) {
+        return Number::Interval(Interval { lo: f64::NEG_INFINITY, hi: f64::INFINITY });
+    }
+    let q = [quotient(a.lo, b.lo), quotient(a.lo, b.hi), quotient(a.hi, b.lo), quotient(a.hi, b.hi)];
+    Number::Interval(Interval { lo: hull(q.map(|(lo, _)| lo)).0, hi: hull(q.map(|(_, hi)| hi)).1 })
+}
+
+fn abs(x: Interval) -> Interval {
+    if x.lo >= 0. {
+        x
+    } else if x.hi <= 0. {
+        Interval { lo: -x.hi, hi: -x.lo }
+    } else {
+        Interval { lo: 0., hi: x.hi.max(-x.lo) }
+    }
+}
+
+/// Raises an interval to a power: exactly monotonic bounds for an integer exponent,
+/// 'exp(b * ln(a))' otherwise
+///
+fn pow(a: Interval, b: Interval, exponent: &Number) -> anyhow::Result<Number> {
+    let integer = match exponent {
+        Number::NaturalNumber(n) => n.to_i32(),
+        Number::DecimalNumber(v) if v.fract() == 0. => v.to_i32(),
+        _ => None,
+    };
+    match integer {
+        Some(0) => Ok(Number::Interval(Interval { lo: 1., hi: 1. })),
+        Some(n) if n < 0 => Ok(div(Interval { lo: 1., hi: 1. }, powi(a, n.unsigned_abs()))),
+        Some(n) => Ok(Number::Interval(powi(a, n.unsigned_abs()))),
+        None if a.hi < 0. => Err(anyhow!(POWER_ERR)),
+        None => {
+            let ln = ln(a.clip(0., f64::INFINITY, MathFunction::Ln)?);
+            Ok(mul(ln, b).map(f64::exp))
+        }
+    }
+}
+
+fn powi(a: Interval, n: u32) -> Interval {
+    // the bounds of a point raised to the n, by squaring
+    let point = |v: f64| {
+        let (mut base, mut acc, mut n) = (Interval { lo: v, hi: v }, Interval { lo: 1., hi: 1. }, n);
+        while n > 0 {
+            if n & 1 == 1 {
+                acc = mul(acc, base);
+            }
+            base = mul(base, base);
+            n >>= 1;
+        }
+        acc
+    };
+    if n % 2 == 1 {
+        Interval { lo: point(a.lo).lo, hi: point(a.hi).hi }
+    } else {
+        let m = abs(a);
+        Interval { lo: point(m.lo).lo.max(0.), hi: point(m.hi).hi }
+    }
+}
+
+fn ln(x: Interval) -> Interval {
+    Interval { lo: next_down(x.lo.ln()), hi: next_up(x.hi.ln()) }
+}
+
+/// Checks if the interval may contain a point 'offset + k * period', for any integer k.
+/// It errs on the side of a yes, which only makes the result wider.
+///
+fn may_contain(x: Interval, offset: f64, period: f64) -> bool {
+    let slack = 1e-9;
+    let k = ((x.lo - offset) / period - slack).ceil();
+    offset + k * period <= x.hi + slack * period
+}
+
+/// The sine and the cosine: the bound is 1 (or -1) if the interval may reach a maximum (or a minimum)
+///
+fn periodic(x: Interval, f: fn(f64) -> f64, max_at: f64) -> Number {
+    if !x.lo.is_finite() || !x.hi.is_finite() || x.hi - x.lo >= 2. * PI {
+        return Number::Interval(Interval { lo: -1., hi: 1. });
+    }
+    let (a, b) = (f(x.lo), f(x.hi));
+    let lo = if may_contain(x, max_at + PI, 2. * PI) { -1. } else { next_down(a.min(b)).max(-1.) };
+    let hi = if may_contain(x, max_at, 2. * PI) { 1. } else { next_up(a.max(b)).min(1.) };
+    Number::Interval(Interval { lo, hi })
+}
+
+/// Evaluates a [`MathFunction`] over an interval, giving back the smallest interval of floats that
+/// contains all its values. The interval is clipped to the domain of the function.
+///
+pub(crate) fn function(fun: MathFunction, x: &Interval) -> anyhow::Result<Number> {
+    let sign = |v: f64| if v == 0. { 0. } else { v.signum() };
+    let res = match fun {
+        MathFunction::Sin => periodic(*x, f64::sin, PI / 2.),
+        MathFunction::Cos => periodic(*x, f64::cos, 0.),
+        MathFunction::Tan if x.hi - x.lo >= PI || may_contain(*x, PI / 2., PI) => {
+            Number::Interval(Interval { lo: f64::NEG_INFINITY, hi: f64::INFINITY })
+        }
+        MathFunction::Tan => x.map(f64::tan),
+        MathFunction::ASin => x.clip(-1., 1., fun)?.map(f64::asin),
+        MathFunction::ACos => {
+            let x = x.clip(-1., 1., fun)?;
+            Interval::outward(x.hi.acos(), x.lo.acos())
+        }
+        MathFunction::ATan => x.map(f64::atan),
+        MathFunction::Ln => x.clip(0., f64::INFINITY, fun)?.map(f64::ln),
+        MathFunction::Log => x.clip(0., f64::INFINITY, fun)?.map(f64::log10),
+        MathFunction::Sqrt => {
+            // never negative, even after rounding outward
+            let x = x.clip(0., f64::INFINITY, fun)?;
+            Number::Interval(Interval { lo: next_down(x.lo.sqrt()).max(0.), hi: next_up(x.hi.sqrt()) })
+        }
+        MathFunction::Abs => Number::Interval(abs(*x)),
+        MathFunction::Sinh => x.map(f64::sinh),
+        MathFunction::Cosh => abs(*x).map(f64::cosh),
+        MathFunction::Tanh => x.map(f64::tanh),
+        MathFunction::ASinh => x.map(f64::asinh),
+        MathFunction::ACosh => x.clip(1., f64::INFINITY, fun)?.map(f64::acosh),
+        MathFunction::ATanh => x.clip(-1., 1., fun)?.map(f64::atanh),
+        MathFunction::Exp => x.map(f64::exp),
+        MathFunction::Log2 => x.clip(0., f64::INFINITY, fun)?.map(f64::log2),
+        MathFunction::Cbrt => x.map(f64::cbrt),
+        // exact, as the bounds are floats
+        MathFunction::Floor => Number::Interval(Interval { lo: x.lo.floor(), hi: x.hi.floor() }),
+        MathFunction::Ceil => Number::Interval(Interval { lo: x.lo.ceil(), hi: x.hi.ceil() }),
+        MathFunction::Round => Number::Interval(Interval { lo: x.lo.round(), hi: x.hi.round() }),
+        MathFunction::Trunc => Number::Interval(Interval { lo: x.lo.trunc(), hi: x.hi.trunc() }),
+        MathFunction::Sign => Number::Interval(Interval { lo: sign(x.lo), hi: sign(x.hi) }),
+        MathFunction::Re | MathFunction::Conj => Number::Interval(*x),
+        MathFunction::Im => Number::NaturalNumber(BigInt::zero()),
+        MathFunction::Arg if x.lo >= 0. => Number::NaturalNumber(BigInt::zero()),
+        MathFunction::Arg if x.hi < 0. => Number::Interval(Interval::enclose(PI)),
+        MathFunction::Arg => Number::Interval(Interval { lo: 0., hi: next_up(PI) }),
+        _ => return Err(anyhow!("Runtime error: Function '{fun}' can't be applied to an interval.")),
+    };
+    Ok(res)
+}
+
+/// Orders two intervals when they don't overlap
+///
+pub(crate) fn compare(a: &Number, b: &Number) -> Option<Ordering> {
+    let (a, b) = (from_number(a)?, from_number(b)?);
+    if a.hi < b.lo {
+        Some(Ordering::Less)
+    } else if a.lo > b.hi {
+        Some(Ordering::Greater)
+    } else if a.hi <= a.lo && a == b {
+        Some(Ordering::Equal)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(lo: f64, hi: f64) -> Number {
+        Number::Interval(Interval::new(lo, hi).unwrap())
+    }
+
+    fn bounds(n: &Number) -> (f64, f64) {
+        let x = from_number(n).unwrap();
+        (x.lo(), x.hi())
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let res = arithmetic(Operator::Mul, &interval(1.9, 2.1), &interval(2.9, 3.1)).unwrap();
+        let (lo, hi) = bounds(&res);
+        assert!(lo < 5.51 && 5.51 - lo < 1e-14);
+        assert!(hi > 6.51 && hi - 6.51 < 1e-14);
+
+        let res = arithmetic(Operator::Sub, &interval(1., 2.), &interval(1., 2.)).unwrap();
+        assert!(from_number(&res).unwrap().contains(0.));
+
+        let res = arithmetic(Operator::Div, &Number::DecimalNumber(1.), &interval(-1., 1.)).unwrap();
+        assert_eq!(bounds(&res), (f64::NEG_INFINITY, f64::INFINITY));
+
+        let res = arithmetic(Operator::Pow, &interval(-2., 1.), &Number::NaturalNumber(BigInt::from(2))).unwrap();
+        assert_eq!(bounds(&res).0, 0.);
+        assert!(bounds(&res).1 >= 4.);
+
+        assert!(arithmetic(Operator::Pow, &interval(-2., -1.), &Number::DecimalNumber(0.5)).is_err());
+    }
+
+    #[test]
+    fn test_next() {
+        assert_eq!(next_up(1.), 1. + f64::EPSILON);
+        assert_eq!(next_down(1.), 1. - f64::EPSILON / 2.);
+        assert_eq!(next_up(-0.), f64::from_bits(1));
+        assert_eq!(next_down(f64::from_bits(1)), 0.);
+        assert_eq!(next_up(f64::MAX), f64::INFINITY);
+        assert_eq!(next_down(f64::INFINITY), f64::MAX);
+        assert_eq!(next_up(f64::NEG_INFINITY), f64::MIN);
+        assert!(next_up(f64::NAN).is_nan());
+    }
+
+        #[test]
+    fn test_from_number() {
+        assert_eq!(bounds(&Number::NaturalNumber(BigInt::from(3))), (3., 3.));
+        let (lo, hi) = bounds(&Number::DecimalNumber(0.1));
+        assert!(lo < 0.1 && 0.1 < hi);
+        assert!(from_number(&Number::DecimalNumber(f64::NAN)).is_none());
+    }
+
+    #[test]
+    fn test_functions() {
+        let x = Interval::new(0., PI).unwrap();
+        let (lo, hi) = bounds(&function(MathFunction::Sin, &x).unwrap());
+        assert!(lo <= 0. && lo > -1e-15);
+        assert_eq!(hi, 1.);
+
+        let (lo, hi) = bounds(&function(MathFunction::Cos, &x).unwrap());
+        assert_eq!((lo, hi), (-1., 1.));
+
+        let x = Interval::new(-4., 9.).unwrap();
+        let (lo, hi) = bounds(&function(MathFunction::Sqrt, &x).unwrap());
+        assert_eq!(lo, 0.);
+        assert!(hi >= 3. && hi - 3. < 1e-15);
+
+        let x = Interval::new(1., 2.).unwrap();
+        let (lo, hi) = bounds(&function(MathFunction::Tan, &x).unwrap());
+        assert_eq!((lo, hi), (f64::NEG_INFINITY, f64::INFINITY));
+
+        assert!(function(MathFunction::ASin, &Interval::new(2., 3.).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let one = Number::NaturalNumber(BigInt::from(1));
+        assert_eq!(compare(&interval(2., 3.), &one), Some(Ordering::Greater));
+        assert_eq!(compare(&interval(0., 3.), &one), None);
+    }
+}
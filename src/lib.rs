@@ -45,7 +45,10 @@
 //! println!("The result is {}", resolver.resolve().unwrap());
 //! ```
 //!
-//! The result can be simply converted into a i32 or a f64 (if decimal) simply with
+//! The result can be converted into an integer or a f64 with the checked [`TryFrom`] conversions,
+//! that fail with a [`ConversionError`](error::ConversionError) when the number doesn't fit.
+//! Borrow the number for a f64: by value, `f64::try_from(result)` is the lossy `From` conversion,
+//! that gives back NaN for anything but a real number
 //!
 //! ```
 //! # use yarer::{rpn_resolver::RpnResolver, session::Session, token::Number};
@@ -54,9 +57,10 @@
 //!
 //! let result: Number = resolver.resolve().unwrap();
 //!
-//! let int : i32 = result.clone().into();
+//! let float = f64::try_from(&result).unwrap();
+//! let int = i32::try_from(&result); // Err, it's not an integer
 //! // or
-//! let float : f64 = result.into();
+//! let int: i64 = result.to_i64_rounded().unwrap();
 //! ```
 //!
 //! Yarer can be used also from command line, and behaves in a very similar manner to GNU bc
@@ -104,12 +108,33 @@
 //! Abs
 //! Sqrt
 //! ```
+mod calculus;
+mod decimal;
+mod distribution;
+/// Errors
+pub mod error;
+mod finance;
+mod integer;
+/// Interval arithmetic
+pub mod interval;
+/// Matrices
+pub mod matrix;
+mod number_theory;
 /// Parser
 pub mod parser;
+mod random;
 /// `RpnResolver`
 pub mod rpn_resolver;
 /// Session
 pub mod session;
+mod special;
+mod stats;
+/// Symbolic expressions
+pub mod symbolic;
 /// Token
 pub mod token;
+/// Uncertainty propagation
+pub mod uncertainty;
+/// Units of measure
+pub mod units;
 
@@ -0,0 +1,465 @@
+use std::fmt::Display;
+
+use anyhow::anyhow;
+use num::{BigInt, BigRational, ToPrimitive, Zero};
+
+use crate::token::{MathFunction, Number, Operator};
+
+static DIMENSION_ERR: &str = "Runtime error: Matrix dimensions don't match:";
+static SQUARE_ERR: &str = "Runtime error: The matrix must be square:";
+static SINGULAR_ERR: &str = "Runtime error: The matrix is singular.";
+static VECTOR_ERR: &str = "Runtime error: The arguments must be vectors of the same length:";
+static CROSS_ERR: &str = "Runtime error: The cross product needs two vectors of length 3.";
+static ROWS_ERR: &str = "Runtime error: The rows of a matrix must be non-empty and of the same length.";
+static NESTED_ERR: &str = "Runtime error: The elements of a matrix must be scalars.";
+static POWER_ERR: &str = "Runtime error: A matrix can only be raised to an integer power.";
+static OPERATOR_ERR: &str = "Runtime error: The operator can't be applied to a matrix:";
+
+/// Evaluates a binary [Operator] between two scalars, with the rules of the resolver
+///
+pub(crate) type Scalar<'a> = &'a dyn Fn(Operator, Number, Number) -> anyhow::Result<Number>;
+
+/// A matrix of [Number]s, stored row by row. A vector is a matrix with a single row or column.
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<Number>,
+}
+
+impl Matrix {
+    /// Builds a matrix out of its elements, row by row, or returns [`None`]
+    /// if their number isn't rows * cols
+    ///
+    #[must_use]
+    pub fn new(rows: usize, cols: usize, data: Vec<Number>) -> Option<Matrix> {
+        (rows * cols == data.len()).then_some(Matrix { rows, cols, data })
+    }
+
+    /// The number of rows
+    ///
+    #[must_use]
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The number of columns
+    ///
+    #[must_use]
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// The element at the given row and column, counting from 0
+    ///
+    #[must_use]
+    pub fn get(&self, row: usize, col: usize) -> Option<&Number> {
+        (row < self.rows && col < self.cols).then(|| &self.data[row * self.cols + col])
+    }
+
+    /// All the elements, row by row
+    ///
+    #[must_use]
+    pub fn elements(&self) -> &[Number] {
+        &self.data
+    }
+
+    /// Swaps the rows with the columns
+    ///
+    #[must_use]
+    pub fn transpose(&self) -> Matrix {
+        let data = (0..self.cols)
+            .flat_map(|c| (0..self.rows).map(move |r| (r, c)))
+            .map(|(r, c)| self.data[r * self.cols + c].clone())
+            .collect();
+        Matrix { rows: self.cols, cols: self.rows, data }
+    }
+
+    fn identity(n: usize) -> Matrix {
+        let data = (0..n * n)
+            .map(|i| Number::NaturalNumber(BigInt::from(u8::from(i / n == i % n))))
+            .collect();
+        Matrix { rows: n, cols: n, data }
+    }
+
+    fn is_vector(&self) -> bool {
+        self.rows == 1 || self.cols == 1
+    }
+
+    fn shape(&self) -> String {
+        format!("{}x{}", self.rows, self.cols)
+    }
+
+    fn map<F>(&self, f: F) -> anyhow::Result<Matrix>
+    where
+        F: FnMut(Number) -> anyhow::Result<Number>,
+    {
+        let data = self.data.iter().cloned().map(f).collect::<anyhow::Result<Vec<Number>>>()?;
+        Ok(Matrix { data, ..*self })
+    }
+
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        for c in 0..self.cols {
+            self.data.swap(a * self.cols + c, b * self.cols + c);
+        }
+    }
+}
+
+impl Display for Matrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows: Vec<String> = self
+            .data
+            .chunks(self.cols.max(1))
+            .map(|row| row.iter().map(ToString::to_string).collect::<Vec<String>>().join(", "))
+            .collect();
+        write!(f, "[{}]", rows.join("; "))
+    }
+}
+
+/// Builds a matrix literal out of the values of its rows
+///
+pub(crate) fn from_rows(values: Vec<Number>, row_lengths: &[usize]) -> anyhow::Result<Number> {
+    let cols = row_lengths.first().copied().unwrap_or_default();
+    if cols == 0 || row_lengths.iter().any(|&len| len != cols) {
+        return Err(anyhow!(ROWS_ERR));
+    }
+    if values.iter().any(|v| matches!(v, Number::Matrix(_))) {
+        return Err(anyhow!(NESTED_ERR));
+    }
+    Ok(Number::from_matrix(Matrix { rows: row_lengths.len(), cols, data: values }))
+}
+
+/// The scalar [Operator] applied to each couple of elements by an element-wise operator
+///
+pub(crate) fn element_wise(op: Operator) -> Operator {
+    match op {
+        Operator::ElemMul => Operator::Mul,
+        Operator::ElemDiv => Operator::Div,
+        Operator::ElemPow => Operator::Pow,
+        op => op,
+    }
+}
+
+/// Evaluates a binary [Operator] between two numbers, at least one of which is a
+/// [`Number::Matrix`]. '*' is the matrix product, 'A/B' is 'A*inv(B)' and '^' is the
+/// power of a square matrix, while '+', '-', '.*', './' and '.^' work element by element.
+/// A scalar on one side is combined with every element of the matrix.
+///
+pub(crate) fn arithmetic(op: Operator, left: Number, right: Number, scalar: Scalar) -> anyhow::Result<Number> {
+    let elem = element_wise(op);
+    let m = match (left, right) {
+        (Number::Matrix(a), Number::Matrix(b)) => match op {
+            Operator::Add | Operator::Sub | Operator::ElemMul | Operator::ElemDiv | Operator::ElemPow => {
+                zip(&a, &b, elem, scalar)?
+            }
+            Operator::Mul => product(&a, &b, scalar)?,
+            Operator::Div => product(&a, &inv(&b, scalar)?, scalar)?,
+            _ => return Err(anyhow!("{OPERATOR_ERR} '{op}'.")),
+        },
+        (Number::Matrix(a), s) if op == Operator::Pow => power(&a, &s, scalar)?,
+        (Number::Matrix(a), s) => a.map(|x| scalar(elem, x, s.clone()))?,
+        (_, Number::Matrix(_)) if op == Operator::Pow => return Err(anyhow!("{OPERATOR_ERR} '{op}'.")),
+        (s, Number::Matrix(b)) if op == Operator::Div => inv(&b, scalar)?.map(|x| scalar(Operator::Mul, s.clone(), x))?,
+        (s, Number::Matrix(b)) => b.map(|x| scalar(elem, s.clone(), x))?,
+        (left, right) => return scalar(elem, left, right),
+    };
+    Ok(Number::from_matrix(m))
+}
+
+fn zip(a: &Matrix, b: &Matrix, op: Operator, scalar: Scalar) -> anyhow::Result<Matrix> {
+    if (a.rows, a.cols) != (b.rows, b.cols) {
+        return Err(anyhow!("{} {} and {}.", DIMENSION_ERR, a.shape(), b.shape()));
+    }
+    let data = a
+        .data
+        .iter()
+        .zip(&b.data)
+        .map(|(x, y)| scalar(op, x.clone(), y.clone()))
+        .collect::<anyhow::Result<Vec<Number>>>()?;
+    Ok(Matrix { data, ..*a })
+}
+
+fn product(a: &Matrix, b: &Matrix, scalar: Scalar) -> anyhow::Result<Matrix> {
+    if a.cols != b.rows {
+        return Err(anyhow!("{} {} and {}.", DIMENSION_ERR, a.shape(), b.shape()));
+    }
+    let mut data = Vec::with_capacity(a.rows * b.cols);
+    for r in 0..a.rows {
+        for c in 0..b.cols {
+            let terms = (0..a.cols).map(|k| (a.data[r * a.cols + k].clone(), b.data[k * b.cols + c].clone()));
+            data.push(sum_of_products(terms, scalar)?);
+        }
+    }
+    Ok(Matrix { rows: a.rows, cols: b.cols, data })
+}
+
+/// Sums up the products of the couples of elements. The sum starts from the first
+/// product rather than from 0, so that it works with quantities too.
+///
+fn sum_of_products<I>(mut terms: I, scalar: Scalar) -> anyhow::Result<Number>
+where
+    I: Iterator<Item = (Number, Number)>,
+{
+    let Some((x, y)) = terms.next() else {
+        return Ok(Number::NaturalNumber(BigInt::zero()));
+    };
+    terms.try_fold(scalar(Operator::Mul, x, y)?, |sum, (x, y)| {
+        let term = scalar(Operator::Mul, x, y)?;
+        scalar(Operator::Add, sum, term)
+    })
+}
+
+/// Raises a square matrix to an integer power, by repeated squaring
+///
+fn power(a: &Matrix, exponent: &Number, scalar: Scalar) -> anyhow::Result<Matrix> {
+    square(a)?;
+    let n = match exponent {
+        Number::NaturalNumber(n) => n.to_i64(),
+        Number::DecimalNumber(n) if n.fract() == 0. => n.to_i64(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!(POWER_ERR))?;
+
+    let mut base = if n < 0 { inv(a, scalar)? } else { a.clone() };
+    let mut result = Matrix::identity(a.rows);
+    let mut n = n.unsigned_abs();
+    while n > 0 {
+        if n & 1 == 1 {
+            result = product(&result, &base, scalar)?;
+        }
+        n >>= 1;
+        if n > 0 {
+            base = product(&base, &base, scalar)?;
+        }
+    }
+    Ok(result)
+}
+
+fn square(a: &Matrix) -> anyhow::Result<usize> {
+    if a.rows == a.cols {
+        Ok(a.rows)
+    } else {
+        Err(anyhow!("{} {}.", SQUARE_ERR, a.shape()))
+    }
+}
+
+/// The magnitude of a number, to choose the pivots of the eliminations
+///
+fn magnitude(n: &Number) -> f64 {
+    match n {
+        Number::Complex(z) => z.norm(),
+        n => f64::from(n.clone()).abs(),
+    }
+}
+
+/// Finds out the row, from `k` on, with the largest element in column `k`
+///
+fn pivot(a: &Matrix, k: usize) -> usize {
+    (k..a.rows)
+        .max_by(|&i, &j| magnitude(&a.data[i * a.cols + k]).total_cmp(&magnitude(&a.data[j * a.cols + k])))
+        .unwrap_or(k)
+}
+
+/// The determinant of a square matrix, with the fraction-free Bareiss algorithm:
+/// it stays exact on integers
+///
+fn det(m: &Matrix, scalar: Scalar) -> anyhow::Result<Number> {
+    let n = square(m)?;
+    let mut work = m.clone();
+    let mut negative = false;
+    let mut previous: Option<Number> = None;
+
+    for k in 0..n - 1 {
+        let pivot_row = pivot(&work, k);
+        if magnitude(&work.data[pivot_row * n + k]) == 0. {
+            return Ok(Number::NaturalNumber(BigInt::zero()));
+        }
+        if pivot_row != k {
+            work.swap_rows(pivot_row, k);
+            negative = !negative;
+        }
+        for i in k + 1..n {
+            for j in k + 1..n {
+                let diagonal = scalar(Operator::Mul, work.data[i * n + j].clone(), work.data[k * n + k].clone())?;
+                let cross = scalar(Operator::Mul, work.data[i * n + k].clone(), work.data[k * n + j].clone())?;
+                let mut entry = scalar(Operator::Sub, diagonal, cross)?;
+                if let Some(previous) = &previous {
+                    entry = scalar(Operator::Div, entry, previous.clone())?;
+                }
+                work.data[i * n + j] = entry;
+            }
+        }
+        previous = Some(work.data[k * n + k].clone());
+    }
+    let result = work.data[n * n - 1].clone();
+    if negative {
+        scalar(Operator::Mul, result, Number::NaturalNumber(BigInt::from(-1)))
+    } else {
+        Ok(result)
+    }
+}
+
+/// The inverse of a square matrix. A matrix of integers and fractions is inverted exactly,
+/// and then brought into the numeric mode of the resolver.
+///
+fn inv(m: &Matrix, scalar: Scalar) -> anyhow::Result<Matrix> {
+    if m.data.iter().all(|x| matches!(x, Number::NaturalNumber(_) | Number::Rational(_))) {
+        return gauss_jordan(m, &|op, a, b| Ok(exact_scalar(op, a, b)))?.map(|x| match x {
+            Number::Rational(r) => {
+                let (numer, denom) = r.into_raw();
+                scalar(Operator::Div, Number::NaturalNumber(numer), Number::NaturalNumber(denom))
+            }
+            x => Ok(x),
+        });
+    }
+    gauss_jordan(m, scalar)
+}
+
+/// The exact arithmetic between integers and fractions
+///
+fn exact_scalar(op: Operator, a: Number, b: Number) -> Number {
+    let to_rational = |n: Number| match n {
+        Number::NaturalNumber(v) => BigRational::from_integer(v),
+        Number::Rational(r) => r,
+        _ => unreachable!("Only integers and fractions"),
+    };
+    let (a, b) = (to_rational(a), to_rational(b));
+    Number::from_rational(match op {
+        Operator::Add => a + b,
+        Operator::Sub => a - b,
+        Operator::Mul => a * b,
+        _ => a / b,
+    })
+}
+
+/// The inverse of a square matrix, with the Gauss-Jordan elimination
+///
+fn gauss_jordan(m: &Matrix, scalar: Scalar) -> anyhow::Result<Matrix> {
+    let n = square(m)?;
+    let mut work = m.clone();
+    let mut inverse = Matrix::identity(n);
+
+    for k in 0..n {
+        let pivot_row = pivot(&work, k);
+        if magnitude(&work.data[pivot_row * n + k]) == 0. {
+            return Err(anyhow!(SINGULAR_ERR));
+        }
+        work.swap_rows(pivot_row, k);
+        inverse.swap_rows(pivot_row, k);
+
+        let pivot = work.data[k * n + k].clone();
+        for j in 0..n {
+            work.data[k * n + j] = scalar(Operator::Div, work.data[k * n + j].clone(), pivot.clone())?;
+            inverse.data[k * n + j] = scalar(Operator::Div, inverse.data[k * n + j].clone(), pivot.clone())?;
+        }
+        for i in (0..n).filter(|&i| i != k) {
+            let factor = work.data[i * n + k].clone();
+            if magnitude(&factor) == 0. {
+                continue;
+            }
+            for j in 0..n {
+                let scaled = scalar(Operator::Mul, factor.clone(), work.data[k * n + j].clone())?;
+                work.data[i * n + j] = scalar(Operator::Sub, work.data[i * n + j].clone(), scaled)?;
+                let scaled_inverse = scalar(Operator::Mul, factor.clone(), inverse.data[k * n + j].clone())?;
+                inverse.data[i * n + j] = scalar(Operator::Sub, inverse.data[i * n + j].clone(), scaled_inverse)?;
+            }
+        }
+    }
+    Ok(inverse)
+}
+
+/// Evaluates 'det', 'inv', 'transpose' and 'norm'. A scalar is seen as a 1x1 matrix.
+///
+pub(crate) fn function(fun: MathFunction, value: Number, scalar: Scalar) -> anyhow::Result<Number> {
+    let m = match value {
+        Number::Matrix(m) => m,
+        v => Matrix { rows: 1, cols: 1, data: vec![v] },
+    };
+    match fun {
+        MathFunction::Det => det(&m, scalar),
+        MathFunction::Inv => inv(&m, scalar).map(Number::from_matrix),
+        MathFunction::Transpose => Ok(Number::from_matrix(m.transpose())),
+        MathFunction::Norm => Ok(Number::DecimalNumber(m.data.iter().map(|x| magnitude(x).powi(2)).sum::<f64>().sqrt())),
+        _ => Err(anyhow!("This should never happen!")),
+    }
+}
+
+/// Evaluates 'dot' and 'cross' between two vectors
+///
+pub(crate) fn vector_function(fun: MathFunction, left: Number, right: Number, scalar: Scalar) -> anyhow::Result<Number> {
+    let as_vector = |n: Number| match n {
+        Number::Matrix(m) if m.is_vector() => Ok(m),
+        Number::Matrix(m) => Err(anyhow!("{} {}.", VECTOR_ERR, m.shape())),
+        v => Ok(Matrix { rows: 1, cols: 1, data: vec![v] }),
+    };
+    let (a, b) = (as_vector(left)?, as_vector(right)?);
+    if a.data.len() != b.data.len() {
+        return Err(anyhow!("{} {} and {}.", VECTOR_ERR, a.shape(), b.shape()));
+    }
+
+    match fun {
+        MathFunction::Dot => sum_of_products(a.data.into_iter().zip(b.data), scalar),
+        MathFunction::Cross if a.data.len() == 3 => {
+            let (lhs, rhs) = (&a.data, &b.data);
+            let component = |i: usize, j: usize| -> anyhow::Result<Number> {
+                let direct = scalar(Operator::Mul, lhs[i].clone(), rhs[j].clone())?;
+                let reverse = scalar(Operator::Mul, lhs[j].clone(), rhs[i].clone())?;
+                scalar(Operator::Sub, direct, reverse)
+            };
+            let data = vec![component(1, 2)?, component(2, 0)?, component(0, 1)?];
+            Ok(Number::Matrix(Matrix { data, ..a }))
+        }
+        MathFunction::Cross => Err(anyhow!(CROSS_ERR)),
+        _ => Err(anyhow!("This should never happen!")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalar(op: Operator, a: Number, b: Number) -> anyhow::Result<Number> {
+        Ok(match op {
+            Operator::Add => a + b,
+            Operator::Sub => a - b,
+            Operator::Mul => a * b,
+            Operator::Div => Number::DecimalNumber(a.into()) / b,
+            _ => a ^ b,
+        })
+    }
+
+    fn matrix(rows: usize, cols: usize, values: &[i32]) -> Matrix {
+        Matrix::new(rows, cols, values.iter().map(|&v| Number::NaturalNumber(BigInt::from(v))).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_product() {
+        let a = matrix(2, 2, &[1, 2, 3, 4]);
+        let b = matrix(2, 1, &[5, 6]);
+
+        assert_eq!(product(&a, &b, &scalar).unwrap(), matrix(2, 1, &[17, 39]));
+        assert!(product(&b, &a, &scalar).is_err());
+        assert_eq!(a.transpose(), matrix(2, 2, &[1, 3, 2, 4]));
+        assert_eq!(a.to_string(), "[1, 2; 3, 4]");
+    }
+
+    #[test]
+    fn test_det() {
+        let a = matrix(3, 3, &[2, -3, 1, 2, 0, -1, 1, 4, 5]);
+        assert_eq!(f64::from(det(&a, &scalar).unwrap()), 49.);
+        assert_eq!(det(&matrix(2, 2, &[1, 2, 2, 4]), &scalar).unwrap(), Number::NaturalNumber(BigInt::zero()));
+    }
+
+    #[test]
+    fn test_inv() {
+        let a = matrix(2, 2, &[4, 7, 2, 6]);
+        let inverse = inv(&a, &scalar).unwrap();
+        let identity = product(&a, &inverse, &scalar).unwrap();
+
+        for (x, y) in identity.elements().iter().zip(Matrix::identity(2).elements()) {
+            assert!((f64::from(x.clone()) - f64::from(y.clone())).abs() < 1e-12);
+        }
+        assert!(inv(&matrix(2, 2, &[1, 2, 2, 4]), &scalar).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use anyhow::anyhow;
+use num::{BigInt, Integer, One, Signed, Zero};
+
+use crate::{
+    error::MathError,
+    matrix::Matrix,
+    token::{MathFunction, Number},
+};
+
+static FACTOR_ERR: &str = "Runtime error: The number has no factor small enough to be found.";
+static DIVISORS_ERR: &str = "Runtime error: The number has too many divisors.";
+
+/// The bases of the Miller-Rabin test: the first 13 of them make it deterministic
+/// below 3.3 * 10^24, while the larger numbers are strong probable primes to all of them
+///
+const BASES: [u32; 20] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71];
+
+/// The trial divisions before Pollard's rho, and the steps it takes before giving up
+///
+const TRIAL_LIMIT: u32 = 1_000;
+const RHO_STEPS: u32 = 1 << 20;
+const MAX_DIVISORS: u64 = 1 << 20;
+
+/// Evaluates a number-theoretic [`MathFunction`] of an integer, exactly:
+/// `isprime`, `nextprime`, `factor`, `phi`, `divisors`, `isqrt` and `digits`
+///
+pub(crate) fn function(fun: MathFunction, n: &BigInt) -> anyhow::Result<Number> {
+    let domain = || MathError::domain(fun, n);
+    let res = match fun {
+        MathFunction::IsPrime => BigInt::from(u8::from(is_prime(n))),
+        MathFunction::NextPrime => next_prime(n),
+        MathFunction::Factor | MathFunction::Divisors if n.is_zero() => return Err(domain().into()),
+        // 1 is the empty product, that has no rows
+        MathFunction::Factor if n.is_one() => return Err(domain().into()),
+        MathFunction::Factor => return factorization_matrix(n),
+        MathFunction::Phi if !n.is_positive() => return Err(domain().into()),
+        MathFunction::Phi => factorize(n)?
+            .iter()
+            .fold(BigInt::one(), |acc, (p, e)| acc * (p - 1u32) * num::pow(p.clone(), *e as usize - 1)),
+        MathFunction::Divisors => {
+            let data = divisors(&n.abs())?.into_iter().map(Number::NaturalNumber).collect::<Vec<_>>();
+            return Ok(Number::Matrix(Matrix::new(1, data.len(), data).expect("A row")));
+        }
+        MathFunction::Isqrt if n.is_negative() => return Err(domain().into()),
+        MathFunction::Isqrt => n.sqrt(),
+        MathFunction::Digits => BigInt::from(n.magnitude().to_string().len()),
+        _ => return Err(anyhow!("This should never happen!")),
+    };
+    Ok(Number::NaturalNumber(res))
+}
+
+/// Whether a number is prime, by trial division of the small ones and the Miller-Rabin test
+///
+pub(crate) fn is_prime(n: &BigInt) -> bool {
+    if *n < BigInt::from(2) {
+        return false;
+    }
+    for p in BASES {
+        if n.is_multiple_of(&BigInt::from(p)) {
+            return *n == BigInt::from(p);
+        }
+    }
+    let n_1 = n - 1u32;
+    let s = n_1.trailing_zeros().unwrap_or_default();
+    let d = &n_1 >> s;
+    BASES.iter().all(|&a| {
+        let mut x = BigInt::from(a).modpow(&d, n);
+        if x.is_one() || x == n_1 {
+            return true;
+        }
+        for _ in 1..s {
+            x = &x * &x % n;
+            if x == n_1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// The smallest prime greater than a number
+///
+fn next_prime(n: &BigInt) -> BigInt {
+    let two = BigInt::from(2);
+    if *n < two {
+        return two;
+    }
+    let mut candidate = if n.is_even() { n + 1u32 } else { n + 2u32 };
+    while !is_prime(&candidate) {
+        candidate += 2u32;
+    }
+    candidate
+}
+
+/// The prime factors of a non-zero number with their exponents, in ascending order.
+/// A negative number has the unit -1 as its first factor, and 1 has no factors at all.
+///
+pub(crate) fn factorize(n: &BigInt) -> anyhow::Result<Vec<(BigInt, u32)>> {
+    let mut factors: Vec<BigInt> = Vec::new();
+    let mut rest = n.abs();
+
+    let mut p = 2u32;
+    while p < TRIAL_LIMIT && BigInt::from(p * p) <= rest {
+        while rest.is_multiple_of(&BigInt::from(p)) {
+            rest /= p;
+            factors.push(BigInt::from(p));
+        }
+        p += if p == 2 { 1 } else { 2 };
+    }
+    let mut composites = vec![rest];
+    while let Some(m) = composites.pop() {
+        if m.is_one() {
+            continue;
+        }
+        if is_prime(&m) {
+            factors.push(m);
+            continue;
+        }
+        let d = rho(&m).ok_or_else(|| anyhow!(FACTOR_ERR))?;
+        composites.push(&m / &d);
+        composites.push(d);
+    }
+    factors.sort();
+
+    let mut res: Vec<(BigInt, u32)> = Vec::new();
+    if n.is_negative() {
+        res.push((-BigInt::one(), 1));
+    }
+    for f in factors {
+        match res.last_mut() {
+            Some((p, e)) if *p == f => *e += 1,
+            _ => res.push((f, 1)),
+        }
+    }
+    Ok(res)
+}
+
+/// A non-trivial factor of an odd composite number, with Brent's variant of Pollard's rho:
+/// the differences are multiplied together, so that a gcd is taken every `BATCH` steps only
+///
+fn rho(n: &BigInt) -> Option<BigInt> {
+    const BATCH: u32 = 128;
+    'attempts: for c in 1u32..=8 {
+        let next = |v: &BigInt| (v * v + c) % n;
+        let (mut tortoise, mut hare) = (BigInt::from(2), BigInt::from(2));
+        let mut saved = hare.clone();
+        let mut product = BigInt::one();
+        let (mut power, mut lap) = (1u32, 0u32);
+        for step in 1..=RHO_STEPS {
+            if lap == power {
+                // the tortoise jumps to the hare at every power of two
+                tortoise.clone_from(&hare);
+                power *= 2;
+                lap = 0;
+            }
+            hare = next(&hare);
+            lap += 1;
+            product = product * (&tortoise - &hare).abs() % n;
+            if step % BATCH != 0 && lap != power {
+                continue;
+            }
+            let mut factor = product.gcd(n);
+            if factor == *n {
+                // the batch went past a factor together with its cofactor: steps through it again
+                factor = (0..BATCH)
+                    .scan(saved.clone(), |v, _| {
+                        *v = next(v);
+                        Some((&tortoise - &*v).abs().gcd(n))
+                    })
+                    .find(|g| !g.is_one())
+                    .unwrap_or_else(|| n.clone());
+            }
+            if factor == *n {
+                // the sequence cycled modulo n: starts again with another polynomial
+                continue 'attempts;
+            }
+            if !factor.is_one() {
+                return Some(factor);
+            }
+            saved.clone_from(&hare);
+        }
+        break;
+    }
+    None
+}
+
+/// The factorization as a matrix with a row for each prime and its exponent: 12 is [2, 2; 3, 1]
+///
+fn factorization_matrix(n: &BigInt) -> anyhow::Result<Number> {
+    let factors = factorize(n)?;
+    let rows = factors.len();
+    let data = factors
+        .into_iter()
+        .flat_map(|(p, e)| [Number::NaturalNumber(p), Number::NaturalNumber(BigInt::from(e))])
+        .collect();
+    Ok(Number::Matrix(Matrix::new(rows, 2, data).expect("Two columns")))
+}
+
+/// The positive divisors of a positive number, in ascending order
+///
+fn divisors(n: &BigInt) -> anyhow::Result<Vec<BigInt>> {
+    let factors = factorize(n)?;
+    let count = factors.iter().try_fold(1u64, |acc, (_, e)| acc.checked_mul(u64::from(*e) + 1));
+    if count.map_or(true, |c| c > MAX_DIVISORS) {
+        return Err(anyhow!(DIVISORS_ERR));
+    }
+    let mut res = vec![BigInt::one()];
+    for (p, e) in &factors {
+        let previous = res.len();
+        let mut power = BigInt::one();
+        for _ in 0..*e {
+            power *= p;
+            for i in 0..previous {
+                res.push(&res[i] * &power);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// The inverse of a modulo m, if they are coprime
+///
+pub(crate) fn mod_inverse(a: &BigInt, m: &BigInt) -> Option<BigInt> {
+    let m = m.abs();
+    let egcd = a.extended_gcd(&m);
+    egcd.gcd.is_one().then(|| egcd.x.mod_floor(&m))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn natural(n: i64) -> Number {
+        Number::NaturalNumber(BigInt::from(n))
+    }
+
+    fn big(s: &str) -> BigInt {
+        BigInt::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_is_prime() {
+        let primes: Vec<i64> = (0..50).filter(|&n| is_prime(&BigInt::from(n))).collect();
+        assert_eq!(primes, vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47]);
+        assert!(is_prime(&big("170141183460469231731687303715884105727"))); // 2^127 - 1
+        assert!(!is_prime(&big("3825123056546413051"))); // a strong pseudoprime to the bases up to 23
+        assert!(!is_prime(&BigInt::from(-7)));
+        assert_eq!(next_prime(&BigInt::from(13)), BigInt::from(17));
+        assert_eq!(next_prime(&BigInt::from(-5)), BigInt::from(2));
+    }
+
+    #[test]
+    fn test_factorize() {
+        let pairs = |v: &[(i64, u32)]| v.iter().map(|&(p, e)| (BigInt::from(p), e)).collect::<Vec<_>>();
+        assert_eq!(factorize(&BigInt::from(360)).unwrap(), pairs(&[(2, 3), (3, 2), (5, 1)]));
+        assert_eq!(factorize(&BigInt::from(-12)).unwrap(), pairs(&[(-1, 1), (2, 2), (3, 1)]));
+        assert_eq!(factorize(&BigInt::one()).unwrap(), pairs(&[]));
+        assert_eq!(factorize(&BigInt::from(-1)).unwrap(), pairs(&[(-1, 1)]));
+        // 2^64 + 1 = 274177 * 67280421310721
+        assert_eq!(
+            factorize(&big("18446744073709551617")).unwrap(),
+            vec![(big("274177"), 1), (big("67280421310721"), 1)]
+        );
+        assert_eq!(
+            factorize(&big("1000000016000000063")).unwrap(),
+            vec![(big("1000000007"), 1), (big("1000000009"), 1)]
+        );
+    }
+
+    #[test]
+    fn test_function() {
+        let eval = |fun, n: i64| function(fun, &BigInt::from(n)).unwrap();
+        assert_eq!(eval(MathFunction::Phi, 36), natural(12));
+        assert_eq!(eval(MathFunction::Phi, 1), natural(1));
+        assert_eq!(eval(MathFunction::Isqrt, 99), natural(9));
+        assert_eq!(eval(MathFunction::Digits, -12345), natural(5));
+        assert_eq!(eval(MathFunction::Divisors, 12).to_string(), "[1, 2, 3, 4, 6, 12]");
+        assert_eq!(eval(MathFunction::Factor, 360).to_string(), "[2, 3; 3, 2; 5, 1]");
+        assert!(function(MathFunction::Factor, &BigInt::zero()).is_err());
+        assert!(function(MathFunction::Isqrt, &BigInt::from(-1)).is_err());
+        assert_eq!(mod_inverse(&BigInt::from(3), &BigInt::from(11)), Some(BigInt::from(4)));
+        assert_eq!(mod_inverse(&BigInt::from(-3), &BigInt::from(11)), Some(BigInt::from(7)));
+        assert_eq!(mod_inverse(&BigInt::from(6), &BigInt::from(9)), None);
+    }
+}
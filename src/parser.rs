@@ -1,4 +1,9 @@
-use crate::token::{self, Operator, Token};
+use std::ops::Range;
+
+use crate::{
+    token::{self, Bracket, Number, Operator, Token},
+    units::{Quantity, Unit},
+};
 
 use log::debug;
 use once_cell::sync::Lazy;
@@ -10,8 +15,12 @@ use regex::Regex;
 #[derive(Debug)]
 pub struct Parser;
 
+/// The byte range that a [`Token`] occupies in the source expression
+///
+pub type Span = Range<usize>;
+
 static EXPRESSION_REGEX: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"(\d+\.?\d*|\.\d+|[-+*/^()=×÷!]|[a-zA-Z_][a-zA-Z0-9_]*|)")
+    Lazy::new(|| Regex::new(r"(\d+\.?\d*|\.\d+|\.[*/^]|\+-|!!|[-+*/^()\[\],;=×÷!±°%]|[a-zA-Z_][a-zA-Z0-9_]*|)")
     .expect("Should compile regex"));
 
 impl Parser {
@@ -19,39 +28,163 @@ impl Parser {
     /// the help of [`EXPRESSION_REGEX`] and then wraps in tokens the &str chunks
     ///
     pub fn parse(expr: &str) -> Vec<Token> {
-        let vex: Vec<Token<'_>> = EXPRESSION_REGEX
-            .find_iter(expr)
-            .map(|m| m.as_str())
-            .filter_map(|s| Token::tokenize(s))
+        let vex: Vec<Token<'_>> = Self::attach_units(&Self::tokenize_spanned(expr))
+            .into_iter()
+            .map(|(t, _)| t)
             .collect();
 
         Self::mod_unary_operators(&vex)
     }
 
+    /// Like [`Parser::parse`], but every [`Token`] keeps the [`Span`]
+    /// of the chunk of the expression it has been built from
+    ///
+    #[must_use]
+    pub fn parse_spanned(expr: &str) -> Vec<(Token<'_>, Span)> {
+        let (vex, spans): (Vec<Token<'_>>, Vec<Span>) =
+            Self::attach_units(&Self::tokenize_spanned(expr)).into_iter().unzip();
+
+        Self::unary_rewrite(&vex)
+            .into_iter()
+            .zip(spans)
+            .filter_map(|(t, span)| t.map(|t| (t, span)))
+            .collect()
+    }
+
+    fn tokenize_spanned(expr: &str) -> Vec<(Token<'_>, Span)> {
+        EXPRESSION_REGEX
+            .find_iter(expr)
+            .filter_map(|m| Token::tokenize(m.as_str()).map(|t| (t, m.range())))
+            .collect()
+    }
+
+    /// Tags with a unit of measure every number followed by a unit ('5 km/h'), multiplies by it
+    /// every variable or constant followed by a unit ('x m' is '(x * 1 m)'), and turns
+    /// the unit on the right of [`Operator::To`] into a quantity ('3 ft to m')
+    ///
+    fn attach_units<'a>(tokens: &[(Token<'a>, Span)]) -> Vec<(Token<'a>, Span)> {
+        let mut out: Vec<(Token<'_>, Span)> = Vec::with_capacity(tokens.len());
+        let mut i = 0;
+
+        while i < tokens.len() {
+            let (t, span) = tokens[i].clone();
+            i += 1;
+
+            match (t, Self::unit_expression(&tokens[i..])) {
+                (Token::Operand(n), Some((unit, len))) => {
+                    let q = Quantity::new(n.into(), unit);
+                    out.push((Token::Operand(Number::from_quantity(q)), span.start..tokens[i + len - 1].1.end));
+                    i += len;
+                }
+                (t @ Token::Variable(_), Some((unit, len))) => {
+                    let whole = span.start..tokens[i + len - 1].1.end;
+                    out.push((Token::Bracket(Bracket::Open), whole.clone()));
+                    out.push((t, span));
+                    out.push((Token::Operator(Operator::Mul), whole.clone()));
+                    out.push((Token::Operand(Number::Quantity(Quantity::new(1., unit))), tokens[i].1.start..whole.end));
+                    out.push((Token::Bracket(Bracket::Close), whole));
+                    i += len;
+                }
+                (t @ Token::Operator(Operator::To), Some((unit, len))) => {
+                    let unit_span = tokens[i].1.start..tokens[i + len - 1].1.end;
+                    out.push((t, span));
+                    out.push((Token::Operand(Number::Quantity(Quantity::new(1., unit))), unit_span));
+                    i += len;
+                }
+                (t, _) => out.push((t, span)),
+            }
+        }
+        out
+    }
+
+    /// Reads a product of units at the beginning of the tokens, such as 'kg*m/s^2',
+    /// giving back the [Unit] and the number of tokens it spans
+    ///
+    fn unit_expression(tokens: &[(Token<'_>, Span)]) -> Option<(Unit, usize)> {
+        let is_unit = |i: usize| Self::unit_symbol(tokens, i).and_then(Unit::parse).is_some();
+
+        let mut unit = Unit::default();
+        let mut sign = 1;
+        let mut i = 0;
+
+        while let Some(symbol) = Self::unit_symbol(tokens, i) {
+            let Some(factor) = Unit::parse(symbol) else { break };
+            i += 1;
+
+            let mut exponent = 1;
+            if let Some((Token::Operator(Operator::Pow), _)) = tokens.get(i) {
+                match (tokens.get(i + 1), tokens.get(i + 2)) {
+                    (Some((Token::Operand(Number::NaturalNumber(n)), _)), _) => {
+                        exponent = i32::try_from(n).ok()?;
+                        i += 2;
+                    }
+                    (Some((Token::Operator(Operator::Sub), _)), Some((Token::Operand(Number::NaturalNumber(n)), _))) => {
+                        exponent = -i32::try_from(n).ok()?;
+                        i += 3;
+                    }
+                    _ => (),
+                }
+            }
+            unit = unit * factor.powi(sign * exponent);
+
+            match tokens.get(i) {
+                Some((Token::Operator(Operator::Mul), _)) if is_unit(i + 1) => sign = 1,
+                Some((Token::Operator(Operator::Div), _)) if is_unit(i + 1) => sign = -1,
+                _ => break,
+            }
+            i += 1;
+        }
+        (i > 0).then_some((unit, i))
+    }
+
+    /// The symbol of the token at a position, that may be a unit: a variable, or a function
+    /// that isn't called, as the minutes in '5 min'
+    ///
+    fn unit_symbol<'a>(tokens: &[(Token<'a>, Span)], i: usize) -> Option<&'a str> {
+        match (tokens.get(i), tokens.get(i + 1)) {
+            (Some((Token::Variable(symbol), _)), _) => Some(symbol),
+            (Some((Token::Function(_), _)), Some((Token::Bracket(Bracket::Open), _))) => None,
+            (Some((Token::Function(fun), _)), _) => Some(fun.name()),
+            _ => None,
+        }
+    }
+
     /// Finds out all the unary operators that are present in the expression
     ///
     fn mod_unary_operators<'a>(v: &[Token<'a>]) -> Vec<Token<'a>> {
-        let mut mod_vec: Vec<Token> = Vec::new();
+        Self::unary_rewrite(v).into_iter().flatten().collect()
+    }
+
+    /// Rewrites every token of the expression, one to one: an unary - becomes
+    /// [`Operator::Une`] and an unary + is dropped (`None`)
+    ///
+    fn unary_rewrite<'a>(v: &[Token<'a>]) -> Vec<Option<Token<'a>>> {
+        let mut mod_vec: Vec<Option<Token>> = Vec::new();
         let mut expect_operand_next = true;
 
         for token in v {
             debug!("{}", token);
 
             match &token {
-                Token::Operand(_) | Token::Variable(_) | Token::Operator(Operator::Fac) => {
+                Token::Operand(_)
+                | Token::Variable(_)
+                | Token::Bracket(Bracket::Close | Bracket::MatrixClose)
+                | Token::Operator(Operator::Fac | Operator::DoubleFac | Operator::Degree | Operator::Percent) => {
                     expect_operand_next = false;
                 }
+                Token::Separator(_) | Token::Bracket(Bracket::Open | Bracket::MatrixOpen) => expect_operand_next = true,
                 Token::Operator(o) => {
                     if expect_operand_next {
                         debug!("-> Unary operator detected");
                         match o {
                             token::Operator::Add => {
                                 // an unary + can be simply ignored.
+                                mod_vec.push(None);
                                 continue;
                             }
                             token::Operator::Sub => {
                                 // an unary - is a special right-associative op with the highest precedence
-                                mod_vec.push(token::Token::Operator(token::Operator::Une));
+                                mod_vec.push(Some(token::Token::Operator(token::Operator::Une)));
                                 continue;
                             }
                             _ => (),
@@ -61,7 +194,7 @@ impl Parser {
                 }
                 _ => (),
             }
-            mod_vec.push(token.clone());
+            mod_vec.push(Some(token.clone()));
         }
         mod_vec
     }
@@ -127,4 +260,42 @@ mod tests {
         let result = Parser::mod_unary_operators(&input);
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_unary_after_call() {
+        // rand() - 1: the '-' after a closing bracket is binary
+        let tokens = Parser::parse("rand() - 1");
+        assert_eq!(tokens[3], Token::Operator(Operator::Sub));
+        assert_eq!(Parser::parse("(-1)")[1], Token::Operator(Operator::Une));
+    }
+
+        #[test]
+    fn test_parse_units() {
+        let tokens = Parser::parse_spanned("5 km/h to m/s^-1");
+        let km_h = Unit::parse("km").unwrap() * Unit::parse("h").unwrap().powi(-1);
+
+        assert_eq!(tokens[0], (Token::Operand(Number::Quantity(Quantity::new(5., km_h))), 0..6));
+        assert_eq!(tokens[1], (Token::Operator(Operator::To), 7..9));
+        assert_eq!(tokens[2].1, 10..16);
+        assert_eq!(Parser::parse("5 x").len(), 2);
+
+        // 'x m' is '(x * 1 m)'
+        let metre = Number::Quantity(Quantity::new(1., Unit::parse("m").unwrap()));
+        assert_eq!(Parser::parse_spanned("x m").into_iter().map(|(t, _)| t).collect::<Vec<_>>(), vec![
+            Token::Bracket(Bracket::Open),
+            Token::Variable("x"),
+            Token::Operator(Operator::Mul),
+            Token::Operand(metre),
+            Token::Bracket(Bracket::Close),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_spanned() {
+        let spans: Vec<Span> = Parser::parse_spanned("-x + 10")
+            .into_iter()
+            .map(|(_, span)| span)
+            .collect();
+        assert_eq!(spans, vec![0..1, 1..2, 3..4, 5..7]);
+    }
 }
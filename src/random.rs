@@ -0,0 +1,166 @@
+use std::{
+    f64::consts::TAU,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use num::{BigInt, BigUint, One, Signed, Zero};
+
+use crate::{
+    error::MathError,
+    integer,
+    token::{MathFunction, Number},
+};
+
+/// A xoshiro256** generator of pseudo-random numbers. It's owned by a [`Session`](crate::session::Session),
+/// and seeded from the clock unless [`Session::seed`](crate::session::Session::seed) is called.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Generator {
+    state: [u64; 4],
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Generator::seeded(now.as_secs() << 30 ^ u64::from(now.subsec_nanos()))
+    }
+}
+
+impl Generator {
+    /// A generator whose state is expanded from the seed by `SplitMix64`, so that close seeds
+    /// give unrelated sequences
+    ///
+    pub(crate) fn seeded(seed: u64) -> Generator {
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Generator { state: [next(), next(), next(), next()] }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let res = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        res
+    }
+
+    /// A float uniformly distributed in [0, 1), with 53 random bits
+    ///
+    fn uniform(&mut self) -> f64 {
+        #[allow(clippy::cast_precision_loss)] // 53 bits fit in the mantissa
+        let bits = (self.next_u64() >> 11) as f64;
+        bits / 9_007_199_254_740_992. // 2^53
+    }
+
+    /// A standard normal deviate, by the method of Box and Muller
+    ///
+    fn normal(&mut self) -> f64 {
+        let radius = (-2. * (1. - self.uniform()).ln()).sqrt();
+        radius * (TAU * self.uniform()).cos()
+    }
+
+    /// An integer uniformly distributed in [0, n), drawing as many bits as n has and
+    /// rejecting the draws beyond it
+    ///
+    fn below(&mut self, n: &BigUint) -> BigUint {
+        let bits = n.bits();
+        loop {
+            let mut digits: Vec<u64> = (0..bits.div_ceil(64)).map(|_| self.next_u64()).collect();
+            if bits % 64 != 0 {
+                if let Some(top) = digits.last_mut() {
+                    *top >>= 64 - bits % 64;
+                }
+            }
+            let draw = digits.iter().rev().fold(BigUint::zero(), |acc, d| (acc << 64u32) | BigUint::from(*d));
+            if draw < *n {
+                return draw;
+            }
+        }
+    }
+}
+
+/// Draws a random number: `rand()` is uniform in [0, 1), `randint(a, b)` is an integer
+/// between a and b included, of any size, and `randn()` is a standard normal deviate
+///
+pub(crate) fn function(fun: MathFunction, args: &[Number], generator: &mut Generator) -> anyhow::Result<Number> {
+    match (fun, args) {
+        (MathFunction::Rand, []) => Ok(Number::DecimalNumber(generator.uniform())),
+        (MathFunction::RandN, []) => Ok(Number::DecimalNumber(generator.normal())),
+        (MathFunction::RandInt, [a, b]) => {
+            let low = integer::to_integer(a).ok_or_else(|| MathError::domain(fun, a))?;
+            let high = integer::to_integer(b).ok_or_else(|| MathError::domain(fun, b))?;
+            let span = &high - &low + BigInt::one();
+            if !span.is_positive() {
+                return Err(MathError::domain(fun, b).into());
+            }
+            Ok(Number::NaturalNumber(low + BigInt::from(generator.below(span.magnitude()))))
+        }
+        (MathFunction::RandInt, _) => Err(anyhow!("Runtime error: Function '{fun}' needs two arguments.")),
+        _ => Err(anyhow!("Runtime error: Function '{fun}' takes no arguments.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn natural(n: i64) -> Number {
+        Number::NaturalNumber(BigInt::from(n))
+    }
+
+    #[test]
+    fn test_generator() {
+        // the first output of xoshiro256** from the state [1, 2, 3, 4]
+        let mut generator = Generator { state: [1, 2, 3, 4] };
+        assert_eq!(generator.next_u64(), 11_520);
+        assert_eq!(Generator::seeded(7), Generator::seeded(7));
+        assert_ne!(Generator::seeded(7), Generator::seeded(8));
+
+        let mut generator = Generator::seeded(42);
+        let draws: Vec<f64> = (0..10_000).map(|_| generator.uniform()).collect();
+        assert!(draws.iter().all(|x| (0. ..1.).contains(x)));
+        let mean = draws.iter().sum::<f64>() / 10_000.;
+        assert!((mean - 0.5).abs() < 0.01, "{mean}");
+
+        let normals: Vec<f64> = (0..10_000).map(|_| generator.normal()).collect();
+        let mean = normals.iter().sum::<f64>() / 10_000.;
+        let variance = normals.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / 9_999.;
+        assert!(mean.abs() < 0.05 && (variance - 1.).abs() < 0.05, "{mean} {variance}");
+    }
+
+    #[test]
+    fn test_function() {
+        let mut generator = Generator::seeded(1);
+        let mut seen = [false; 6];
+        for _ in 0..200 {
+            let Number::NaturalNumber(n) = function(MathFunction::RandInt, &[natural(-2), natural(3)], &mut generator).unwrap() else {
+                panic!("Not an integer");
+            };
+            let index = usize::try_from(n + 2).unwrap();
+            seen[index] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+
+        let huge = Number::NaturalNumber(BigInt::one() << 200);
+        let n = function(MathFunction::RandInt, &[natural(0), huge.clone()], &mut generator).unwrap();
+        assert!(n >= natural(0) && n <= huge);
+
+        assert_eq!(function(MathFunction::RandInt, &[natural(5), natural(5)], &mut generator).unwrap(), natural(5));
+        assert!(function(MathFunction::RandInt, &[natural(5), natural(4)], &mut generator).is_err());
+        assert!(function(MathFunction::RandInt, &[Number::DecimalNumber(0.5), natural(4)], &mut generator).is_err());
+        assert!(function(MathFunction::RandInt, &[natural(1)], &mut generator).is_err());
+        assert!(function(MathFunction::Rand, &[natural(1)], &mut generator).is_err());
+    }
+}
@@ -1,15 +1,34 @@
-use std::{collections::{HashMap, VecDeque}, rc::Rc, cell::RefCell, fmt::Display};
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, rc::Rc, cell::RefCell, fmt::Display, time::Instant};
 use crate::{
-    parser::Parser,
-    token::{self, MathFunction, Number, Operator, Token},
+    calculus,
+    decimal,
+    distribution,
+    error::MathError,
+    finance,
+    integer,
+    interval,
+    parser::{Parser, Span},
+    session::{AngleMode, FloatPolicy, Limits, NumericMode, Settings, CONSTANTS},
+    matrix::{self, Matrix},
+    random,
+    number_theory,
+    token::{self, Bracket, MathFunction, Number, Operator, Token},
+    uncertainty,
+    special,
+    stats,
+    symbolic::Expr,
+    units,
 };
 use anyhow::anyhow;
+use bigdecimal::Context;
 use log::debug;
-use num::{BigInt, One, Zero};
+use num::{complex::Complex64, BigInt, BigRational, FromPrimitive, One, Signed, ToPrimitive, Zero};
 
 static MALFORMED_ERR: &str = "Runtime Error: The mathematical expression is malformed.";
 static DIVISION_ZERO_ERR: &str = "Runtime error: Divide by zero.";
 static NO_VARIABLE_ERR: &str = "Runtime error: No variable has been defined for assignent.";
+static FACTORIAL_ERR: &str = "Runtime error: The factorial is too large.";
+static NO_EXPRESSION_ERR: &str = "Runtime error: The expression of the function needs a plain variable.";
 
 /// The main [`RpnResolver`] contains the core logic of Yarer
 /// for parsing and evaluating a math expression.
@@ -18,8 +37,97 @@ static NO_VARIABLE_ERR: &str = "Runtime error: No variable has been defined for
 /// a heap of local variables borrowed from a [`Session`]
 ///
 pub struct RpnResolver<'a> {
-    rpn_expr: VecDeque<Token<'a>>,
+    rpn_expr: RpnExpr<'a>,
+    /// the number of tokens of the source expression
+    token_count: usize,
+    /// the deepest nesting of brackets of the source expression
+    nesting_depth: usize,
     local_heap: Rc<RefCell<HashMap<String, Number>>>,
+    settings: Rc<RefCell<Settings>>,
+}
+
+/// A postfix expression, where each [`Token`] keeps its [`Span`] in the source expression
+///
+type RpnExpr<'a> = VecDeque<(Token<'a>, Span)>;
+
+/// The values of the variables bound by the [`Token::Lambda`]s being evaluated, the innermost last
+///
+type Scope<'a> = [(&'a str, Number)];
+
+/// The row lengths of a matrix literal, while the shunting-yard reads it,
+/// or the arguments of a call to a function that counts them, as a single row
+///
+#[derive(Default)]
+struct MatrixRows {
+    rows: Vec<usize>,
+    elements: usize,
+    /// whether an element has begun since the last separator
+    pending: bool,
+    /// where each argument of a call begins in the postfix expression
+    starts: Vec<usize>,
+}
+
+impl MatrixRows {
+    fn end_element(&mut self) {
+        if self.pending {
+            self.elements += 1;
+            self.pending = false;
+        }
+    }
+
+    fn end_row(&mut self) {
+        self.end_element();
+        self.rows.push(std::mem::take(&mut self.elements));
+    }
+}
+
+/// The resources spent by an evaluation so far, against the [`Limits`] of the session
+///
+struct Budget {
+    limits: Limits,
+    steps: usize,
+    start: Instant,
+}
+
+impl Budget {
+    fn new(limits: Limits) -> Budget {
+        Budget { limits, steps: 0, start: Instant::now() }
+    }
+
+    /// Takes one more evaluation step, failing if it's one too many or if the time is up
+    ///
+    fn step(&mut self) -> anyhow::Result<()> {
+        self.steps += 1;
+        Budget::check("number of evaluation steps", self.steps, self.limits.max_steps)?;
+        match self.limits.max_time {
+            Some(max) if self.start.elapsed() > max => {
+                Err(MathError::LimitExceeded { limit: "evaluation time", max: format!("{max:?}") }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check(limit: &'static str, value: usize, max: Option<usize>) -> anyhow::Result<()> {
+        match max {
+            Some(max) if value > max => Err(MathError::LimitExceeded { limit, max: max.to_string() }.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The names referenced by an expression, as reported by [`RpnResolver::symbols`].
+///
+/// Every name is mapped to the [`Span`]s of its occurrences in the source expression.
+///
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Symbols {
+    /// Variables read before the expression assigns them: their value must come from the session.
+    /// Built-in constants such as `pi` and `e` are not reported.
+    pub free_variables: BTreeMap<String, Vec<Span>>,
+    /// Variables on the left side of an assignment ('x = 1')
+    pub assigned_variables: BTreeMap<String, Vec<Span>>,
+    /// Functions called by the expression
+    pub functions: BTreeMap<String, Vec<Span>>,
 }
 
 impl RpnResolver<'_> {
@@ -30,131 +138,977 @@ impl RpnResolver<'_> {
         exp: &'a str,
         borrowed_heap: Rc<RefCell<HashMap<String, Number>>>,
     ) -> RpnResolver<'a> {
-        let tokenised_expr: Vec<Token<'a>> = Parser::parse(exp);
+        let tokenised_expr: Vec<(Token<'a>, Span)> = Parser::parse_spanned(exp);
         let (rpn_expr, local_heap) =
             RpnResolver::reverse_polish_notation(&tokenised_expr, borrowed_heap);
 
+        let mut depth: usize = 0;
+        let mut nesting_depth = 0;
+        for (t, _) in &tokenised_expr {
+            match t {
+                Token::Bracket(Bracket::Open | Bracket::MatrixOpen) => depth += 1,
+                Token::Bracket(Bracket::Close | Bracket::MatrixClose) => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            nesting_depth = nesting_depth.max(depth);
+        }
+
         RpnResolver {
             rpn_expr,
+            token_count: tokenised_expr.len(),
+            nesting_depth,
             local_heap,
+            settings: Rc::default(),
         }
     }
 
+    /// Shares the evaluation settings of a [`Session`] with this [`RpnResolver`]
+    ///
+    pub(crate) fn with_settings(mut self, settings: Rc<RefCell<Settings>>) -> Self {
+        self.settings = settings;
+        self
+    }
+
     /// This method evaluates the rpn expression stack
     ///
     pub fn resolve(&mut self) -> anyhow::Result<Number> {
+        let settings = self.settings.borrow().clone();
+
+        let mut budget = Budget::new(settings.limits);
+        Budget::check("number of tokens", self.token_count, settings.limits.max_tokens)?;
+        Budget::check("nesting depth", self.nesting_depth, settings.limits.max_depth)?;
+
+        self.evaluate(&self.rpn_expr, &[], &settings, &mut budget)
+    }
+
+    /// Evaluates a rpn expression, the whole one or the body of a [`Token::Lambda`],
+    /// where the variables of the scope hide the ones of the heap
+    ///
+    fn evaluate(&self, rpn_expr: &RpnExpr, scope: &Scope, settings: &Settings, budget: &mut Budget) -> anyhow::Result<Number> {
 
         let zero: Number = Number::NaturalNumber(Zero::zero());
         let minus_one: Number = Number::NaturalNumber(BigInt::from(-1));
       
         let mut result_stack: VecDeque<Number> = VecDeque::new();
 
-        let mut last_var_ref: Option<&str> = None;
+        let assignments = Self::assignment_targets(rpn_expr);
+        let decimal_context = settings.numeric_mode.decimal_context();
+        let ctx = decimal_context.as_ref();
+        let tolerance = settings.interval_tolerance;
 
-        for t in &self.rpn_expr {
+        for (i, (t, _)) in rpn_expr.iter().enumerate() {
+            budget.step()?;
             match t {
                 Token::Operand(n) => {
-                    result_stack.push_back(n.clone());
+                    result_stack.push_back(Self::in_mode(n.clone(), ctx));
                 }
                 Token::Operator(op) => {
                     let right_value: Number = result_stack
                         .pop_back()
                         .ok_or_else(|| anyhow!("{} {}", MALFORMED_ERR, "Invalid Right Operand."))?;
 
-                    let mut left_value = if op != &Operator::Une && op != &Operator::Fac {
+                    let left_value = if matches!(op, Operator::Une | Operator::Fac | Operator::DoubleFac | Operator::Degree | Operator::Percent) {
+                        zero.clone()
+                    } else {
                         result_stack
                             .pop_back()
                             .ok_or_else(|| anyhow!("{} {}", MALFORMED_ERR, "Invalid Left Operand."))?
-                    } else {
-                        zero.clone()
                     };
 
                     match op {
-                        Operator::Add => result_stack.push_back(left_value + right_value),
-                        Operator::Sub => result_stack.push_back(left_value - right_value),
-                        Operator::Mul => result_stack.push_back(left_value * right_value),
-                        Operator::Div => {
-                            if right_value == zero {
-                                return Err(anyhow!(DIVISION_ZERO_ERR));
-                            }
-                            left_value = Number::DecimalNumber(left_value.into());
-                            result_stack.push_back(left_value / right_value);
-                        }
-                        Operator::Pow => {
-                            if right_value < zero {
-                                if left_value == zero {
-                                    return Err(anyhow!(DIVISION_ZERO_ERR));
-                                }
-                                left_value = Number::DecimalNumber(left_value.into());
-                            }
-                            result_stack.push_back(left_value ^ right_value);
-                        }
                         Operator::Eql => {
-                            if let Some(var) = last_var_ref {
+                            if let Some(Token::Variable(var)) = assignments.get(&i).map(|&v| &rpn_expr[v].0) {
                                 self.local_heap.borrow_mut()
-                                    .insert(var.to_string(), right_value.clone());
+                                    .insert((*var).to_string(), right_value.clone());
                                 
                                 result_stack.push_back(right_value);
                             } else {
                                 return Err(anyhow!(NO_VARIABLE_ERR));
                             }
                         }
-                        Operator::Fac => {
-                            // factorial. Only for natural numbers
-                            let v = BigInt::from(right_value);
-                            if v.partial_cmp(&Zero::zero()) == Some(std::cmp::Ordering::Less) {
-                                eprintln!("Warning: Factorial of a Negative or Decimal number has not been yet implemented.");
-                            }
-                            let res = Self::factorial_helper(v);
-                            result_stack.push_back(Number::NaturalNumber(res));
+                        Operator::Fac | Operator::DoubleFac => {
+                            let res = Self::factorial(*op, right_value, &settings.limits)?;
+                            Self::check_finite(op, &res, settings.float_policy)?;
+                            result_stack.push_back(res);
                         }
+                        Operator::Degree => result_stack.push_back(Self::degrees(right_value, settings)?),
+                        Operator::Percent => result_stack.push_back(Self::percent(right_value, settings)?),
                         Operator::Une => {
                             //# unary neg
                             result_stack.push_back(right_value * minus_one.clone());
                         }
+                        _ => {
+                            let res = Self::operate(*op, left_value, right_value, settings)?;
+                            Self::check_finite(op, &res, settings.float_policy)?;
+                            result_stack.push_back(res);
+                        }
                     }
                 }
-                Token::Variable(v) => {
-                    let _ = last_var_ref.insert(*v);
-                    debug!("Heap {:?}", self.local_heap);
-                    let heap = self.local_heap.borrow_mut();
-                    let n = heap
-                        .get(*v)
-                        .unwrap_or(&Number::DecimalNumber(0.));
-                    result_stack.push_back(n.clone());
-                }
+                Token::Variable(v) => result_stack.push_back(self.variable(v, scope, ctx, tolerance)),
                 Token::Function(fun) => {
                     let value: Number = result_stack
                         .pop_back()
                         .ok_or(anyhow!("{} {}", MALFORMED_ERR, "Wrong use of function"))?;
 
-                    let res = match fun {
-                        MathFunction::Sin => f64::sin(value.into()),
-                        MathFunction::Cos => f64::cos(value.into()),
-                        MathFunction::Tan => f64::tan(value.into()),
-                        MathFunction::ASin => f64::asin(value.into()),
-                        MathFunction::ACos => f64::acos(value.into()),
-                        MathFunction::ATan => f64::atan(value.into()),
-                        MathFunction::Ln => f64::ln(value.into()),
-                        MathFunction::Log => f64::log10(value.into()),
-                        MathFunction::Abs => f64::abs(value.into()),
-                        MathFunction::Max => {
-                            let value2: Number = result_stack.pop_back().unwrap();
-                            f64::max(value.into(), value2.into())
-                        }
-                        MathFunction::Min => {
-                            let value2: Number = result_stack.pop_back().unwrap();
-                            f64::min(value.into(), value2.into())
-                        }
-                        MathFunction::Sqrt => f64::sqrt(value.into()),
-                        MathFunction::None => return Err(anyhow!("This should never happen!")),
+                    let res = Self::apply_function(*fun, value, &mut result_stack, settings)?;
+                    Self::check_finite(fun, &res, settings.float_policy)?;
+                    result_stack.push_back(res);
+                }
+                Token::Lambda(..) => (),
+                Token::Call(fun, count) if fun.lambda_arguments().is_some() => {
+                    let Some((Token::Lambda(var, body), _)) = rpn_expr.get(i.wrapping_sub(1)) else {
+                        return Err(anyhow!(NO_EXPRESSION_ERR));
                     };
-                    result_stack.push_back(Number::DecimalNumber(res));
+                    let res = self.call_lazy(*fun, *count, (var, body), &mut result_stack, scope, settings, budget)?;
+                    result_stack.push_back(res);
+                }
+                Token::Call(fun, count) => {
+                    let res = Self::call(*fun, *count, &mut result_stack, settings)?;
+                    result_stack.push_back(res);
+                }
+                Token::MatrixLiteral(rows) => {
+                    let len: usize = rows.iter().sum();
+                    if result_stack.len() < len {
+                        return Err(anyhow!("{} {}", MALFORMED_ERR, "Invalid matrix."));
+                    }
+                    let values = result_stack.split_off(result_stack.len() - len);
+                    if tolerance.is_some() && rows[..] == [2] {
+                        // '[1.9, 2.1]' is an interval in interval mode
+                        result_stack.push_back(interval::from_bounds(&values[0], &values[1])?);
+                    } else {
+                        result_stack.push_back(matrix::from_rows(values.into(), rows)?);
+                    }
                 }
                 _ => return Err(anyhow!("{} Internal Error at line: {}.", MALFORMED_ERR, line!())),
             }
+            if let Some(n) = result_stack.back() {
+                Self::check_size(n, &settings.limits)?;
+            }
+        }
+        // a value left over, as in '(1, 2)', is a malformed expression too
+        match (result_stack.pop_back(), result_stack.is_empty()) {
+            (Some(n), true) => Ok(n),
+            _ => Err(anyhow!("{}", MALFORMED_ERR)),
+        }
+    }
+
+    /// The value of a variable: the innermost one bound in the scope, or else the one in the heap
+    ///
+    fn variable(&self, v: &str, scope: &Scope, ctx: Option<&Context>, tolerance: Option<f64>) -> Number {
+        if let Some((_, n)) = scope.iter().rev().find(|(var, _)| *var == v) {
+            return n.clone();
+        }
+        debug!("Heap {:?}", self.local_heap);
+        let heap = self.local_heap.borrow_mut();
+        let n = heap
+            .get(v)
+            .unwrap_or(&Number::DecimalNumber(0.));
+        let n = Self::constant_in_mode(v, n, ctx).unwrap_or_else(|| Self::in_mode(n.clone(), ctx));
+        match tolerance {
+            Some(t) => Self::as_interval(v, n, t),
+            None => n,
+        }
+    }
+
+    /// Evaluates a [`MathFunction`] of a [`Token::Lambda`], given the last `count` values of the stack,
+    /// by binding its variable to the real values the function chooses, or to the integers of a range
+    /// for the comprehensions
+    ///
+    #[allow(clippy::too_many_arguments)] // the state of the evaluation, threaded into the lambda
+    fn call_lazy(
+        &self,
+        fun: MathFunction,
+        count: usize,
+        (var, body): (&str, &RpnExpr),
+        result_stack: &mut VecDeque<Number>,
+        scope: &Scope,
+        settings: &Settings,
+        budget: &mut Budget,
+    ) -> anyhow::Result<Number> {
+        if result_stack.len() < count {
+            return Err(anyhow!("{} {}", MALFORMED_ERR, "Wrong use of function"));
+        }
+        let values = result_stack.split_off(result_stack.len() - count);
+        if fun == MathFunction::Diff {
+            // there's no derivative, or it depends on the angle mode, or it would have replaced the call
+            let body = Self::derivatives_in(body, settings.angle_mode)?;
+            let derivative = Expr::from_rpn(&body)?.derivative_in(var, settings.angle_mode)?;
+            return self.evaluate(&derivative.to_rpn(&(0..0)), scope, settings, budget);
+        }
+        let mut scope = scope.to_vec();
+        scope.push((var, Number::DecimalNumber(0.)));
+        if fun.is_comprehension() {
+            return self.comprehension(fun, &values, &mut scope, body, settings, budget);
+        }
+        let args = values.iter()
+            .map(|n| f64::try_from(n).map_err(|_| MathError::domain(fun, n)))
+            .collect::<Result<Vec<f64>, MathError>>()?;
+        let ctx = settings.numeric_mode.decimal_context();
+        let mut lambda = |x: f64| {
+            if let Some((_, value)) = scope.last_mut() {
+                *value = Self::in_mode(Number::DecimalNumber(x), ctx.as_ref());
+            }
+            let n = self.evaluate(body, &scope, settings, budget)?;
+            f64::try_from(&n).map_err(|_| MathError::domain(fun, &n).into())
+        };
+        let res = Number::DecimalNumber(calculus::function(fun, &args, &mut lambda)?);
+        Self::check_finite(fun, &res, settings.float_policy)?;
+        Ok(res)
+    }
+
+    /// Evaluates 'sumover(i, a, b, expr)' or 'prodover(i, a, b, expr)', binding the last variable of the scope
+    /// to each integer from a to b: the result is exact as long as the terms are
+    ///
+    fn comprehension(
+        &self,
+        fun: MathFunction,
+        bounds: &VecDeque<Number>,
+        scope: &mut [(&str, Number)],
+        body: &RpnExpr,
+        settings: &Settings,
+        budget: &mut Budget,
+    ) -> anyhow::Result<Number> {
+        let (Some(a), Some(b), 2) = (bounds.front(), bounds.back(), bounds.len()) else {
+            return Err(anyhow!("Runtime error: Function '{}' takes a variable, the two ends of its range, and an expression.", fun.name()));
+        };
+        let first = integer::to_integer(a).ok_or_else(|| MathError::domain(fun, a))?;
+        let last = integer::to_integer(b).ok_or_else(|| MathError::domain(fun, b))?;
+        let (op, mut res) = match fun {
+            MathFunction::ProdOver => (Operator::Mul, Number::NaturalNumber(BigInt::one())),
+            _ => (Operator::Add, Number::NaturalNumber(BigInt::zero())),
+        };
+        let ctx = settings.numeric_mode.decimal_context();
+        let mut i = first;
+        while i <= last {
+            if let Some((_, value)) = scope.last_mut() {
+                *value = Self::in_mode(Number::NaturalNumber(i.clone()), ctx.as_ref());
+            }
+            let term = self.evaluate(body, scope, settings, budget)?;
+            res = Self::operate(op, res, term, settings)?;
+            Self::check_size(&res, &settings.limits)?;
+            i += 1;
+        }
+        Self::check_finite(fun, &res, settings.float_policy)?;
+        Ok(res)
+    }
+
+    /// Evaluates a [`MathFunction`] that counts its arguments over the last `count` values of the stack
+    ///
+    fn call(
+        fun: MathFunction,
+        count: usize,
+        result_stack: &mut VecDeque<Number>,
+        settings: &Settings,
+    ) -> anyhow::Result<Number> {
+        if result_stack.len() < count {
+            return Err(anyhow!("{} {}", MALFORMED_ERR, "Wrong use of function"));
+        }
+        if !fun.counts_arguments() && count != fun.arity() {
+            let arguments = match fun.arity() {
+                1 => "one argument".to_string(),
+                n => format!("{n} arguments"),
+            };
+            return Err(anyhow!("Runtime error: Function '{fun}' takes {arguments}, not {count}."));
+        }
+        let mut values = result_stack.split_off(result_stack.len() - count);
+        if fun.is_impure() {
+            return random::function(fun, values.make_contiguous(), &mut settings.random.borrow_mut());
+        }
+        let scalar = |op, left, right| Self::operate(op, left, right, settings);
+        let res = if finance::is_financial(fun) {
+            let unary = |fun, value| Self::apply_function(fun, value, &mut VecDeque::new(), settings);
+            finance::function(fun, values.make_contiguous(), &scalar, &unary, settings.numeric_mode.decimal_context().as_ref())?
+        } else {
+            stats::aggregate(fun, values.into(), &scalar)?
+        };
+        Self::check_finite(fun, &res, settings.float_policy)?;
+        Ok(res)
+    }
+
+    /// Evaluates a [`MathFunction`]: with the precision of the context in [`NumericMode::Decimal`],
+    /// as a complex number when the argument is complex or out of the real domain,
+    /// or as a f64 otherwise
+    ///
+    fn apply_function(
+        fun: MathFunction,
+        value: Number,
+        result_stack: &mut VecDeque<Number>,
+        settings: &Settings,
+    ) -> anyhow::Result<Number> {
+        let mode = settings.numeric_mode;
+        let ctx = mode.decimal_context();
+        let scalar = |op, left, right| Self::operate(op, left, right, settings);
+        match fun {
+            MathFunction::Det | MathFunction::Inv | MathFunction::Transpose | MathFunction::Norm => {
+                return matrix::function(fun, value, &scalar);
+            }
+            _ if fun.is_variadic() => return stats::aggregate(fun, vec![value], &scalar),
+            _ if fun.is_impure() => return random::function(fun, &[value], &mut settings.random.borrow_mut()),
+            _ if finance::is_financial(fun) => return Self::call(fun, 1, &mut VecDeque::from([value]), settings),
+            MathFunction::IsPrime
+            | MathFunction::NextPrime
+            | MathFunction::Factor
+            | MathFunction::Phi
+            | MathFunction::Divisors
+            | MathFunction::Isqrt
+            | MathFunction::Digits => {
+                let n = integer::to_integer(&value).ok_or_else(|| MathError::domain(fun, &value))?;
+                return number_theory::function(fun, &n);
+            }
+            MathFunction::Dot | MathFunction::Cross => {
+                let left = result_stack.pop_back().ok_or(anyhow!("{} {}", MALFORMED_ERR, "Wrong use of function"))?;
+                return matrix::vector_function(fun, left, value, &scalar);
+            }
+            _ if fun.arity() > 1 => {
+                let others = fun.arity() - 1;
+                if result_stack.len() < others {
+                    return Err(anyhow!("{} {}", MALFORMED_ERR, "Wrong use of function"));
+                }
+                let mut args: Vec<Number> = result_stack.split_off(result_stack.len() - others).into();
+                args.push(value);
+                return Self::apply_many(fun, &args, settings);
+            }
+            _ => (),
+        }
+        if let Number::Matrix(m) = value {
+            // any other function is applied element by element
+            let mut stack = VecDeque::new();
+            let res = m.elements().iter().map(|x| Self::apply_function(fun, x.clone(), &mut stack, settings));
+            let data = res.collect::<anyhow::Result<Vec<Number>>>()?;
+            return Ok(Number::Matrix(Matrix::new(m.rows(), m.cols(), data).expect("Same shape")));
+        }
+        if special::is_special(fun) {
+            return Self::apply_special(fun, &[value], settings);
+        }
+
+        let reciprocal = match fun {
+            MathFunction::Sec => Some(MathFunction::Cos),
+            MathFunction::Csc => Some(MathFunction::Sin),
+            MathFunction::Cot => Some(MathFunction::Tan),
+            _ => None,
+        };
+        if let Some(fun) = reciprocal {
+            let res = Self::apply_function(fun, value, result_stack, settings)?;
+            return Self::operate(Operator::Div, Number::NaturalNumber(BigInt::one()), res, settings);
+        }
+
+        match (fun, settings.angle_mode.half_turn()) {
+            (MathFunction::Deg, _) => return Self::operate(Operator::Div, value, Self::radians_per(180, settings)?, settings),
+            (MathFunction::Rad, _) => return Self::operate(Operator::Mul, value, Self::radians_per(180, settings)?, settings),
+            (
+                MathFunction::Sin | MathFunction::Cos | MathFunction::Tan | MathFunction::ASin | MathFunction::ACos | MathFunction::ATan,
+                Some(half_turn),
+            ) => return Self::apply_with_angles(fun, value, half_turn, result_stack, settings),
+            _ => (),
+        }
+
+        if let Some(res) = integer::rounding(fun, &value) {
+            return Ok(res);
         }
-        result_stack.pop_front().ok_or(anyhow!("{}", MALFORMED_ERR))
+        if let (MathFunction::Cbrt, Some(res)) = (fun, integer::root(&value, 3)) {
+            return Ok(res);
+        }
+
+        match (fun, &value) {
+            (MathFunction::IsNan, _) => return Ok(Number::NaturalNumber(BigInt::from(u8::from(value.is_nan())))),
+            (MathFunction::IsInf, _) => return Ok(Number::NaturalNumber(BigInt::from(u8::from(value.is_infinite())))),
+            (MathFunction::Re, Number::Complex(z)) => return Ok(Number::DecimalNumber(z.re)),
+            (MathFunction::Conj, Number::Complex(z)) => return Ok(Number::Complex(z.conj())),
+            (MathFunction::Im, Number::Complex(z)) => return Ok(Number::DecimalNumber(z.im)),
+            (MathFunction::Re | MathFunction::Conj, _) => return Ok(value),
+            (MathFunction::Im, _) => return Ok(Number::NaturalNumber(Zero::zero())),
+            (_, Number::Interval(x)) => return interval::function(fun, x),
+            (_, Number::Uncertain(u)) => return uncertainty::function(fun, u),
+            (MathFunction::Arg, _) => return Ok(Number::DecimalNumber(Complex64::from(value).arg())),
+            (_, Number::Complex(z)) => return Self::apply_complex_function(fun, *z),
+            (_, Number::Quantity(q)) => return units::function(fun, q),
+            _ => (),
+        }
+
+        if let Some(ctx) = ctx {
+            if let Some(res) = decimal::from_number(&value, &ctx).and_then(|x| decimal::function(fun, &x, &ctx)) {
+                return Ok(Number::BigDecimal(res));
+            }
+        }
+
+        let x: f64 = value.clone().into();
+        let out_of_domain = match fun {
+            MathFunction::Sqrt | MathFunction::Ln | MathFunction::Log | MathFunction::Log2 => x < 0.,
+            MathFunction::ASin | MathFunction::ACos | MathFunction::ATanh => x.abs() > 1.,
+            MathFunction::ACosh => x < 1.,
+            _ => false,
+        };
+        if out_of_domain {
+            return Self::apply_complex_function(fun, Complex64::from(value));
+        }
+        Ok(Number::DecimalNumber(Self::apply_float_function(fun, x)?))
+    }
+
+    /// Evaluates a trigonometric [`MathFunction`] with the angles measured in units of pi/`half_turn` radians
+    ///
+    fn apply_with_angles(
+        fun: MathFunction,
+        value: Number,
+        half_turn: u32,
+        result_stack: &mut VecDeque<Number>,
+        settings: &Settings,
+    ) -> anyhow::Result<Number> {
+        let float = matches!(value, Number::NaturalNumber(_) | Number::DecimalNumber(_) | Number::Rational(_))
+            && settings.numeric_mode.decimal_context().is_none();
+        let x = value.to_f64_lossy();
+        match fun {
+            MathFunction::Sin | MathFunction::Cos | MathFunction::Tan if float => {
+                Ok(Number::DecimalNumber(Self::trig_of_turn(fun, x, half_turn)))
+            }
+            MathFunction::Sin | MathFunction::Cos | MathFunction::Tan => {
+                let value = Self::operate(Operator::Mul, value, Self::radians_per(half_turn, settings)?, settings)?;
+                Self::apply_function(fun, value, result_stack, &settings.in_radians())
+            }
+            _ if float && (fun == MathFunction::ATan || x.abs() <= 1.) => {
+                Ok(Number::DecimalNumber(Self::inverse_of_turn(fun, x, half_turn)))
+            }
+            _ => {
+                let res = Self::apply_function(fun, value, result_stack, &settings.in_radians())?;
+                Self::operate(Operator::Div, res, Self::radians_per(half_turn, settings)?, settings)
+            }
+        }
+    }
+
+    /// Evaluates a [`MathFunction`] over a f64 in its real domain
+    ///
+    fn apply_float_function(fun: MathFunction, x: f64) -> anyhow::Result<f64> {
+        let res = match fun {
+            MathFunction::Sin => f64::sin(x),
+            MathFunction::Cos => f64::cos(x),
+            MathFunction::Tan => f64::tan(x),
+            MathFunction::ASin => f64::asin(x),
+            MathFunction::ACos => f64::acos(x),
+            MathFunction::ATan => f64::atan(x),
+            MathFunction::Ln => f64::ln(x),
+            MathFunction::Log => f64::log10(x),
+            MathFunction::Abs => f64::abs(x),
+            MathFunction::Sqrt => f64::sqrt(x),
+            MathFunction::Sinh => f64::sinh(x),
+            MathFunction::Cosh => f64::cosh(x),
+            MathFunction::Tanh => f64::tanh(x),
+            MathFunction::ASinh => f64::asinh(x),
+            MathFunction::ACosh => f64::acosh(x),
+            MathFunction::ATanh => f64::atanh(x),
+            MathFunction::Exp => f64::exp(x),
+            MathFunction::Log2 => f64::log2(x),
+            MathFunction::Cbrt => f64::cbrt(x),
+            // not a number or infinite, otherwise exact
+            MathFunction::Floor | MathFunction::Ceil | MathFunction::Round | MathFunction::Trunc => x,
+            MathFunction::Fract => f64::fract(x),
+            MathFunction::Sign => f64::signum(x),
+            _ => return Err(anyhow!("This should never happen!")),
+        };
+        Ok(res)
+    }
+
+    /// Evaluates a special [`MathFunction`] or a probability distribution of real arguments as a f64,
+    /// but the gamma function of a positive natural number, that is the exact factorial of its predecessor
+    ///
+    fn apply_special(fun: MathFunction, args: &[Number], settings: &Settings) -> anyhow::Result<Number> {
+        if let (MathFunction::Gamma, [Number::NaturalNumber(n)]) = (fun, args) {
+            if n.is_positive() {
+                return Self::factorial(Operator::Fac, Number::NaturalNumber(n - 1), &settings.limits);
+            }
+        }
+        let real = |n: &Number| matches!(n, Number::NaturalNumber(_) | Number::DecimalNumber(_) | Number::Rational(_) | Number::BigDecimal(_));
+        if let Some(value) = args.iter().find(|a| !real(a)) {
+            return Err(MathError::domain(fun, value).into());
+        }
+        let reals: Vec<f64> = args.iter().map(Number::to_f64_lossy).collect();
+        let res = if special::is_special(fun) {
+            special::function(fun, &reals)?
+        } else {
+            distribution::function(fun, &reals)?
+        };
+        Ok(Number::DecimalNumber(res))
+    }
+
+    /// Evaluates a [`MathFunction`] of many arguments. The integer functions are exact, and only
+    /// take integers, while 'ncr' and 'npr' of real numbers are computed with the gamma function.
+    ///
+    fn apply_many(fun: MathFunction, args: &[Number], settings: &Settings) -> anyhow::Result<Number> {
+        if special::is_special(fun) || distribution::is_distribution(fun) {
+            return Self::apply_special(fun, args, settings);
+        }
+        match (fun, args) {
+            (MathFunction::NRoot, [x, n]) => return Self::nroot(x.clone(), n.clone(), settings),
+            (MathFunction::Interval, [lo, hi]) => return interval::from_bounds(lo, hi),
+            _ => (),
+        }
+        if let Some(integers) = args.iter().map(integer::to_integer).collect::<Option<Vec<BigInt>>>() {
+            if let [n, k] = &integers[..] {
+                Self::check_choose_size(fun, n, k, &settings.limits)?;
+            }
+            return integer::function(fun, &integers);
+        }
+        let real = |n: &Number| matches!(n, Number::NaturalNumber(_) | Number::DecimalNumber(_) | Number::Rational(_) | Number::BigDecimal(_));
+        match args {
+            [n, k] if matches!(fun, MathFunction::NCr | MathFunction::NPr) && real(n) && real(k) => {
+                let (n, k) = (n.to_f64_lossy(), k.to_f64_lossy());
+                let permutations = special::gamma(n + 1.) / special::gamma(n - k + 1.);
+                Ok(Number::DecimalNumber(match fun {
+                    MathFunction::NCr => permutations / special::gamma(k + 1.),
+                    _ => permutations,
+                }))
+            }
+            _ => {
+                let value = args.iter().find(|a| integer::to_integer(a).is_none()).unwrap_or(&args[0]);
+                Err(MathError::domain(fun, value).into())
+            }
+        }
+    }
+
+    /// The n-th root of x: exact for the perfect powers, and negative for an odd root of a negative number
+    ///
+    fn nroot(x: Number, n: Number, settings: &Settings) -> anyhow::Result<Number> {
+        let index = integer::to_integer(&n);
+        if index.as_ref().is_some_and(Zero::is_zero) {
+            return Err(MathError::domain(MathFunction::NRoot, n).into());
+        }
+        let index = index.and_then(|k| k.to_u32());
+        if let Some(res) = index.and_then(|k| integer::root(&x, k)) {
+            return Ok(res);
+        }
+        let zero = Number::NaturalNumber(Zero::zero());
+        let minus_one = Number::NaturalNumber(BigInt::from(-1));
+        let negative = matches!(x, Number::NaturalNumber(_) | Number::DecimalNumber(_) | Number::Rational(_) | Number::BigDecimal(_)) && x < zero;
+        match index {
+            Some(k) if negative && k % 2 == 1 => {
+                let root = Self::nroot(Self::operate(Operator::Mul, x, minus_one.clone(), settings)?, n, settings)?;
+                Self::operate(Operator::Mul, root, minus_one, settings)
+            }
+            _ if negative => Ok(Number::from_complex(Complex64::from(x).powf(1. / n.to_f64_lossy()))),
+            _ => {
+                let exponent = Self::operate(Operator::Div, Number::NaturalNumber(BigInt::one()), n, settings)?;
+                Self::operate(Operator::Pow, x, exponent, settings)
+            }
+        }
+    }
+
+    /// The sine, the cosine or the tangent of an angle `x` measured in units of pi/`half_turn` radians
+    /// (180 for the degrees). The angle is reduced exactly to the nearest quarter turn, so that
+    /// the sine of 180 degrees is 0 rather than 1.2e-16, and the cosine of 60 degrees is 0.5.
+    ///
+    #[allow(clippy::float_cmp)] // the exact angles of the reduction
+    fn trig_of_turn(fun: MathFunction, x: f64, half_turn: u32) -> f64 {
+        let half_turn = f64::from(half_turn);
+        let quarter = half_turn / 2.;
+        let x = x.rem_euclid(2. * half_turn);
+        let quarters = (x / quarter).round();
+        let rest = x - quarters * quarter;
+
+        let (sin, cos) = if rest == 0. {
+            (0., 1.)
+        } else if rest.abs() * 6. == half_turn {
+            (0.5f64.copysign(rest), 0.75f64.sqrt())
+        } else if rest.abs() * 4. == half_turn {
+            (std::f64::consts::FRAC_1_SQRT_2.copysign(rest), std::f64::consts::FRAC_1_SQRT_2)
+        } else {
+            (rest * std::f64::consts::PI / half_turn).sin_cos()
+        };
+        // rotates by the quarter turns, keeping the zeros positive
+        let (sin, cos) = match quarters.to_u8().unwrap_or_default() % 4 {
+            0 => (sin, cos),
+            1 => (cos, 0. - sin),
+            2 => (0. - sin, 0. - cos),
+            _ => (0. - cos, sin),
+        };
+        match fun {
+            MathFunction::Sin => sin,
+            MathFunction::Cos => cos,
+            _ => sin / cos,
+        }
+    }
+
+    /// The arc sine, the arc cosine or the arc tangent of `x`, measured in units of pi/`half_turn`
+    /// radians. The angles of the exact values of the sine are exact too: 'asin(0.5)' is 30 degrees.
+    ///
+    #[allow(clippy::float_cmp)] // the exact values of the sine
+    fn inverse_of_turn(fun: MathFunction, x: f64, half_turn: u32) -> f64 {
+        let half_turn = f64::from(half_turn);
+        let asin = |x: f64| {
+            if x.abs() == 1. {
+                (half_turn / 2.).copysign(x)
+            } else if x.abs() == 0.5 {
+                (half_turn / 6.).copysign(x)
+            } else {
+                x.asin() * half_turn / std::f64::consts::PI
+            }
+        };
+        match fun {
+            MathFunction::ASin => asin(x),
+            MathFunction::ACos if x.abs() == 1. || x.abs() == 0.5 || x == 0. => half_turn / 2. - asin(x),
+            MathFunction::ACos => x.acos() * half_turn / std::f64::consts::PI,
+            _ if x.abs() == 1. => (half_turn / 4.).copysign(x),
+            _ => x.atan() * half_turn / std::f64::consts::PI,
+        }
+    }
+
+    /// The measure of an angle of pi/`half_turn` radians (one degree when `half_turn` is 180),
+    /// with the precision of [`NumericMode::Decimal`]
+    ///
+    fn radians_per(half_turn: u32, settings: &Settings) -> anyhow::Result<Number> {
+        let ctx = settings.numeric_mode.decimal_context();
+        let pi = match &ctx {
+            Some(ctx) => Number::BigDecimal(decimal::pi(ctx)),
+            None => Number::DecimalNumber(std::f64::consts::PI),
+        };
+        Self::arithmetic(Operator::Div, pi, Number::NaturalNumber(BigInt::from(half_turn)), ctx.as_ref())
+    }
+
+    /// Converts an angle in degrees ('30°') into the [`AngleMode`] of the session
+    ///
+    fn degrees(value: Number, settings: &Settings) -> anyhow::Result<Number> {
+        match settings.angle_mode {
+            AngleMode::Radians => Self::operate(Operator::Mul, value, Self::radians_per(180, settings)?, settings),
+            AngleMode::Degrees => Ok(value),
+            AngleMode::Gradians => {
+                let value = Self::operate(Operator::Mul, value, Number::NaturalNumber(BigInt::from(10)), settings)?;
+                Self::operate(Operator::Div, value, Number::NaturalNumber(BigInt::from(9)), settings)
+            }
+        }
+    }
+
+    /// A percentage ('5%'): a hundredth, exact in rational mode and decimal in decimal mode
+    ///
+    fn percent(value: Number, settings: &Settings) -> anyhow::Result<Number> {
+        Self::operate(Operator::Div, value, Number::NaturalNumber(BigInt::from(100)), settings)
+    }
+
+    /// Evaluates a [`MathFunction`] over the complex numbers, with the principal branch
+    /// of the multivalued functions
+    ///
+    fn apply_complex_function(fun: MathFunction, z: Complex64) -> anyhow::Result<Number> {
+        let res = match fun {
+            MathFunction::Sin => z.sin(),
+            MathFunction::Cos => z.cos(),
+            MathFunction::Tan => z.tan(),
+            MathFunction::ASin => z.asin(),
+            MathFunction::ACos => z.acos(),
+            MathFunction::ATan => z.atan(),
+            MathFunction::Ln => z.ln(),
+            MathFunction::Log => z.log10(),
+            MathFunction::Sqrt => z.sqrt(),
+            MathFunction::Sinh => z.sinh(),
+            MathFunction::Cosh => z.cosh(),
+            MathFunction::Tanh => z.tanh(),
+            MathFunction::ASinh => z.asinh(),
+            MathFunction::ACosh => z.acosh(),
+            MathFunction::ATanh => z.atanh(),
+            MathFunction::Exp => z.exp(),
+            MathFunction::Log2 => z.log(2.),
+            MathFunction::Cbrt => z.cbrt(),
+            MathFunction::Abs => return Ok(Number::DecimalNumber(z.norm())),
+            MathFunction::Floor
+            | MathFunction::Ceil
+            | MathFunction::Round
+            | MathFunction::Trunc
+            | MathFunction::Fract
+            | MathFunction::Sign => return Err(MathError::domain(fun, z).into()),
+            _ => return Err(anyhow!("This should never happen!")),
+        };
+        Ok(Number::from_complex(res))
+    }
+
+    /// Evaluates a binary [Operator], but the assignment: element by element or as a matrix
+    /// operation when one side is a [`Number::Matrix`]
+    ///
+    fn operate(op: Operator, left: Number, right: Number, settings: &Settings) -> anyhow::Result<Number> {
+        if matches!(left, Number::Matrix(_)) || matches!(right, Number::Matrix(_)) {
+            return matrix::arithmetic(op, left, right, &|op, l, r| Self::operate(op, l, r, settings));
+        }
+        let mode = settings.numeric_mode;
+        let op = matrix::element_wise(op);
+        if op == Operator::Pow {
+            Self::check_power_size(&left, &right, &settings.limits)?;
+        }
+        let mut left = left;
+        match op {
+            Operator::Div => {
+                if Self::is_exact_zero(&right) {
+                    return Err(anyhow!(DIVISION_ZERO_ERR));
+                }
+                left = Self::divisible(left, &right, mode);
+            }
+            Operator::Pow if right < Number::NaturalNumber(Zero::zero()) => {
+                if Self::is_exact_zero(&left) {
+                    return Err(anyhow!(DIVISION_ZERO_ERR));
+                }
+                left = Self::divisible(left, &right, mode);
+            }
+            Operator::To => return units::arithmetic(op, &left, &right),
+            Operator::PlusMinus => return uncertainty::arithmetic(op, &left, &right),
+            _ => (),
+        }
+        Self::arithmetic(op, left, right, mode.decimal_context().as_ref())
+    }
+
+    /// Evaluates +, -, *, / and ^. In [`NumericMode::Decimal`] the result is rounded to the
+    /// precision of the context whenever one side is a [`Number::BigDecimal`].
+    /// Fails when the units of measure of the two sides don't match, or when an interval
+    /// or a measured value is combined with something that is not a real number.
+    ///
+    fn arithmetic(op: Operator, left: Number, right: Number, ctx: Option<&Context>) -> anyhow::Result<Number> {
+        if matches!(left, Number::Interval(_)) || matches!(right, Number::Interval(_)) {
+            return interval::arithmetic(op, &left, &right);
+        }
+        if matches!(left, Number::Uncertain(_)) || matches!(right, Number::Uncertain(_)) {
+            return uncertainty::arithmetic(op, &left, &right);
+        }
+        if matches!(left, Number::Quantity(_)) || matches!(right, Number::Quantity(_)) {
+            return units::arithmetic(op, &left, &right);
+        }
+        if let Some(ctx) = ctx {
+            if matches!(left, Number::BigDecimal(_)) || matches!(right, Number::BigDecimal(_)) {
+                if let Some(res) = decimal::arithmetic(op, &left, &right, ctx) {
+                    return Ok(res);
+                }
+            }
+        }
+        Ok(match op {
+            Operator::Add => left + right,
+            Operator::Sub => left - right,
+            Operator::Mul => left * right,
+            Operator::Div => left / right,
+            _ => left ^ right,
+        })
+    }
+
+    /// Brings an operand into the numeric mode: in [`NumericMode::Decimal`] (when a context is given)
+    /// every [`Number::DecimalNumber`] becomes a [`Number::BigDecimal`]
+    ///
+    fn in_mode(n: Number, ctx: Option<&Context>) -> Number {
+        match (n, ctx) {
+            (Number::DecimalNumber(v), Some(ctx)) => decimal::from_number(&Number::DecimalNumber(v), ctx)
+                .map_or(Number::DecimalNumber(v), Number::BigDecimal),
+            (n, _) => n,
+        }
+    }
+
+    /// In [`NumericMode::Decimal`], the built-in constants are computed with the precision
+    /// of the context, unless they have been overridden
+    ///
+    #[allow(clippy::float_cmp)] // the exact values set by the session
+    fn constant_in_mode(name: &str, n: &Number, ctx: Option<&Context>) -> Option<Number> {
+        let ctx = ctx?;
+        match (name, n) {
+            ("pi", Number::DecimalNumber(v)) if *v == std::f64::consts::PI => Some(Number::BigDecimal(decimal::pi(ctx))),
+            ("e", Number::DecimalNumber(v)) if *v == std::f64::consts::E => Some(Number::BigDecimal(decimal::e(ctx))),
+            _ => None,
+        }
+    }
+
+    /// In interval mode, a variable becomes an interval widened by the relative tolerance, while
+    /// a built-in constant becomes the smallest interval that contains its exact value
+    ///
+    fn as_interval(name: &str, n: Number, tolerance: f64) -> Number {
+        if let Number::Interval(_) = n {
+            return n;
+        }
+        let tolerance = if CONSTANTS.contains(&name) { 0. } else { tolerance };
+        interval::with_tolerance(&n, tolerance).unwrap_or(n)
+    }
+
+    /// Fails if the result of an operator or of a function is NaN or infinite,
+    /// and the [`FloatPolicy`] doesn't allow it
+    ///
+    fn check_finite(operation: impl Display, n: &Number, policy: FloatPolicy) -> anyhow::Result<()> {
+        let fails = match policy {
+            FloatPolicy::Propagate => false,
+            FloatPolicy::ErrorOnNonFinite => n.is_nan() || n.is_infinite(),
+            FloatPolicy::ErrorOnNaN => n.is_nan(),
+        };
+        if fails {
+            return Err(MathError::NonFinite { operation: operation.to_string(), value: n.to_string() }.into());
+        }
+        Ok(())
+    }
+
+    /// Fails if an integer, or the numerator or the denominator of a fraction, is larger than the limit
+    ///
+    fn check_size(n: &Number, limits: &Limits) -> anyhow::Result<()> {
+        let Some(max) = limits.max_bits else { return Ok(()) };
+        let bits = match n {
+            Number::NaturalNumber(v) => v.bits(),
+            Number::Rational(v) => v.numer().bits().max(v.denom().bits()),
+            Number::Matrix(m) => return m.elements().iter().try_for_each(|x| Self::check_size(x, limits)),
+            _ => 0,
+        };
+        Self::bits_limit(bits, max)
+    }
+
+    /// Fails before raising an integer or a fraction to a power, if the result would be larger than the limit
+    ///
+    fn check_power_size(base: &Number, exponent: &Number, limits: &Limits) -> anyhow::Result<()> {
+        let (Some(max), Number::NaturalNumber(e)) = (limits.max_bits, exponent) else { return Ok(()) };
+        let bits = match base {
+            Number::NaturalNumber(v) if v.magnitude().bits() > 1 => v.bits(),
+            Number::Rational(v) => v.numer().bits().max(v.denom().bits()),
+            _ => return Ok(()),
+        };
+        // |base| >= 2^(bits-1), so the power has at least (bits-1)*e bits
+        let e = e.magnitude().to_u64().unwrap_or(u64::MAX);
+        Self::bits_limit((bits - 1).saturating_mul(e), max)
+    }
+
+    /// Fails before computing a number of permutations or of combinations larger than the limit,
+    /// as 'npr(n, k)' is at least (n-k+1)^k and 'ncr(n, k)' is at least (n/k)^k
+    ///
+    fn check_choose_size(fun: MathFunction, n: &BigInt, k: &BigInt, limits: &Limits) -> anyhow::Result<()> {
+        let Some(max) = limits.max_bits else { return Ok(()) };
+        if !matches!(fun, MathFunction::NCr | MathFunction::NPr) || k.is_negative() || k > n {
+            return Ok(());
+        }
+        let n = n.to_f64().unwrap_or(f64::INFINITY);
+        let k = k.to_f64().unwrap_or(f64::INFINITY);
+        let bits = match fun {
+            MathFunction::NCr => k.min(n - k) * (n / k.min(n - k)).log2(),
+            _ => k * (n - k + 1.).log2(),
+        };
+        Self::bits_limit(bits.max(0.).to_u64().unwrap_or(u64::MAX), max)
+    }
+
+    /// Fails before computing a factorial ('step' 1) or a double factorial ('step' 2) larger than the limit.
+    /// The size of n! is about 'n*log2(n) - n*log2(e)' bits, by Stirling's formula.
+    ///
+    fn check_factorial_size(n: u64, step: u64, limits: &Limits) -> anyhow::Result<()> {
+        let Some(max) = limits.max_bits else { return Ok(()) };
+        let x = n.to_f64().unwrap_or(f64::INFINITY);
+        let bits = (x * x.log2() - x * std::f64::consts::LOG2_E) / step.to_f64().unwrap_or(1.);
+        Self::bits_limit(bits.max(0.).to_u64().unwrap_or(u64::MAX), max)
+    }
+
+    fn bits_limit(bits: u64, max: u64) -> anyhow::Result<()> {
+        if bits > max {
+            return Err(MathError::LimitExceeded { limit: "size of an integer", max: format!("{max} bits") }.into());
+        }
+        Ok(())
+    }
+
+    /// Checks if a number is an exact zero, that is neither a float nor a fraction
+    ///
+    fn is_exact_zero(n: &Number) -> bool {
+        match n {
+            Number::NaturalNumber(v) => v.is_zero(),
+            Number::BigDecimal(v) => v.is_zero(),
+            _ => false,
+        }
+    }
+
+    /// Lists the variables and the functions referenced by the expression, without evaluating it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::Session;
+    /// let session = Session::init();
+    /// let resolver = session.process("y = 2 * sin(x) + pi");
+    /// let symbols = resolver.symbols();
+    ///
+    /// assert_eq!(symbols.free_variables["x"], vec![12..13]);
+    /// assert_eq!(symbols.assigned_variables["y"], vec![0..1]);
+    /// assert_eq!(symbols.functions["sin"], vec![8..11]);
+    /// ```
+    ///
+    #[must_use]
+    pub fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::default();
+        Self::collect_symbols(&self.rpn_expr, &mut HashSet::new(), &mut symbols);
+        symbols
+    }
+
+    /// Collects the [`Symbols`] of a rpn expression, and of the bodies of its [`Token::Lambda`]s,
+    /// where their variable is bound rather than free
+    ///
+    fn collect_symbols<'a>(rpn_expr: &'a RpnExpr, assigned: &mut HashSet<&'a str>, symbols: &mut Symbols) {
+        let assignments = Self::assignment_targets(rpn_expr);
+        let targets: HashSet<usize> = assignments.values().copied().collect();
+
+        for (i, (t, span)) in rpn_expr.iter().enumerate() {
+            match t {
+                Token::Variable(v) if targets.contains(&i) => {
+                    symbols.assigned_variables.entry((*v).to_string()).or_default().push(span.clone());
+                }
+                Token::Variable(v) if !assigned.contains(v) && !CONSTANTS.contains(v) => {
+                    symbols.free_variables.entry((*v).to_string()).or_default().push(span.clone());
+                }
+                Token::Function(fun) | Token::Call(fun, _) => {
+                    symbols.functions.entry(fun.name().to_string()).or_default().push(span.clone());
+                }
+                Token::Operator(Operator::Eql) => {
+                    if let Some(Token::Variable(v)) = assignments.get(&i).map(|&v| &rpn_expr[v].0) {
+                        assigned.insert(v);
+                    }
+                }
+                Token::Lambda(var, body) => {
+                    let mut bound = assigned.clone();
+                    bound.insert(var);
+                    Self::collect_symbols(body, &mut bound, symbols);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Whether the expression gives back the same value whenever its variables have the same values,
+    /// so that its result can be cached. It doesn't if it calls an impure function, such as `rand()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::Session;
+    /// let session = Session::init();
+    ///
+    /// assert!(session.process("2 * sin(x)").is_pure());
+    /// assert!(!session.process("x + randn()").is_pure());
+    /// ```
+    ///
+    #[must_use]
+    pub fn is_pure(&self) -> bool {
+        Self::is_pure_expr(&self.rpn_expr)
+    }
+
+    fn is_pure_expr(rpn_expr: &RpnExpr) -> bool {
+        rpn_expr.iter().all(|(t, _)| match t {
+            Token::Function(fun) | Token::Call(fun, _) => !fun.is_impure(),
+            Token::Lambda(_, body) => Self::is_pure_expr(body),
+            _ => true,
+        })
+    }
+
+    /// Finds out the targets of the assignments of a rpn expression, by simulating
+    /// its evaluation. Maps the position of each [`Operator::Eql`] to the position
+    /// of the [`Token::Variable`] on its left side.
+    ///
+    fn assignment_targets(rpn_expr: &RpnExpr) -> HashMap<usize, usize> {
+        let mut targets = HashMap::new();
+        let mut stack: Vec<usize> = Vec::new();
+
+        for (i, (t, _)) in rpn_expr.iter().enumerate() {
+            let arity = match t {
+                Token::Lambda(..) => continue,
+                Token::Operator(Operator::Une | Operator::Fac | Operator::DoubleFac | Operator::Degree | Operator::Percent) => 1,
+                Token::Function(fun) => fun.arity(),
+                Token::Call(_, argc) => *argc,
+                Token::Operator(_) => 2,
+                Token::MatrixLiteral(rows) => rows.iter().sum(),
+                _ => 0,
+            };
+            let operands = stack.split_off(stack.len().saturating_sub(arity));
+
+            if let (Token::Operator(Operator::Eql), [left, _]) = (t, operands.as_slice()) {
+                if let Token::Variable(_) = rpn_expr[*left].0 {
+                    targets.insert(i, *left);
+                }
+            }
+            stack.push(i);
+        }
+        targets
     }
 
     /// Transforming an infix notation to Reverse Polish Notation (RPN)
@@ -164,21 +1118,66 @@ impl RpnResolver<'_> {
     ///     "3 * 4 + 5 * 6" becomes "3 4 * 5 6 * +"
     /// ``
     fn reverse_polish_notation<'a>(
-        infix_stack: &[Token<'a>],
+        infix_stack: &[(Token<'a>, Span)],
         local_heap: Rc<RefCell<HashMap<String, Number>>>,
-    ) -> (VecDeque<Token<'a>>, Rc<RefCell<HashMap<String, Number>>>) {
+    ) -> (RpnExpr<'a>, Rc<RefCell<HashMap<String, Number>>>) {
         /*  Create an empty stack for keeping operators. Create an empty list for output. */
-        let mut operators_stack: Vec<Token> = Vec::new();
-        let mut postfix_stack: VecDeque<Token> = VecDeque::new();
+        let mut operators_stack: Vec<(Token, Span)> = Vec::new();
+        let mut postfix_stack: RpnExpr = VecDeque::new();
+        /* The rows of the matrix literals being read, one for each open bracket: None for a parenthesis,
+           but for the one of a function, that counts its arguments */
+        let mut matrices: Vec<Option<MatrixRows>> = Vec::new();
 
         /* Scan the infix expression from left to right. */
-        for t in infix_stack {
+        for (i, st @ (t, span)) in infix_stack.iter().enumerate() {
+            if let Some(Some(rows)) = matrices.last_mut() {
+                rows.pending |= !matches!(t, Token::Separator(_) | Token::Operator(_) | Token::Bracket(token::Bracket::Close | token::Bracket::MatrixClose));
+            }
             match *t {
-                /* If the token is an operand, add it to the output list. */
-                Token::Operand(_) => postfix_stack.push_back(t.clone()),
+                /* If the token is an operand or a variable, add it to the output list. */
+                Token::Operand(_) | Token::Variable(_) | Token::MatrixLiteral(_) | Token::Call(..) | Token::Lambda(..) => postfix_stack.push_back(st.clone()),
 
                 /* If the token is a left parenthesis, push it on the stack. */
-                Token::Bracket(token::Bracket::Open) => operators_stack.push(t.clone()),
+                Token::Bracket(token::Bracket::Open) => {
+                    operators_stack.push(st.clone());
+                    let counted = i > 0 && matches!(infix_stack[i - 1].0, Token::Function(_));
+                    matrices.push(counted.then(|| MatrixRows { starts: vec![postfix_stack.len()], ..MatrixRows::default() }));
+                }
+
+                /* A left square bracket opens a matrix literal */
+                Token::Bracket(token::Bracket::MatrixOpen) => {
+                    operators_stack.push(st.clone());
+                    matrices.push(Some(MatrixRows::default()));
+                }
+
+                /* A separator ends an element of a matrix, or an argument of a function:
+                   pop the operators of the element until the bracket */
+                Token::Separator(sep) => {
+                    while let Some((Token::Operator(_) | Token::Function(_), _)) = operators_stack.last() {
+                        postfix_stack.push_back(operators_stack.pop().expect("It should not happen."));
+                    }
+                    if let Some(Some(rows)) = matrices.last_mut() {
+                        match sep {
+                            token::Separator::Comma => rows.end_element(),
+                            token::Separator::Semicolon => rows.end_row(),
+                        }
+                        rows.starts.push(postfix_stack.len());
+                    }
+                }
+
+                /* A right square bracket pops the operators until the left one, and builds the matrix */
+                Token::Bracket(token::Bracket::MatrixClose) => {
+                    while let Some(token) = operators_stack.pop() {
+                        match token {
+                            (Token::Bracket(token::Bracket::MatrixOpen), _) => break,
+                            _ => postfix_stack.push_back(token),
+                        }
+                    }
+                    if let Some(Some(mut rows)) = matrices.pop() {
+                        rows.end_row();
+                        postfix_stack.push_back((Token::MatrixLiteral(rows.rows), span.clone()));
+                    }
+                }
 
                 /* If the token is a right parenthesis:
                     Pop the stack and add operators to the output list until you encounter a left parenthesis.
@@ -187,17 +1186,31 @@ impl RpnResolver<'_> {
 
                     while let Some(token) = operators_stack.pop() {
                         match token {
-                            Token::Bracket(token::Bracket::Open) => break, // discards left parenthesis
+                            (Token::Bracket(token::Bracket::Open), _) => break, // discards left parenthesis
                             _ => postfix_stack.push_back(token),
                         }
                     }
+                    /* The right parenthesis of a function records how many arguments it was given,
+                       but for a function that takes as many as its arity */
+                    if let Some(Some(mut args)) = matrices.pop() {
+                        args.end_row();
+                        if let Some((Token::Function(fun), fun_span)) = operators_stack.pop() {
+                            let count: usize = args.rows.iter().sum();
+                            let count = if Self::bind_lambda(fun, &args.starts, &mut postfix_stack) { count - 2 } else { count };
+                            if !fun.counts_arguments() && count == fun.arity() {
+                                postfix_stack.push_back((Token::Function(fun), fun_span));
+                            } else if fun != MathFunction::Diff || !Self::differentiate(&mut postfix_stack, &(fun_span.start..span.end)) {
+                                postfix_stack.push_back((Token::Call(fun, count), fun_span));
+                            }
+                        }
+                    }
                 },
 
                 Token::Operator(_op) => {
                     let op1: Token<'_> = t.clone();
 
                     while !operators_stack.is_empty() {
-                        let op2: &Token = operators_stack.last().unwrap();
+                        let (op2, _) = operators_stack.last().unwrap();
                         match op2 {
                             Token::Operator(_) => {
                                 if Token::compare_operator_priority(op1.clone(), op2.clone()) {
@@ -212,19 +1225,11 @@ impl RpnResolver<'_> {
                             _ => break,
                         }
                     }
-                    operators_stack.push(op1.clone());
+                    operators_stack.push(st.clone());
                 },
 
                 Token::Function(_) => {
-                    operators_stack.push(t.clone());
-                },
-
-                /* If the token is a variable, add it to the output list and to the local_heap with a default value*/
-                Token::Variable(s) => {
-                    postfix_stack.push_back(t.clone());
-                    let s = s.to_lowercase();
-                    local_heap.borrow_mut().entry(s) // let's not override consts
-                        .or_insert(Number::NaturalNumber(Zero::zero()));
+                    operators_stack.push(st.clone());
                 },
             }
             debug!("Inspecting... {} - OUT {} - OP - {}", *t, DisplayThisDeque(&postfix_stack), DisplayThatVec(&operators_stack));
@@ -232,38 +1237,214 @@ impl RpnResolver<'_> {
 
         /* After all tokens are read, pop remaining operators from the stack and add them to the list. */
         operators_stack.reverse();
-        operators_stack.iter().for_each(|t| postfix_stack.push_back(t.clone()));
+        postfix_stack.extend(operators_stack.iter().cloned());
         
         debug!(
             "DEBUG: EOF - OUT {} - OP - {}", DisplayThisDeque(&postfix_stack), DisplayThatVec(&operators_stack)
         );
 
+        /* Add the free variables to the local_heap with a default value */
+        Self::declare_variables(&postfix_stack, &[], &local_heap);
+
         (postfix_stack, local_heap)
     }
 
-    fn factorial_helper(n: BigInt) -> BigInt {
-        if n == BigInt::zero() {
-            return BigInt::one();
+    /// Adds the variables of a rpn expression to the heap, with a default value, but for the
+    /// ones bound by a [`Token::Lambda`]
+    ///
+    fn declare_variables(rpn_expr: &RpnExpr, bound: &[&str], local_heap: &RefCell<HashMap<String, Number>>) {
+        for (t, _) in rpn_expr {
+            match t {
+                Token::Variable(s) if !bound.contains(s) => {
+                    local_heap.borrow_mut().entry(s.to_lowercase()) // let's not override consts
+                        .or_insert(Number::NaturalNumber(Zero::zero()));
+                }
+                Token::Lambda(var, body) => Self::declare_variables(body, &[bound, &[*var]].concat(), local_heap),
+                _ => (),
+            }
+        }
+    }
+
+    /// Moves the expression and the variable among the arguments of a call to a function of an expression,
+    /// whose postfix tokens begin at the given starts, into a [`Token::Lambda`] after the other arguments,
+    /// right before the [`Token::Call`]. Leaves them as they are if the variable is not a plain one.
+    ///
+    fn bind_lambda<'a>(fun: MathFunction, starts: &[usize], postfix_stack: &mut RpnExpr<'a>) -> bool {
+        let Some((expr, var)) = fun.lambda_arguments() else {
+            return false;
+        };
+        let end = postfix_stack.len();
+        let argument = |k: usize| starts[k]..starts.get(k + 1).copied().unwrap_or(end);
+        if starts.len() <= expr.max(var) {
+            return false;
+        }
+        let (body, variable) = (argument(expr), argument(var));
+        let name = match postfix_stack.range(variable.clone()).collect::<Vec<_>>()[..] {
+            [(Token::Variable(name), _)] if !body.is_empty() => *name,
+            _ => return false,
+        };
+        let tokens = postfix_stack.range(body.clone());
+        let span = tokens.clone().map(|(_, span)| span.start).min().unwrap_or_default()..tokens.map(|(_, span)| span.end).max().unwrap_or_default();
+        let lambda: RpnExpr<'a> = if expr < var {
+            postfix_stack.drain(variable);
+            postfix_stack.drain(body).collect()
+        } else {
+            let lambda = postfix_stack.drain(body).collect();
+            postfix_stack.drain(variable);
+            lambda
+        };
+        postfix_stack.push_back((Token::Lambda(name, lambda), span));
+        true
+    }
+
+    /// Replaces the [`Token::Lambda`] of 'diff(expr, x)' with the derivative of its expression,
+    /// with respect to its variable, that is left free. Leaves it as it is if there's no derivative,
+    /// and the evaluation will fail, or if the derivative depends on the angle mode, and it will be
+    /// worked out at the evaluation.
+    ///
+    fn differentiate(postfix_stack: &mut RpnExpr, span: &Span) -> bool {
+        let Some((Token::Lambda(var, body), _)) = postfix_stack.back() else {
+            return false;
+        };
+        let derivative = |angle_mode| Expr::from_rpn(body).and_then(|expr| expr.derivative_in(var, angle_mode));
+        match (derivative(AngleMode::Radians), derivative(AngleMode::Degrees)) {
+            (Ok(derivative), Ok(in_degrees)) if derivative == in_degrees => {
+                postfix_stack.pop_back();
+                postfix_stack.extend(derivative.to_rpn(span));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the calls to 'diff' that are left in a rpn expression with their derivatives,
+    /// in the given angle mode
+    ///
+    fn derivatives_in<'a>(rpn_expr: &RpnExpr<'a>, angle_mode: AngleMode) -> anyhow::Result<RpnExpr<'a>> {
+        let mut res = RpnExpr::new();
+        for (t, span) in rpn_expr {
+            match (t, res.back()) {
+                (Token::Call(MathFunction::Diff, _), Some((Token::Lambda(var, body), _))) => {
+                    let derivative = Expr::from_rpn(&Self::derivatives_in(body, angle_mode)?)?.derivative_in(var, angle_mode)?;
+                    res.pop_back();
+                    res.extend(derivative.to_rpn(span));
+                }
+                _ => res.push_back((t.clone(), span.clone())),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Prepares the left side of a division (or of a power with a negative exponent):
+    /// it becomes a [`Number::Rational`] when both sides are exact and the
+    /// [`NumericMode::Rational`] is on, a [`Number::BigDecimal`] in [`NumericMode::Decimal`],
+    /// otherwise a [`Number::DecimalNumber`]
+    ///
+    fn divisible(left: Number, right: &Number, mode: NumericMode) -> Number {
+        if let Number::Complex(_) | Number::Quantity(_) | Number::Matrix(_) | Number::Interval(_) | Number::Uncertain(_) = left {
+            return left;
+        }
+        if let Some(ctx) = mode.decimal_context() {
+            return decimal::from_number(&left, &ctx)
+                .map_or_else(|| Number::DecimalNumber(left.into()), Number::BigDecimal);
+        }
+        match (mode, left, right) {
+            (NumericMode::Rational, Number::NaturalNumber(v), Number::NaturalNumber(_) | Number::Rational(_)) => {
+                Number::Rational(BigRational::from_integer(v))
+            }
+            (NumericMode::Rational, left @ Number::Rational(_), Number::NaturalNumber(_) | Number::Rational(_)) => left,
+            (_, left, _) => Number::DecimalNumber(left.into()),
+        }
+    }
+
+    /// The factorial ('n!') or the double factorial ('n!!') of a natural number, exactly.
+    /// The factorial of any other real number is 'gamma(x+1)', while the double factorial
+    /// needs an integer. Fails for the negative integers, where the gamma function has its poles.
+    ///
+    fn factorial(op: Operator, value: Number, limits: &Limits) -> anyhow::Result<Number> {
+        let double = op == Operator::DoubleFac;
+        let integer = match &value {
+            Number::NaturalNumber(n) => Some(n.clone()),
+            Number::DecimalNumber(v) if v.fract() == 0. => BigInt::from_f64(*v),
+            Number::BigDecimal(v) if v.is_integer() => Some(v.with_scale(0).into_bigint_and_exponent().0),
+            Number::Matrix(m) => {
+                let data = m.elements().iter().map(|x| Self::factorial(op, x.clone(), limits));
+                let data = data.collect::<anyhow::Result<Vec<Number>>>()?;
+                return Ok(Number::Matrix(Matrix::new(m.rows(), m.cols(), data).expect("Same shape")));
+            }
+            Number::DecimalNumber(_) | Number::Rational(_) | Number::BigDecimal(_) => None,
+            _ => return Err(MathError::domain(op, &value).into()),
+        };
+        match integer {
+            // (-1)!! is 1, as the empty product
+            Some(n) if double && n == BigInt::from(-1) => Ok(Number::NaturalNumber(BigInt::one())),
+            Some(n) if n.is_negative() => Err(MathError::domain(op, n).into()),
+            Some(n) if !matches!(value, Number::NaturalNumber(_)) => {
+                // the factorial of a float is a float
+                let exact = Self::factorial(op, Number::NaturalNumber(n), limits)?;
+                Ok(Number::DecimalNumber(exact.into()))
+            }
+            Some(n) if double => {
+                let n = n.to_u64().ok_or_else(|| anyhow!(FACTORIAL_ERR))?;
+                Self::check_factorial_size(n, 2, limits)?;
+                Ok(Number::NaturalNumber(integer::product(&BigInt::from(2 - n % 2), n.div_ceil(2), 2)))
+            }
+            Some(n) if n.to_u64().is_some() => {
+                Self::check_factorial_size(n.to_u64().unwrap_or_default(), 1, limits)?;
+                Ok(Number::NaturalNumber(Self::factorial_helper(n)))
+            }
+            Some(_) => Err(anyhow!(FACTORIAL_ERR)),
+            None if double => Err(MathError::domain(op, &value).into()),
+            None => Ok(Number::DecimalNumber(special::gamma(f64::from(value) + 1.))),
         }
-    
-        let previous = n.checked_sub(&BigInt::one()).expect("Subtraction underflow");
-        let sub_result = RpnResolver::factorial_helper(previous);
-        n.checked_mul(&sub_result).expect("Multiplication overflow")
+    }
+
+    /// n! of a natural number that fits into a u64
+    ///
+    fn factorial_helper(n: BigInt) -> BigInt {
+        integer::product(&BigInt::one(), n.to_u64().expect("Should not happen"), 1)
+    }
+}
+
+impl<'a> RpnResolver<'a> {
+    /// The expression as a tree, that can be printed in infix notation, or derived
+    ///
+    /// # Errors
+    ///
+    /// The matrix literals and the functions of an expression bound to a variable,
+    /// as `solve`, have no place in a tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::Session;
+    /// let session = Session::init();
+    /// let gradient = session.process("x^2*y").expression().unwrap().derivative("x").unwrap().to_string();
+    ///
+    /// assert_eq!(gradient, "2*x*y");
+    /// session.set("x", 3);
+    /// session.set("y", 5);
+    /// assert_eq!(session.process(&gradient).resolve().unwrap().to_string(), "30");
+    /// ```
+    ///
+    pub fn expression(&self) -> anyhow::Result<Expr<'a>> {
+        // the calls to 'diff' that depend on the angle mode are worked out in the current one
+        Expr::from_rpn(&Self::derivatives_in(&self.rpn_expr, self.settings.borrow().angle_mode)?)
     }
 }
 
-struct DisplayThatVec<'a>(&'a Vec<Token<'a>>);
-struct DisplayThisDeque<'a>(&'a VecDeque<Token<'a>>);
+struct DisplayThatVec<'a>(&'a Vec<(Token<'a>, Span)>);
+struct DisplayThisDeque<'a>(&'a RpnExpr<'a>);
 
 impl Display for DisplayThatVec<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0.iter().map(ToString::to_string).collect::<String>())
+        write!(f, "{}", self.0.iter().map(|(t, _)| t.to_string()).collect::<String>())
     }
 }
 
 impl Display for DisplayThisDeque<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0.iter().map(ToString::to_string).collect::<String>())
+        write!(f, "{}", self.0.iter().map(|(t, _)| t.to_string()).collect::<String>())
     }
 }
 
@@ -271,7 +1452,11 @@ impl Display for DisplayThisDeque<'_> {
 mod tests {
     use num_bigint::BigInt;
     use super::*;
-    use crate::token::{Number, Operator};
+    use crate::token::{MathFunction, Number, Operator};
+
+    fn spanned(tokens: Vec<Token>) -> Vec<(Token, Span)> {
+        tokens.into_iter().enumerate().map(|(i, t)| (t, i..i + 1)).collect()
+    }
 
     #[test]
     fn test_reverse_polish_notation() {
@@ -285,10 +1470,28 @@ mod tests {
             Token::Operand(Number::NaturalNumber(BigInt::from(2u8))),
             Token::Operator(Operator::Add),
         ];
-        assert_eq!(
-            RpnResolver::reverse_polish_notation(&a, Rc::new(RefCell::new(HashMap::new()))).0,
-            b
-        );
+        let (rpn, _) = RpnResolver::reverse_polish_notation(&spanned(a), Rc::new(RefCell::new(HashMap::new())));
+        assert_eq!(rpn.into_iter().map(|(t, _)| t).collect::<Vec<Token>>(), b);
+    }
+
+    #[test]
+    fn test_lambda() {
+        let natural = |n: u8| Token::Operand(Number::NaturalNumber(BigInt::from(n)));
+        let heap = Rc::new(RefCell::new(HashMap::new()));
+        let (rpn, heap) = RpnResolver::reverse_polish_notation(&Parser::parse_spanned("sumover(i, 1, n, i^2)"), heap);
+        let tokens: Vec<Token> = rpn.into_iter().map(|(t, _)| t).collect();
+        let [one, n, Token::Lambda("i", body), call] = &tokens[..] else {
+            panic!("No lambda in {tokens:?}");
+        };
+        assert_eq!([one, n, call], [&natural(1), &Token::Variable("n"), &Token::Call(MathFunction::SumOver, 2)]);
+        assert_eq!(body.iter().map(|(t, _)| t.clone()).collect::<Vec<Token>>(), vec![Token::Variable("i"), natural(2), Token::Operator(Operator::Pow)]);
+        // the bound variable is not added to the heap
+        assert_eq!(heap.borrow().keys().collect::<Vec<_>>(), vec!["n"]);
+
+        // a sum of four values is not a comprehension
+        let (rpn, _) = RpnResolver::reverse_polish_notation(&Parser::parse_spanned("sum(a, b, c, d)"), heap);
+        assert!(!rpn.iter().any(|(t, _)| matches!(t, Token::Lambda(..))));
+        assert_eq!(rpn.back().map(|(t, _)| t.clone()), Some(Token::Call(MathFunction::Sum, 4)));
     }
 
     #[test]
@@ -299,14 +1502,75 @@ mod tests {
     #[test]
     fn test_resolve() {
         let mut resolver = RpnResolver {
-            rpn_expr: VecDeque::from(vec![
+            rpn_expr: VecDeque::from(spanned(vec![
                 Token::Operand(Number::NaturalNumber(BigInt::from(1u8))),
                 Token::Operand(Number::NaturalNumber(BigInt::from(2u8))),
                 Token::Operator(Operator::Add),
-            ]),
+            ])),
+            token_count: 3,
+            nesting_depth: 0,
             local_heap: Rc::new(RefCell::new(HashMap::new())),
+            settings: Rc::default(),
         };
         assert_eq!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(3u8)));
     }
 
+    #[test]
+    fn test_complex_promotion() {
+        let mut stack = VecDeque::new();
+        let apply = |fun, v, stack: &mut VecDeque<Number>| RpnResolver::apply_function(fun, v, stack, &Settings::default()).unwrap();
+
+        assert_eq!(apply(MathFunction::Sqrt, Number::DecimalNumber(-9.), &mut stack), Number::Complex(Complex64::new(0., 3.)));
+        assert_eq!(apply(MathFunction::Sqrt, Number::DecimalNumber(9.), &mut stack), Number::DecimalNumber(3.));
+        assert_eq!(apply(MathFunction::Im, Number::DecimalNumber(9.), &mut stack), Number::NaturalNumber(BigInt::zero()));
+        assert!(matches!(apply(MathFunction::ASin, Number::DecimalNumber(2.), &mut stack), Number::Complex(_)));
+    }
+
+    #[test]
+    fn test_trig_of_turn() {
+        assert_eq!(RpnResolver::trig_of_turn(MathFunction::Sin, 30., 180), 0.5);
+        assert_eq!(RpnResolver::trig_of_turn(MathFunction::Sin, 540., 180), 0.);
+        assert_eq!(RpnResolver::trig_of_turn(MathFunction::Cos, -120., 180), -0.5);
+        assert_eq!(RpnResolver::trig_of_turn(MathFunction::Tan, 225., 180), 1.);
+        assert_eq!(RpnResolver::trig_of_turn(MathFunction::Tan, 90., 180), f64::INFINITY);
+        assert_eq!(RpnResolver::trig_of_turn(MathFunction::Cos, 100., 200), 0.);
+        assert_eq!(RpnResolver::inverse_of_turn(MathFunction::ACos, -0.5, 180), 120.);
+        assert_eq!(RpnResolver::inverse_of_turn(MathFunction::ATan, 1., 200), 50.);
+    }
+
+    #[test]
+    fn test_matrix_literal() {
+        let heap = Rc::new(RefCell::new(HashMap::new()));
+        let (rpn, _) = RpnResolver::reverse_polish_notation(&Parser::parse_spanned("[1, max(2, 3); -x, 4]"), heap);
+        let last = rpn.back().map(|(t, _)| t.clone());
+
+        assert_eq!(last, Some(Token::MatrixLiteral(vec![2, 2])));
+        assert_eq!(RpnResolver::assignment_targets(&rpn), HashMap::new());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let heap = Rc::new(RefCell::new(HashMap::new()));
+        let resolver = RpnResolver::parse_with_borrowed_heap("x = x + y * cos(pi) - cos(y)", heap);
+        let symbols = resolver.symbols();
+
+        assert_eq!(symbols.assigned_variables, BTreeMap::from([("x".to_string(), vec![0..1])]));
+        assert_eq!(
+            symbols.free_variables,
+            BTreeMap::from([("x".to_string(), vec![4..5]), ("y".to_string(), vec![8..9, 26..27])])
+        );
+        assert_eq!(symbols.functions, BTreeMap::from([("cos".to_string(), vec![12..15, 22..25])]));
+    }
+
+    #[test]
+    fn test_assignment_target() {
+        let heap = Rc::new(RefCell::new(HashMap::new()));
+        heap.borrow_mut().insert("y".to_string(), Number::NaturalNumber(BigInt::from(2)));
+
+        let mut resolver = RpnResolver::parse_with_borrowed_heap("x = y + 1", Rc::clone(&heap));
+        resolver.resolve().unwrap();
+
+        assert_eq!(heap.borrow()["x"], Number::NaturalNumber(BigInt::from(3)));
+        assert_eq!(heap.borrow()["y"], Number::NaturalNumber(BigInt::from(2)));
+    }
 }
@@ -1,7 +1,18 @@
 
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, num::NonZeroU64, rc::Rc, str::FromStr, time::Duration};
+use anyhow::anyhow;
+use bigdecimal::Context;
+use num::complex::Complex64;
 use num_bigint::BigInt;
-use crate::{rpn_resolver::RpnResolver, token::Number};
+
+pub use bigdecimal::RoundingMode;
+use crate::{interval::Interval, random::Generator, rpn_resolver::RpnResolver, token::Number};
+
+/// The names of the built-in constants that every new [`Session`] defines
+///
+pub(crate) static CONSTANTS: &[&str] = &["pi", "e", "i"];
+
+static ANGLE_MODE_ERR: &str = "The angle mode must be one of 'rad', 'deg' or 'grad'.";
 
 /// A [`Session`] is an object that holds a variable heap in the form of a [`HashMap`]
 /// that is borrowed to all the [`RpnResolver`] that are built from the builder [`build_resolver_for`()]
@@ -10,6 +21,138 @@ use crate::{rpn_resolver::RpnResolver, token::Number};
 ///
 pub struct Session {
     variable_heap: Rc<RefCell<HashMap<String, Number>>>,
+    settings: Rc<RefCell<Settings>>,
+}
+
+/// How the divisions between natural numbers and the decimal numbers are carried out
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum NumericMode {
+    /// '10/4' gives the float 2.5
+    #[default]
+    Float,
+    /// '10/4' gives the exact fraction 5/2 ([`Number::Rational`]), and '1/3*3' gives exactly 1
+    Rational,
+    /// Every decimal number is a [`Number::BigDecimal`], so that '0.1 + 0.2' gives exactly 0.3.
+    /// The results of the operations and of the functions are rounded to `precision` significant digits.
+    Decimal {
+        /// the number of significant digits
+        precision: u64,
+        /// how the digits beyond the precision are rounded
+        rounding: RoundingMode,
+    },
+}
+
+impl NumericMode {
+    /// The [`Context`] of the [`NumericMode::Decimal`] computations
+    ///
+    pub(crate) fn decimal_context(self) -> Option<Context> {
+        match self {
+            NumericMode::Decimal { precision, rounding } => {
+                Some(Context::new(NonZeroU64::new(precision).unwrap_or(NonZeroU64::MIN), rounding))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The unit of the angles of the trigonometric functions: of the arguments of `sin`, `cos` and `tan`,
+/// and of the results of `asin`, `acos` and `atan`
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum AngleMode {
+    /// a full turn is 2*pi
+    #[default]
+    Radians,
+    /// a full turn is 360
+    Degrees,
+    /// a full turn is 400
+    Gradians,
+}
+
+impl AngleMode {
+    /// The measure of a half turn, or [`None`] for the radians
+    ///
+    pub(crate) fn half_turn(self) -> Option<u32> {
+        match self {
+            AngleMode::Radians => None,
+            AngleMode::Degrees => Some(180),
+            AngleMode::Gradians => Some(200),
+        }
+    }
+}
+
+/// Parses "rad", "deg" or "grad"
+///
+impl FromStr for AngleMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rad" => Ok(AngleMode::Radians),
+            "deg" => Ok(AngleMode::Degrees),
+            "grad" => Ok(AngleMode::Gradians),
+            _ => Err(anyhow!(ANGLE_MODE_ERR)),
+        }
+    }
+}
+
+/// What happens when an operator or a function gives back a non-finite float,
+/// such as 'ln(0)' (-inf) or '0.0/0' (NaN)
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum FloatPolicy {
+    /// NaN and the infinities flow into the following operations
+    #[default]
+    Propagate,
+    /// Any NaN or infinite result fails with a [`MathError::NonFinite`](crate::error::MathError::NonFinite)
+    /// that names the operator or the function that gave it
+    ErrorOnNonFinite,
+    /// Only a NaN result fails, while the infinities are propagated
+    ErrorOnNaN,
+}
+
+/// Limits on the resources that the evaluation of an expression can take, for the expressions
+/// typed by untrusted users. Exceeding any of them makes [`RpnResolver::resolve`] fail with a
+/// [`MathError::LimitExceeded`](crate::error::MathError::LimitExceeded). Every limit is off ([`None`]) by default,
+/// and a session without a `max_bits` can hang on a single power or factorial, such as '9^9^9^9',
+/// that the `max_time` can't interrupt.
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Limits {
+    /// the maximum number of tokens of the expression
+    pub max_tokens: Option<usize>,
+    /// the maximum nesting depth of the brackets of the expression
+    pub max_depth: Option<usize>,
+    /// the maximum size in bits of an integer, or of the numerator and the denominator of a fraction.
+    /// It's checked ahead of the powers and the factorials, that would be too large to compute.
+    pub max_bits: Option<u64>,
+    /// the maximum number of evaluation steps
+    pub max_steps: Option<usize>,
+    /// the maximum wall time of an evaluation, checked between the steps
+    pub max_time: Option<Duration>,
+}
+
+/// The evaluation settings of a [`Session`], shared with all its [`RpnResolver`]s
+///
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Settings {
+    pub(crate) numeric_mode: NumericMode,
+    /// the relative tolerance of the variables in interval mode, if enabled
+    pub(crate) interval_tolerance: Option<f64>,
+    pub(crate) limits: Limits,
+    pub(crate) float_policy: FloatPolicy,
+    pub(crate) angle_mode: AngleMode,
+    /// the generator of the random numbers, shared by all the copies of the settings
+    pub(crate) random: Rc<RefCell<Generator>>,
+}
+
+impl Settings {
+    /// The same settings, with the angles in radians
+    ///
+    pub(crate) fn in_radians(&self) -> Settings {
+        Settings { angle_mode: AngleMode::Radians, ..self.clone() }
+    }
 }
 
 impl Session {
@@ -30,6 +173,7 @@ impl Session {
         // let variable_heap: HashMap<String, Number> = ;
         Session {
             variable_heap: Rc::new(RefCell::new(Session::init_local_heap())),
+            settings: Rc::new(RefCell::new(Settings::default())),
         }
     }
 
@@ -39,6 +183,128 @@ impl Session {
     pub fn process<'a>(&'a self, line: &'a str) -> RpnResolver<'_> {
         let clone = Rc::clone(&self.variable_heap); // clones the Rc pointer, not the whole heap!
         RpnResolver::parse_with_borrowed_heap(line, clone)
+            .with_settings(Rc::clone(&self.settings))
+    }
+
+    /// Sets how the divisions between natural numbers and the decimal numbers are carried out by
+    /// all the [`RpnResolver`]s of the session. It's [`NumericMode::Float`] by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::{NumericMode, RoundingMode, Session};
+    /// let session = Session::init();
+    /// session.set_numeric_mode(NumericMode::Rational);
+    ///
+    /// let result = session.process("10/4").resolve().unwrap();
+    /// assert_eq!(format!("{result}"), "5/2");
+    /// assert_eq!(format!("{result:#}"), "2.5");
+    ///
+    /// session.set_numeric_mode(NumericMode::Decimal { precision: 50, rounding: RoundingMode::HalfEven });
+    ///
+    /// let result = session.process("0.1 + 0.2 - 0.3").resolve().unwrap();
+    /// assert_eq!(format!("{result}"), "0");
+    /// ```
+    ///
+    pub fn set_numeric_mode(&self, mode: NumericMode) {
+        self.settings.borrow_mut().numeric_mode = mode;
+    }
+
+    /// Turns the interval mode on, or off with [`None`]. In interval mode every variable `x` becomes
+    /// the interval `[x - |x|*tolerance, x + |x|*tolerance]` ([`Number::Interval`]), so that any
+    /// formula gives back guaranteed bounds of its result, and a literal with two values
+    /// such as `[1.9, 2.1]` is an interval rather than a vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::Session;
+    /// let session = Session::init();
+    /// session.set_interval_mode(Some(0.01));
+    /// session.set("r", 10);
+    ///
+    /// let area = session.process("pi * r^2").resolve().unwrap();
+    /// let area: f64 = area.into();
+    /// assert!((area - 314.19).abs() < 0.01);
+    /// ```
+    ///
+    pub fn set_interval_mode(&self, tolerance: Option<f64>) {
+        self.settings.borrow_mut().interval_tolerance = tolerance;
+    }
+
+    /// Sets the [`Limits`] on the resources that the evaluation of an expression can take
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::{Limits, Session};
+    /// let session = Session::init();
+    /// session.set_limits(Limits { max_bits: Some(4096), ..Limits::default() });
+    ///
+    /// assert!(session.process("9^9^9^9").resolve().is_err());
+    /// assert!(session.process("10000000!").resolve().is_err());
+    /// ```
+    ///
+    pub fn set_limits(&self, limits: Limits) {
+        self.settings.borrow_mut().limits = limits;
+    }
+
+    /// Sets what happens when an operator or a function gives back NaN or an infinity.
+    /// It's [`FloatPolicy::Propagate`] by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::{FloatPolicy, Session};
+    /// let session = Session::init();
+    /// assert_eq!(format!("{}", session.process("ln(0)").resolve().unwrap()), "-inf");
+    ///
+    /// session.set_float_policy(FloatPolicy::ErrorOnNonFinite);
+    /// let err = session.process("1 + ln(0)").resolve().unwrap_err();
+    /// assert_eq!(err.to_string(), "Runtime error: 'ln' gave -inf.");
+    /// ```
+    ///
+    pub fn set_float_policy(&self, policy: FloatPolicy) {
+        self.settings.borrow_mut().float_policy = policy;
+    }
+
+    /// Sets the unit of the angles of the trigonometric functions. It's [`AngleMode::Radians`] by default.
+    /// Whatever the mode, '30°' is an angle in degrees, while `deg(x)` and `rad(x)` convert
+    /// radians to degrees and back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::{session::{AngleMode, Session}, token::Number};
+    /// let session = Session::init();
+    /// session.set_angle_mode(AngleMode::Degrees);
+    ///
+    /// assert_eq!(session.process("sin(30)").resolve().unwrap(), Number::DecimalNumber(0.5));
+    /// assert_eq!(session.process("cos(90°)").resolve().unwrap(), Number::DecimalNumber(0.));
+    /// assert_eq!(session.process("atan(1)").resolve().unwrap(), Number::DecimalNumber(45.));
+    /// ```
+    ///
+    pub fn set_angle_mode(&self, mode: AngleMode) {
+        self.settings.borrow_mut().angle_mode = mode;
+    }
+
+    /// Reseeds the generator of `rand()`, `randint(a, b)` and `randn()`, so that the same seed
+    /// gives back the same sequence of random numbers. It's seeded from the clock by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::Session;
+    /// let session = Session::init();
+    /// session.seed(42);
+    /// let first = session.process("randint(1, 6)").resolve().unwrap();
+    ///
+    /// session.seed(42);
+    /// assert_eq!(session.process("randint(1, 6)").resolve().unwrap(), first);
+    /// ```
+    ///
+    pub fn seed(&self, seed: u64) {
+        *self.settings.borrow().random.borrow_mut() = Generator::seeded(seed);
     }
 
     /// Creates a Variables heap (name-value)
@@ -48,6 +314,8 @@ impl Session {
         let mut local_heap: HashMap<String, Number> = HashMap::new();
         local_heap.insert("pi".to_string(), Number::DecimalNumber(std::f64::consts::PI));
         local_heap.insert("e".to_string(), Number::DecimalNumber(std::f64::consts::E));
+        // a breaking change for the expressions that used 'i' as a variable, that was 0 until assigned
+        local_heap.insert("i".to_string(), Number::Complex(Complex64::i()));
         local_heap
     }
 
@@ -76,6 +344,19 @@ impl Session {
             .borrow_mut()
             .insert(key.to_string(), Number::DecimalNumber(value));
     }
+
+    /// Declares and saves a new interval variable ([`Number::Interval`])
+    ///
+    /// Example
+    /// ``
+    ///     session.set_interval("x", Interval::new(1.9, 2.1).unwrap());
+    /// ``
+    ///
+    pub fn set_interval(&self, key: &str, value: Interval) {
+        self.variable_heap
+            .borrow_mut()
+            .insert(key.to_string(), Number::Interval(value));
+    }
 }
 
 
@@ -91,6 +372,17 @@ mod tests {
         assert_eq!(resolver.resolve().unwrap(), Number::DecimalNumber(-5.0));
     }
 
+    #[test]
+    fn test_rational_mode() {
+        let session = Session::init();
+        session.set_numeric_mode(NumericMode::Rational);
+        let mut resolver: RpnResolver = session.process("1/3*3");
+        assert_eq!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(1)));
+
+        session.set_numeric_mode(NumericMode::Float);
+        assert_ne!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(1)));
+    }
+
     #[test]
     fn test_session_set() {
         let session = Session::init();
@@ -0,0 +1,387 @@
+use std::f64::consts::{E, FRAC_2_SQRT_PI, LN_2, PI};
+
+use crate::{error::MathError, token::MathFunction};
+
+/// The coefficients of the Lanczos approximation with g = 7 and n = 9
+///
+const LANCZOS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+/// The terms of the series of Borwein for the Dirichlet eta function
+///
+const BORWEIN_TERMS: f64 = 30.;
+
+/// Beyond this argument, the Bessel functions take their asymptotic expansion
+///
+const BESSEL_ASYMPTOTIC: f64 = 1_000.;
+const BESSEL_MAX_ORDER: f64 = 1e6;
+
+/// Whether a [`MathFunction`] is one of the special functions
+///
+pub(crate) fn is_special(fun: MathFunction) -> bool {
+    matches!(
+        fun,
+        MathFunction::Gamma
+            | MathFunction::LGamma
+            | MathFunction::Beta
+            | MathFunction::Erf
+            | MathFunction::Erfc
+            | MathFunction::Zeta
+            | MathFunction::J0
+            | MathFunction::J1
+            | MathFunction::Jn
+            | MathFunction::LambertW
+    )
+}
+
+/// Evaluates a special [`MathFunction`] of real arguments: `gamma`, `lgamma`, `beta`, `erf`, `erfc`,
+/// `zeta`, `j0`, `j1`, `jn` and `lambertw`. The poles and the arguments out of the real domain
+/// are a [`MathError::Domain`].
+///
+#[allow(clippy::float_cmp)] // the pole of zeta
+pub(crate) fn function(fun: MathFunction, args: &[f64]) -> Result<f64, MathError> {
+    let domain = |value: f64| MathError::domain(fun, value);
+    let pole = |x: f64| x <= 0. && x.fract() == 0.;
+    let res = match (fun, args) {
+        (MathFunction::Gamma | MathFunction::LGamma, [x]) if pole(*x) => return Err(domain(*x)),
+        (MathFunction::Gamma, [x]) => gamma(*x),
+        (MathFunction::LGamma, [x]) => lgamma(*x),
+        (MathFunction::Beta, [a, b]) if pole(*a) || pole(*b) => return Err(domain(if pole(*a) { *a } else { *b })),
+        (MathFunction::Beta, [a, b]) => beta(*a, *b),
+        (MathFunction::Erf, [x]) => erf(*x),
+        (MathFunction::Erfc, [x]) => erfc(*x),
+        (MathFunction::Zeta, [s]) if *s == 1. => return Err(domain(*s)),
+        (MathFunction::Zeta, [s]) => zeta(*s),
+        (MathFunction::J0, [x]) => bessel_j(0, *x),
+        (MathFunction::J1, [x]) => bessel_j(1, *x),
+        (MathFunction::Jn, [n, _]) if n.fract() != 0. || n.abs() > BESSEL_MAX_ORDER => return Err(domain(*n)),
+        #[allow(clippy::cast_possible_truncation)]
+        (MathFunction::Jn, [n, x]) => bessel_j(*n as i64, *x),
+        (MathFunction::LambertW, [x]) => lambert_w(*x).ok_or_else(|| domain(*x))?,
+        _ => return Err(domain(args.first().copied().unwrap_or(f64::NAN))),
+    };
+    Ok(res)
+}
+
+/// The sum of the Lanczos series, for an argument already lowered by 1
+///
+fn lanczos_sum(x: f64) -> f64 {
+    let (sum, _) = LANCZOS[1..]
+        .iter()
+        .fold((LANCZOS[0], x), |(acc, d), c| (acc + c / (d + 1.), d + 1.));
+    sum
+}
+
+/// The gamma function, with the Lanczos approximation and the reflection formula
+/// for the arguments below 1/2. Its relative error is about 1e-15 for the small arguments,
+/// and grows with them up to 1e-13 close to the overflow beyond 171.
+/// The non-positive integers are poles, where it gives back not a number.
+///
+pub(crate) fn gamma(x: f64) -> f64 {
+    if x <= 0. && x.fract() == 0. {
+        return f64::NAN;
+    }
+    if x < 0.5 {
+        return PI / ((PI * x).sin() * gamma(1. - x));
+    }
+    let x = x - 1.;
+    let t = x + 7.5;
+    // the power is split in two halves, so that it doesn't overflow before the exponential shrinks it
+    let half = t.powf((x + 0.5) * 0.5);
+    (2. * PI).sqrt() * half * (half * (-t).exp()) * lanczos_sum(x)
+}
+
+/// The logarithm of the absolute value of the gamma function, that doesn't overflow for large arguments.
+/// Its error is about 1e-15, relative but for the absolute one close to its zeros at 1 and 2.
+///
+pub(crate) fn lgamma(x: f64) -> f64 {
+    if x <= 0. && x.fract() == 0. {
+        return f64::INFINITY;
+    }
+    if x < 0.5 {
+        return (PI / (PI * x).sin().abs()).ln() - lgamma(1. - x);
+    }
+    let x = x - 1.;
+    let t = x + 7.5;
+    0.5 * (2. * PI).ln() + (x + 0.5) * t.ln() - t + lanczos_sum(x).ln()
+}
+
+/// The sign of the gamma function: negative between the odd negative integers and the even ones below them
+///
+fn gamma_sign(x: f64) -> f64 {
+    if x > 0. || x.floor() % 2. == 0. {
+        1.
+    } else {
+        -1.
+    }
+}
+
+/// The beta function, as a ratio of gamma functions, or of their logarithms when they would overflow.
+/// Its relative error is about 1e-14, and about 1e-12 out of the logarithms.
+///
+pub(crate) fn beta(a: f64, b: f64) -> f64 {
+    if a.abs() < 170. && b.abs() < 170. && (a + b).abs() < 170. {
+        return gamma(a) * gamma(b) / gamma(a + b);
+    }
+    let sign = gamma_sign(a) * gamma_sign(b) * gamma_sign(a + b);
+    sign * (lgamma(a) + lgamma(b) - lgamma(a + b)).exp()
+}
+
+/// The error function, with its series of positive terms below 3 and out of `erfc` above.
+/// Its relative error is about 1e-15.
+///
+pub(crate) fn erf(x: f64) -> f64 {
+    if x.abs() >= 3. {
+        return x.signum() * (1. - erfc(x.abs()));
+    }
+    // erf(x) = 2/sqrt(pi) * exp(-x^2) * sum of (2x^2)^n * x / (1*3*5*...*(2n+1))
+    let (mut term, mut sum) = (x, x);
+    let mut n = 0.;
+    while term.abs() > sum.abs() * 1e-17 {
+        n += 1.;
+        term *= 2. * x * x / (2. * n + 1.);
+        sum += term;
+    }
+    FRAC_2_SQRT_PI * (-x * x).exp() * sum
+}
+
+/// The complementary error function, 1 - erf(x), with a continued fraction above 2, so that
+/// it keeps its relative precision in the tail. Below 2 its relative error is about 1e-13,
+/// and about 1e-15 above.
+///
+pub(crate) fn erfc(x: f64) -> f64 {
+    if x < 2. {
+        return 1. - erf(x);
+    }
+    if x.is_infinite() {
+        return 0.;
+    }
+    // erfc(x) = exp(-x^2)/sqrt(pi) / (x + (1/2)/(x + 1/(x + (3/2)/(x + ...)))), by the method of Lentz
+    let tiny = 1e-300;
+    let (mut fraction, mut c, mut d) = (x, x, 0.);
+    for k in 1..500 {
+        let numerator = f64::from(k) / 2.;
+        d = x + numerator * d;
+        d = 1. / if d == 0. { tiny } else { d };
+        c = x + numerator / c;
+        if c == 0. {
+            c = tiny;
+        }
+        let delta = c * d;
+        fraction *= delta;
+        if (delta - 1.).abs() < 1e-16 {
+            break;
+        }
+    }
+    (-x * x).exp() / (PI.sqrt() * fraction)
+}
+
+/// The Riemann zeta function, out of the Dirichlet eta function summed with the series of Borwein
+/// from 1/2 on, and with the functional equation below. Its relative error is about 1e-14,
+/// and grows to 1e-12 close to the pole in 1.
+///
+pub(crate) fn zeta(s: f64) -> f64 {
+    if s < 0. && s % 2. == 0. {
+        // the trivial zeros
+        return 0.;
+    }
+    if s == 0. {
+        return -0.5;
+    }
+    if s < 0.5 {
+        return 2f64.powf(s) * PI.powf(s - 1.) * (PI * s / 2.).sin() * gamma(1. - s) * zeta(1. - s);
+    }
+    // d_k = n * sum of (n+i-1)! 4^i / ((n-i)! (2i)!) for i up to k
+    let n = BORWEIN_TERMS;
+    let mut partial = vec![1.];
+    let (mut term, mut sum, mut i) = (1., 1., 0.);
+    while i < n {
+        i += 1.;
+        term *= (n + i - 1.) * 4. * (n - i + 1.) / ((2. * i) * (2. * i - 1.));
+        sum += term;
+        partial.push(sum);
+    }
+    // eta(s) = -1/d_n * sum of (-1)^k (d_k - d_n) / (k+1)^s for k below n
+    let (mut eta, mut sign, mut k) = (0., -1., 0.);
+    for d_k in &partial[..partial.len() - 1] {
+        k += 1.;
+        eta += sign * (d_k - sum) / f64::powf(k, s);
+        sign = -sign;
+    }
+    eta /= sum;
+    // zeta(s) = eta(s) / (1 - 2^(1-s))
+    eta / -((1. - s) * LN_2).exp_m1()
+}
+
+/// The Bessel function of the first kind of integer order, with the backward recurrence of Miller,
+/// normalized by 1 = J0 + 2*(J2 + J4 + ...), and with its asymptotic expansion for the large arguments.
+/// Its absolute error is about 1e-15, and 1e-13 beyond 1000.
+///
+pub(crate) fn bessel_j(n: i64, x: f64) -> f64 {
+    // J(-n, x) = (-1)^n J(n, x) = J(n, -x)
+    let sign = if n % 2 != 0 && (n < 0) != (x < 0.) { -1. } else { 1. };
+    let (n, x) = (n.unsigned_abs(), x.abs());
+    if x == 0. {
+        return if n == 0 { 1. } else { 0. };
+    }
+    if !x.is_finite() {
+        return if x.is_nan() { x } else { 0. };
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let order = n as f64;
+    if x > BESSEL_ASYMPTOTIC && x > order * order {
+        return sign * bessel_asymptotic(order, x);
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let top = {
+        let start = order.max(x.ceil());
+        2 * ((start + 20. + (40. * start).sqrt()) as u64 / 2)
+    };
+    let (mut next, mut current) = (0., 1e-30);
+    let (mut res, mut norm) = (0., 0.);
+    for k in (1..=top).rev() {
+        #[allow(clippy::cast_precision_loss)]
+        let previous = 2. * k as f64 / x * current - next;
+        (next, current) = (current, previous);
+        if current.abs() > 1e250 {
+            // rescales before the overflow
+            (next, current, res, norm) = (next * 1e-250, current * 1e-250, res * 1e-250, norm * 1e-250);
+        }
+        // current is now J(k-1)
+        if k - 1 == n {
+            res = current;
+        }
+        if (k - 1) % 2 == 0 && k > 1 {
+            norm += 2. * current;
+        }
+    }
+    norm += current;
+    sign * res / norm
+}
+
+/// The asymptotic expansion of Hankel: J(n, x) = sqrt(2/(pi x)) (P cos(w) - Q sin(w)), with w = x - (n/2 + 1/4) pi
+///
+fn bessel_asymptotic(n: f64, x: f64) -> f64 {
+    let mu = 4. * n * n;
+    let (mut even, mut odd) = (1., 0.);
+    let mut term: f64 = 1.;
+    for k in 1..60u8 {
+        let j = f64::from(k);
+        let next = term * (mu - (2. * j - 1.).powi(2)) / (j * 8. * x);
+        if next.abs() > term.abs() || next.abs() < 1e-17 {
+            break;
+        }
+        term = next;
+        // the terms alternate between Q and P, with signs +, -, -, +
+        match k % 4 {
+            1 => odd += term,
+            2 => even -= term,
+            3 => odd -= term,
+            _ => even += term,
+        }
+    }
+    let phase = x - (n / 2. + 0.25) * PI;
+    (2. / (PI * x)).sqrt() * (even * phase.cos() - odd * phase.sin())
+}
+
+/// The principal branch of the Lambert W function, the solution of w * exp(w) = x for w >= -1,
+/// with the iterations of Halley. Its relative error is about 1e-15.
+/// Returns [`None`] below -1/e, out of its real domain.
+///
+pub(crate) fn lambert_w(x: f64) -> Option<f64> {
+    let branch_point = -1. / E;
+    if x < branch_point {
+        return None;
+    }
+    #[allow(clippy::float_cmp)] // the branch point itself
+    if x == branch_point {
+        return Some(-1.);
+    }
+    if x == 0. || !x.is_finite() {
+        return Some(x);
+    }
+    let mut w = if x < -0.25 {
+        // the series about the branch point
+        let p = (2. * (E * x + 1.)).sqrt();
+        -1. + p - p * p / 3. + 11. / 72. * p * p * p
+    } else if x < 3. {
+        x.ln_1p()
+    } else {
+        let (l1, l2) = (x.ln(), x.ln().ln());
+        l1 - l2 + l2 / l1
+    };
+    for _ in 0..50 {
+        let ew = w.exp();
+        let f = w * ew - x;
+        let next = w - f / (ew * (w + 1.) - (w + 2.) * f / (2. * w + 2.));
+        if (next - w).abs() <= 1e-16 * next.abs() || !next.is_finite() {
+            return Some(next);
+        }
+        w = next;
+    }
+    Some(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(value: f64, expected: f64, tolerance: f64) {
+        assert!((value - expected).abs() <= tolerance * expected.abs().max(1.), "{value} != {expected}");
+    }
+
+    #[test]
+    fn test_gamma() {
+        assert!((gamma(5.) - 24.).abs() < 1e-12);
+        assert!((gamma(0.5) - PI.sqrt()).abs() < 1e-14);
+        assert!((gamma(-0.5) + 2. * PI.sqrt()).abs() < 1e-13);
+        assert!(gamma(-2.).is_nan());
+        close(gamma(150.), 3.808_922_637_630_569_7e260, 1e-13);
+        close(lgamma(1000.), 5_905.220_423_209_181, 1e-15);
+        close(lgamma(-2.5), -0.056_243_716_497_674_05, 1e-13);
+        close(beta(2., 3.), 1. / 12., 1e-15);
+        close(beta(200., 300.), (lgamma(200.) + lgamma(300.) - lgamma(500.)).exp(), 1e-12);
+    }
+
+    #[test]
+    fn test_erf() {
+        close(erf(0.5), 0.520_499_877_813_046_5, 1e-15);
+        close(erf(-3.5), -0.999_999_256_901_627_7, 1e-15);
+        close(erfc(5.), 1.537_459_794_428_034_8e-12, 1e-14);
+        assert_eq!(erf(0.), 0.);
+    }
+
+    #[test]
+    fn test_zeta() {
+        close(zeta(2.), PI * PI / 6., 1e-14);
+        close(zeta(-1.), -1. / 12., 1e-14);
+        close(zeta(0.), -0.5, 1e-14);
+        assert_eq!(zeta(-4.), 0.);
+    }
+
+    #[test]
+    fn test_bessel_j() {
+        close(bessel_j(0, 1.), 0.765_197_686_557_966_6, 1e-15);
+        close(bessel_j(1, -2.), -0.576_724_807_756_873_4, 1e-15);
+        close(bessel_j(-3, 2.), -0.128_943_249_474_402_05, 1e-15);
+        close(bessel_j(0, 2000.), 0.007_098_341_833_199_617, 1e-12);
+    }
+
+    #[test]
+    fn test_lambert_w() {
+        assert_eq!(lambert_w(-1.), None);
+        close(lambert_w(1.).unwrap(), 0.567_143_290_409_783_8, 1e-15);
+        close(lambert_w(-0.3).unwrap(), -0.489_402_227_180_214_9, 1e-14);
+        close(lambert_w(1e10).unwrap(), 20.028_685_413_304_95, 1e-15);
+    }
+}
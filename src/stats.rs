@@ -0,0 +1,189 @@
+use std::cmp::Ordering;
+
+use anyhow::anyhow;
+use num::{BigInt, One, Zero};
+
+use crate::{
+    error::MathError,
+    integer,
+    matrix::Scalar,
+    token::{MathFunction, Number, Operator},
+};
+
+static ORDER_ERR: &str = "Runtime error: The values can't be ordered:";
+
+/// Evaluates a variadic [`MathFunction`] over its arguments, with the rules of the resolver:
+/// the sum of natural numbers is exact, and so is the mean of fractions in rational mode.
+/// A matrix argument counts as all of its elements, so that 'max([1, 5, 3])' is 5.
+/// The first argument of 'percentile' is the percentage.
+///
+pub(crate) fn aggregate(fun: MathFunction, args: Vec<Number>, scalar: Scalar) -> anyhow::Result<Number> {
+    let mut args = args.into_iter();
+    let percentage = if fun == MathFunction::Percentile { args.next() } else { None };
+    let values: Vec<Number> = args
+        .flat_map(|n| match n {
+            Number::Matrix(m) => m.elements().to_vec(),
+            n => vec![n],
+        })
+        .collect();
+
+    let least = match fun {
+        MathFunction::Sum | MathFunction::Prod => (0, ""),
+        MathFunction::Var | MathFunction::StdDev => (2, "two values"),
+        _ => (1, "one value"),
+    };
+    if values.len() < least.0 {
+        return Err(anyhow!("Runtime error: Function '{fun}' needs at least {}.", least.1));
+    }
+
+    match fun {
+        MathFunction::Sum => sum(values, scalar),
+        MathFunction::Prod => values.into_iter().try_fold(Number::NaturalNumber(BigInt::one()), |acc, x| scalar(Operator::Mul, acc, x)),
+        MathFunction::Avg => mean(values, scalar),
+        MathFunction::Max => Ok(sorted(values)?.pop().expect("Not empty")),
+        MathFunction::Min => Ok(sorted(values)?.swap_remove(0)),
+        MathFunction::Median => {
+            let mut values = sorted(values)?;
+            let upper = values.split_off(values.len() / 2);
+            match values.pop() {
+                Some(lower) if upper.len() == values.len() + 1 => mean(vec![lower, upper[0].clone()], scalar),
+                _ => Ok(upper[0].clone()),
+            }
+        }
+        MathFunction::Mode => {
+            let values = sorted(values)?;
+            let mut best = (0, 0);
+            let mut start = 0;
+            for i in 1..=values.len() {
+                if i == values.len() || values[i].partial_cmp(&values[start]) != Some(Ordering::Equal) {
+                    if i - start > best.1 - best.0 {
+                        best = (start, i);
+                    }
+                    start = i;
+                }
+            }
+            Ok(values[best.0].clone())
+        }
+        MathFunction::Var => variance(values, scalar),
+        MathFunction::StdDev => scalar(Operator::Pow, variance(values, scalar)?, Number::DecimalNumber(0.5)),
+        MathFunction::Percentile => percentile(percentage.unwrap_or(Number::NaturalNumber(BigInt::zero())), &sorted(values)?, scalar),
+        _ => Err(anyhow!("This should never happen!")),
+    }
+}
+
+/// The sum of the values, from the first one, so that a sum of quantities keeps their unit
+///
+fn sum(values: Vec<Number>, scalar: Scalar) -> anyhow::Result<Number> {
+    let mut values = values.into_iter();
+    let Some(first) = values.next() else {
+        return Ok(Number::NaturalNumber(BigInt::zero()));
+    };
+    values.try_fold(first, |acc, x| scalar(Operator::Add, acc, x))
+}
+
+fn mean(values: Vec<Number>, scalar: Scalar) -> anyhow::Result<Number> {
+    let n = Number::NaturalNumber(BigInt::from(values.len()));
+    scalar(Operator::Div, sum(values, scalar)?, n)
+}
+
+/// The sample variance: the sum of the squared deviations from the mean, over n - 1
+///
+fn variance(values: Vec<Number>, scalar: Scalar) -> anyhow::Result<Number> {
+    let mean = mean(values.clone(), scalar)?;
+    let n = Number::NaturalNumber(BigInt::from(values.len() - 1));
+    let squares = values.into_iter().map(|x| {
+        let deviation = scalar(Operator::Sub, x, mean.clone())?;
+        scalar(Operator::Mul, deviation.clone(), deviation)
+    });
+    scalar(Operator::Div, sum(squares.collect::<anyhow::Result<Vec<Number>>>()?, scalar)?, n)
+}
+
+/// The values in ascending order, failing if any two of them can't be compared
+///
+fn sorted(mut values: Vec<Number>) -> anyhow::Result<Vec<Number>> {
+    let mut incomparable = None;
+    values.sort_by(|a, b| {
+        a.partial_cmp(b).unwrap_or_else(|| {
+            incomparable.get_or_insert_with(|| format!("{a} and {b}"));
+            Ordering::Equal
+        })
+    });
+    match incomparable {
+        Some(pair) => Err(anyhow!("{ORDER_ERR} {pair}.")),
+        None => Ok(values),
+    }
+}
+
+/// The p-th percentile of the sorted values: the value of rank p*(n-1)/100, interpolating linearly
+/// between the two closest ranks
+///
+fn percentile(p: Number, values: &[Number], scalar: Scalar) -> anyhow::Result<Number> {
+    let (zero, hundred) = (Number::NaturalNumber(BigInt::zero()), Number::NaturalNumber(BigInt::from(100)));
+    if !(p >= zero && p <= hundred) {
+        return Err(MathError::domain(MathFunction::Percentile, p).into());
+    }
+    let n = Number::NaturalNumber(BigInt::from(values.len() - 1));
+    let rank = scalar(Operator::Div, scalar(Operator::Mul, p, n)?, hundred)?;
+
+    let lower = integer::rounding(MathFunction::Floor, &rank).ok_or_else(|| MathError::domain(MathFunction::Percentile, &rank))?;
+    let index = integer::to_integer(&lower).and_then(|i| usize::try_from(i).ok()).unwrap_or_default();
+    let fraction = scalar(Operator::Sub, rank, lower)?;
+    if integer::to_integer(&fraction).is_some_and(|f| f.is_zero()) {
+        return Ok(values[index].clone());
+    }
+    let step = scalar(Operator::Sub, values[index + 1].clone(), values[index].clone())?;
+    scalar(Operator::Add, values[index].clone(), scalar(Operator::Mul, fraction, step)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use num::{BigRational, ToPrimitive};
+
+    use super::*;
+
+    fn exact(op: Operator, a: Number, b: Number) -> anyhow::Result<Number> {
+        let rational = |n: Number| match n {
+            Number::NaturalNumber(v) => BigRational::from_integer(v),
+            Number::Rational(r) => r,
+            _ => unreachable!(),
+        };
+        let (a, b) = (rational(a), rational(b));
+        Ok(Number::from_rational(match op {
+            Operator::Add => a + b,
+            Operator::Sub => a - b,
+            Operator::Mul => a * b,
+            Operator::Div => a / b,
+            _ => BigRational::from_float(a.to_f64().unwrap().powf(b.to_f64().unwrap())).unwrap(),
+        }))
+    }
+
+    fn naturals(values: &[i64]) -> Vec<Number> {
+        values.iter().map(|&v| Number::NaturalNumber(BigInt::from(v))).collect()
+    }
+
+    fn fraction(n: i64, d: i64) -> Number {
+        Number::from_rational(BigRational::new(BigInt::from(n), BigInt::from(d)))
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let stat = |fun, values: &[i64]| aggregate(fun, naturals(values), &exact).unwrap();
+        assert_eq!(stat(MathFunction::Sum, &[1, 2, 3]), fraction(6, 1));
+        assert_eq!(stat(MathFunction::Sum, &[]), fraction(0, 1));
+        assert_eq!(stat(MathFunction::Prod, &[2, 3, 4]), fraction(24, 1));
+        assert_eq!(stat(MathFunction::Prod, &[]), fraction(1, 1));
+        assert_eq!(stat(MathFunction::Avg, &[1, 2]), fraction(3, 2));
+        assert_eq!(stat(MathFunction::Max, &[3, 9, -1]), fraction(9, 1));
+        assert_eq!(stat(MathFunction::Min, &[3, 9, -1]), fraction(-1, 1));
+        assert_eq!(stat(MathFunction::Median, &[5, 1, 3]), fraction(3, 1));
+        assert_eq!(stat(MathFunction::Median, &[4, 1, 3, 2]), fraction(5, 2));
+        assert_eq!(stat(MathFunction::Mode, &[3, 1, 3, 1, 2]), fraction(1, 1));
+        assert_eq!(stat(MathFunction::Var, &[1, 2, 3, 4]), fraction(5, 3));
+        assert_eq!(stat(MathFunction::Percentile, &[25, 1, 2, 3, 4, 5]), fraction(2, 1));
+        assert_eq!(stat(MathFunction::Percentile, &[90, 1, 2, 3, 4]), fraction(37, 10));
+
+        assert!(aggregate(MathFunction::Avg, vec![], &exact).is_err());
+        assert!(aggregate(MathFunction::Var, naturals(&[1]), &exact).is_err());
+        assert!(aggregate(MathFunction::Percentile, naturals(&[101, 1, 2]), &exact).is_err());
+    }
+}
@@ -0,0 +1,843 @@
+use std::{cmp::Ordering, collections::VecDeque, fmt::Display};
+
+use anyhow::anyhow;
+use num::{BigInt, BigRational, FromPrimitive, One, Signed, ToPrimitive, Zero};
+
+use crate::{
+    parser::Span,
+    session::AngleMode,
+    token::{MathFunction, Number, Operator, Token},
+};
+
+static TREE_ERR: &str = "Runtime error: The expression can't be handled symbolically, because of";
+static DERIVATIVE_ERR: &str = "Runtime error: The derivative is not supported for";
+
+/// The most bits of an exact power worked out by the simplifier
+///
+const MAX_FOLDED_BITS: u64 = 4096;
+
+/// The powers of the distinct bases of a product, and its numeric coefficient
+///
+type Product<'a> = (Number, Vec<(Expr<'a>, Expr<'a>)>);
+
+/// A mathematical expression as a tree, built from the rpn expression of a
+/// [`RpnResolver`](crate::rpn_resolver::RpnResolver). It's printed back in infix notation,
+/// with the fewest brackets that keep its meaning, so that it can be parsed again.
+///
+/// # Examples
+///
+/// ```
+/// # use yarer::session::Session;
+/// let session = Session::init();
+/// let expr = session.process("x^3 + sin(2*x)").expression().unwrap();
+///
+/// assert_eq!(expr.derivative("x").unwrap().to_string(), "3*x^2 + cos(2*x)*2");
+/// ```
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr<'a> {
+    /// A number
+    Number(Number),
+    /// A variable, or a constant such as `pi`
+    Variable(&'a str),
+    /// A prefix or a postfix operator: '-x', 'x!', 'x!!', 'x°' or 'x%'
+    Unary(Operator, Box<Expr<'a>>),
+    /// A binary operator
+    Binary(Operator, Box<Expr<'a>>, Box<Expr<'a>>),
+    /// A function and its arguments
+    Function(MathFunction, Vec<Expr<'a>>),
+}
+
+impl<'a> Expr<'a> {
+    /// Builds the tree of a rpn expression, failing on the tokens that have no place in it,
+    /// as the matrix literals and the expressions bound to a variable
+    ///
+    pub(crate) fn from_rpn(rpn_expr: &VecDeque<(Token<'a>, Span)>) -> anyhow::Result<Expr<'a>> {
+        let mut stack: Vec<Expr<'a>> = Vec::new();
+        let malformed = || anyhow!("Runtime Error: The mathematical expression is malformed.");
+        for (t, _) in rpn_expr {
+            let (node, arity) = match t {
+                Token::Operand(n) => (Expr::Number(n.clone()), 0),
+                Token::Variable(v) => (Expr::Variable(v), 0),
+                Token::Operator(op @ (Operator::Une | Operator::Fac | Operator::DoubleFac | Operator::Degree | Operator::Percent)) => (Expr::Unary(*op, Box::new(Expr::zero())), 1),
+                Token::Operator(op) => (Expr::Binary(*op, Box::new(Expr::zero()), Box::new(Expr::zero())), 2),
+                Token::Function(fun) => (Expr::Function(*fun, Vec::new()), fun.arity()),
+                Token::Call(fun, count) if !fun.counts_arguments() && *count != fun.arity() => return Err(malformed()),
+                Token::Call(fun, count) if fun.lambda_arguments().is_none() => (Expr::Function(*fun, Vec::new()), *count),
+                // the expression of a variable bound by the call that follows it: an error at its call
+                Token::Lambda(..) => continue,
+                Token::Call(fun, _) => return Err(anyhow!("{TREE_ERR} '{}'.", fun.name())),
+                Token::MatrixLiteral(_) => return Err(anyhow!("{TREE_ERR} a matrix.")),
+                _ => return Err(anyhow!("{TREE_ERR} '{t}'.")),
+            };
+            let args = stack.split_off(stack.len().checked_sub(arity).ok_or_else(malformed)?);
+            stack.push(match node {
+                Expr::Unary(op, _) => Expr::Unary(op, Box::new(args.into_iter().next().ok_or_else(malformed)?)),
+                Expr::Binary(op, ..) => {
+                    let [left, right]: [Expr<'a>; 2] = args.try_into().map_err(|_| malformed())?;
+                    Expr::Binary(op, Box::new(left), Box::new(right))
+                }
+                Expr::Function(fun, _) => Expr::Function(fun, args),
+                leaf => leaf,
+            });
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(expr), true) => Ok(expr),
+            _ => Err(malformed()),
+        }
+    }
+
+    /// The rpn expression of the tree, where every token has the given span
+    ///
+    pub(crate) fn to_rpn(&self, span: &Span) -> VecDeque<(Token<'a>, Span)> {
+        let mut rpn_expr = VecDeque::new();
+        self.push_rpn(span, &mut rpn_expr);
+        rpn_expr
+    }
+
+    fn push_rpn(&self, span: &Span, rpn_expr: &mut VecDeque<(Token<'a>, Span)>) {
+        let token = match self {
+            Expr::Number(n) => Token::Operand(n.clone()),
+            Expr::Variable(v) => Token::Variable(v),
+            Expr::Unary(op, operand) => {
+                operand.push_rpn(span, rpn_expr);
+                Token::Operator(*op)
+            }
+            Expr::Binary(op, left, right) => {
+                left.push_rpn(span, rpn_expr);
+                right.push_rpn(span, rpn_expr);
+                Token::Operator(*op)
+            }
+            Expr::Function(fun, args) => {
+                for arg in args {
+                    arg.push_rpn(span, rpn_expr);
+                }
+                if fun.counts_arguments() {
+                    Token::Call(*fun, args.len())
+                } else {
+                    Token::Function(*fun)
+                }
+            }
+        };
+        rpn_expr.push_back((token, span.clone()));
+    }
+
+    /// Whether the value of the expression depends on the variable
+    ///
+    #[must_use]
+    pub fn depends_on(&self, var: &str) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Variable(v) => *v == var,
+            Expr::Unary(_, operand) => operand.depends_on(var),
+            Expr::Binary(_, left, right) => left.depends_on(var) || right.depends_on(var),
+            Expr::Function(_, args) => args.iter().any(|arg| arg.depends_on(var)),
+        }
+    }
+
+    /// The derivative of the expression with respect to a variable, as a new expression.
+    /// The trigonometric functions are derived as functions of radians.
+    ///
+    /// # Errors
+    ///
+    /// The derivative is not supported for the functions of integers, the matrices, the complex
+    /// numbers, and the functions whose derivative is not an elementary one, as `gamma`,
+    /// unless their arguments don't depend on the variable.
+    ///
+    pub fn derivative(&self, var: &str) -> anyhow::Result<Expr<'a>> {
+        self.derivative_in(var, AngleMode::Radians)
+    }
+
+    /// The derivative of the expression with respect to a variable, with the trigonometric functions
+    /// of angles in the given unit: in degrees, the derivative of 'sin(x)' is 'cos(x)*pi/180'.
+    ///
+    /// # Errors
+    ///
+    /// As [`Expr::derivative`].
+    ///
+    pub fn derivative_in(&self, var: &str, angle_mode: AngleMode) -> anyhow::Result<Expr<'a>> {
+        if !self.depends_on(var) {
+            return Ok(Expr::zero());
+        }
+        match self {
+            Expr::Number(_) => Ok(Expr::zero()),
+            Expr::Variable(_) => Ok(Expr::one()),
+            Expr::Unary(op, operand) => {
+                let d = operand.derivative_in(var, angle_mode)?;
+                match op {
+                    Operator::Une => Ok(neg(d)),
+                    Operator::Degree | Operator::Percent => Ok(Expr::Unary(*op, Box::new(d))),
+                    _ => Err(anyhow!("{DERIVATIVE_ERR} '{op}'.")),
+                }
+            }
+            Expr::Binary(op, left, right) => Self::binary_derivative(*op, left, right, var, angle_mode),
+            Expr::Function(fun, args) => Self::function_derivative(*fun, args, var, angle_mode),
+        }
+    }
+
+    fn binary_derivative(op: Operator, u: &Expr<'a>, v: &Expr<'a>, var: &str, angle_mode: AngleMode) -> anyhow::Result<Expr<'a>> {
+        let (du, dv) = (u.derivative_in(var, angle_mode)?, v.derivative_in(var, angle_mode)?);
+        let (u, v) = (u.clone(), v.clone());
+        match op {
+            Operator::Add => Ok(add(du, dv)),
+            Operator::Sub => Ok(sub(du, dv)),
+            Operator::Mul => Ok(add(mul(du, v), mul(u, dv))),
+            Operator::Div if !v.depends_on(var) => Ok(div(du, v)),
+            Operator::Div => Ok(div(sub(mul(du, v.clone()), mul(u, dv)), pow(v, Expr::natural(2)))),
+            // n*u^(n-1)*u'
+            Operator::Pow if !v.depends_on(var) => Ok(mul(mul(v.clone(), pow(u, minus_one(v))), du)),
+            // a^v*ln(a)*v'
+            Operator::Pow if !u.depends_on(var) => Ok(mul(mul(pow(u.clone(), v), function(MathFunction::Ln, u)), dv)),
+            // u^v*(v'*ln(u) + v*u'/u)
+            Operator::Pow => {
+                let ln = function(MathFunction::Ln, u.clone());
+                Ok(mul(pow(u.clone(), v.clone()), add(mul(dv, ln), div(mul(v, du), u))))
+            }
+            _ => Err(anyhow!("{DERIVATIVE_ERR} '{op}'.")),
+        }
+    }
+
+    fn function_derivative(fun: MathFunction, args: &[Expr<'a>], var: &str, angle_mode: AngleMode) -> anyhow::Result<Expr<'a>> {
+        let unsupported = || anyhow!("{DERIVATIVE_ERR} '{fun}'.");
+        match (fun, args) {
+            // linear in all their arguments
+            (MathFunction::Sum | MathFunction::Avg, _) => {
+                let derivatives = args.iter().map(|arg| arg.derivative_in(var, angle_mode)).collect::<anyhow::Result<Vec<Expr>>>()?;
+                Ok(Expr::Function(fun, derivatives))
+            }
+            (MathFunction::NRoot, [u, n]) if !n.depends_on(var) => {
+                let root = Expr::Function(fun, args.to_vec());
+                Ok(div(mul(root, u.derivative_in(var, angle_mode)?), mul(n.clone(), u.clone())))
+            }
+            (MathFunction::Jn, [n, u]) if !n.depends_on(var) => {
+                let order = |k| Expr::Binary(k, Box::new(n.clone()), Box::new(Expr::one()));
+                let jn = |n| Expr::Function(MathFunction::Jn, vec![n, u.clone()]);
+                Ok(mul(div(sub(jn(order(Operator::Sub)), jn(order(Operator::Add))), Expr::natural(2)), u.derivative_in(var, angle_mode)?))
+            }
+            (MathFunction::NormCdf | MathFunction::NormPdf, [u, mu, sigma]) if !mu.depends_on(var) && !sigma.depends_on(var) => {
+                let pdf = Expr::Function(MathFunction::NormPdf, args.to_vec());
+                let outer = match fun {
+                    MathFunction::NormCdf => pdf,
+                    _ => neg(mul(div(sub(u.clone(), mu.clone()), pow(sigma.clone(), Expr::natural(2))), pdf)),
+                };
+                Ok(mul(outer, u.derivative_in(var, angle_mode)?))
+            }
+            (_, [u]) => {
+                let outer = Self::outer_derivative(fun, u).ok_or_else(unsupported)?;
+                let inner = u.derivative_in(var, angle_mode)?;
+                // an angle of the trigonometric functions is pi/half_turn radians
+                let pi = || Expr::Variable("pi");
+                let outer = match (fun, angle_mode.half_turn()) {
+                    (MathFunction::Sin | MathFunction::Cos | MathFunction::Tan | MathFunction::Sec | MathFunction::Csc | MathFunction::Cot, Some(n)) => {
+                        div(mul(outer, pi()), Expr::natural(n.into()))
+                    }
+                    (MathFunction::ASin | MathFunction::ACos | MathFunction::ATan, Some(n)) => div(mul(outer, Expr::natural(n.into())), pi()),
+                    _ => outer,
+                };
+                match fun {
+                    // linear, whatever the angle mode
+                    MathFunction::Deg | MathFunction::Rad => Ok(function(fun, inner)),
+                    _ => Ok(mul(outer, inner)),
+                }
+            }
+            _ => Err(unsupported()),
+        }
+    }
+
+    /// The derivative of a function of one argument, at the argument
+    ///
+    fn outer_derivative(fun: MathFunction, u: &Expr<'a>) -> Option<Expr<'a>> {
+        let f = |fun| function(fun, u.clone());
+        let square = |e| pow(e, Expr::natural(2));
+        let u2 = || square(u.clone());
+        Some(match fun {
+            MathFunction::Sin => f(MathFunction::Cos),
+            MathFunction::Cos => neg(f(MathFunction::Sin)),
+            MathFunction::Tan => square(f(MathFunction::Sec)),
+            MathFunction::Sec => mul(f(MathFunction::Sec), f(MathFunction::Tan)),
+            MathFunction::Csc => neg(mul(f(MathFunction::Csc), f(MathFunction::Cot))),
+            MathFunction::Cot => neg(square(f(MathFunction::Csc))),
+            MathFunction::ASin => div(Expr::one(), function(MathFunction::Sqrt, sub(Expr::one(), u2()))),
+            MathFunction::ACos => neg(div(Expr::one(), function(MathFunction::Sqrt, sub(Expr::one(), u2())))),
+            MathFunction::ATan => div(Expr::one(), add(Expr::one(), u2())),
+            MathFunction::Sinh => f(MathFunction::Cosh),
+            MathFunction::Cosh => f(MathFunction::Sinh),
+            MathFunction::Tanh => div(Expr::one(), square(f(MathFunction::Cosh))),
+            MathFunction::ASinh => div(Expr::one(), function(MathFunction::Sqrt, add(u2(), Expr::one()))),
+            MathFunction::ACosh => div(Expr::one(), function(MathFunction::Sqrt, sub(u2(), Expr::one()))),
+            MathFunction::ATanh => div(Expr::one(), sub(Expr::one(), u2())),
+            MathFunction::Exp => f(MathFunction::Exp),
+            MathFunction::Ln => div(Expr::one(), u.clone()),
+            MathFunction::Log => div(Expr::one(), mul(u.clone(), function(MathFunction::Ln, Expr::natural(10)))),
+            MathFunction::Log2 => div(Expr::one(), mul(u.clone(), function(MathFunction::Ln, Expr::natural(2)))),
+            MathFunction::Sqrt => div(Expr::one(), mul(Expr::natural(2), f(MathFunction::Sqrt))),
+            MathFunction::Cbrt => div(Expr::one(), mul(Expr::natural(3), square(f(MathFunction::Cbrt)))),
+            MathFunction::Abs => f(MathFunction::Sign),
+            MathFunction::Fract | MathFunction::Deg | MathFunction::Rad => Expr::one(),
+            // constant but for their jumps
+            MathFunction::Floor
+            | MathFunction::Ceil
+            | MathFunction::Round
+            | MathFunction::Trunc
+            | MathFunction::Sign
+            | MathFunction::IsNan
+            | MathFunction::IsInf => Expr::zero(),
+            MathFunction::Erf | MathFunction::Erfc => {
+                let pi = Expr::Variable("pi");
+                let erf = div(mul(Expr::natural(2), function(MathFunction::Exp, neg(u2()))), function(MathFunction::Sqrt, pi));
+                if fun == MathFunction::Erf { erf } else { neg(erf) }
+            }
+            MathFunction::J0 => neg(f(MathFunction::J1)),
+            MathFunction::J1 => sub(f(MathFunction::J0), div(f(MathFunction::J1), u.clone())),
+            MathFunction::LambertW => div(f(MathFunction::LambertW), mul(u.clone(), add(Expr::one(), f(MathFunction::LambertW)))),
+            _ => return None,
+        })
+    }
+
+    /// A simpler expression with the same value: the arithmetic operations between numbers are worked out,
+    /// exactly on the natural numbers and the fractions, while the function calls and the postfix operators
+    /// are kept as 'sqrt(2)' and '5!', the identities as 'x*1' and 'x + 0' and the
+    /// annihilators as 'x*0' are dropped, the like terms of a sum are collected, as the powers of
+    /// a base in a product, and the integer powers of a power or of a product are expanded.
+    /// The divisors are assumed not to be zero, so that 'x/x' is 1, but the calls to an impure function,
+    /// as `rand()`, are never collected nor dropped, so that `rand() - rand()` and `0*rand()` stay as they are.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use yarer::session::Session;
+    /// let session = Session::init();
+    /// let expr = session.process("0*x + 1*y + (2+3)").expression().unwrap();
+    ///
+    /// assert_eq!(expr.simplify().to_string(), "y + 5");
+    /// ```
+    ///
+    #[must_use]
+    pub fn simplify(&self) -> Expr<'a> {
+        match self {
+            Expr::Number(_) | Expr::Variable(_) => self.clone(),
+            Expr::Unary(Operator::Une, _) | Expr::Binary(Operator::Add | Operator::Sub, ..) => {
+                let mut terms = Vec::new();
+                self.collect_terms(&natural_number(1), &mut terms);
+                sum_of(terms)
+            }
+            Expr::Binary(Operator::Mul | Operator::Div | Operator::Pow, ..) => {
+                let mut product = (natural_number(1), Vec::new());
+                self.collect_factors(&natural_number(1), &mut product);
+                product_of(product)
+            }
+            Expr::Unary(Operator::Percent, operand) => binary(Operator::Div, (**operand).clone(), Expr::natural(100)).simplify(),
+            Expr::Unary(op, operand) => Expr::Unary(*op, Box::new(operand.simplify())),
+            Expr::Binary(op, left, right) => binary(*op, left.simplify(), right.simplify()),
+            Expr::Function(fun, args) => Expr::Function(*fun, args.iter().map(Expr::simplify).collect()),
+        }
+    }
+
+    /// Adds the terms of a sum, times a factor, to the coefficients of the distinct terms
+    ///
+    fn collect_terms(&self, factor: &Number, terms: &mut Vec<(Number, Expr<'a>)>) {
+        match self {
+            Expr::Binary(Operator::Add, left, right) => {
+                left.collect_terms(factor, terms);
+                right.collect_terms(factor, terms);
+            }
+            Expr::Binary(Operator::Sub, left, right) => {
+                left.collect_terms(factor, terms);
+                right.collect_terms(&negated(factor), terms);
+            }
+            Expr::Unary(Operator::Une, operand) => operand.collect_terms(&negated(factor), terms),
+            term => {
+                let simple = term.simplify();
+                if simple != *term && matches!(simple, Expr::Binary(Operator::Add | Operator::Sub, ..) | Expr::Unary(Operator::Une, _)) {
+                    return simple.collect_terms(factor, terms);
+                }
+                let (coefficient, rest) = simple.split_coefficient();
+                let Some(scaled) = fold(Operator::Mul, factor, &coefficient) else {
+                    return terms.push((factor.clone(), scale(coefficient, rest)));
+                };
+                if let Some((sum, _)) = terms.iter_mut().find(|(_, other)| *other == rest && !rest.is_impure()) {
+                    if let Some(total) = fold(Operator::Add, sum, &scaled) {
+                        *sum = total;
+                        return;
+                    }
+                }
+                terms.push((scaled, rest));
+            }
+        }
+    }
+
+    /// The numeric coefficient of a simplified term, and the rest of it
+    ///
+    fn split_coefficient(self) -> (Number, Expr<'a>) {
+        match self {
+            Expr::Number(n) if is_real(&n) => (n, Expr::one()),
+            Expr::Binary(Operator::Mul, left, right) => {
+                let (coefficient, rest) = left.split_coefficient();
+                (coefficient, mul(rest, *right))
+            }
+            Expr::Binary(Operator::Div, left, right) => {
+                let (coefficient, rest) = left.split_coefficient();
+                (coefficient, binary(Operator::Div, rest, *right))
+            }
+            term => (natural_number(1), term),
+        }
+    }
+
+    /// Multiplies a product by the factors of the expression, raised to an integer exponent
+    ///
+    fn collect_factors(&self, exponent: &Number, product: &mut Product<'a>) {
+        match self {
+            Expr::Binary(Operator::Mul, left, right) => {
+                left.collect_factors(exponent, product);
+                right.collect_factors(exponent, product);
+            }
+            Expr::Binary(Operator::Div, left, right) => {
+                left.collect_factors(exponent, product);
+                right.collect_factors(&negated(exponent), product);
+            }
+            Expr::Unary(Operator::Une, operand) => {
+                Expr::natural(-1).collect_factors(exponent, product);
+                operand.collect_factors(exponent, product);
+            }
+            Expr::Binary(Operator::Pow, base, power) => {
+                let (base, power) = (base.simplify(), power.simplify());
+                if let (Expr::Number(base), Expr::Number(power)) = (&base, &power) {
+                    if let Some(n) = fold(Operator::Pow, base, power) {
+                        return Expr::Number(n).collect_factors(exponent, product);
+                    }
+                }
+                match power {
+                    Expr::Number(ref n) if exact(n).is_some_and(|n| n.is_integer()) => match fold(Operator::Mul, exponent, n) {
+                        Some(exponent) => base.collect_factors(&exponent, product),
+                        None => push_factor(binary(Operator::Pow, base, power), Expr::Number(exponent.clone()), product),
+                    },
+                    power => {
+                        let power = if is_one(exponent) { power } else { binary(Operator::Mul, Expr::Number(exponent.clone()), power).simplify() };
+                        push_factor(base, power, product);
+                    }
+                }
+            }
+            Expr::Number(n) => match fold(Operator::Pow, n, exponent).and_then(|power| fold(Operator::Mul, &product.0, &power)) {
+                Some(coefficient) => product.0 = coefficient,
+                None => push_factor(self.clone(), Expr::Number(exponent.clone()), product),
+            },
+            factor => {
+                let simple = factor.simplify();
+                if simple != *factor && matches!(simple, Expr::Binary(Operator::Mul | Operator::Div | Operator::Pow, ..) | Expr::Unary(Operator::Une, _) | Expr::Number(_)) {
+                    return simple.collect_factors(exponent, product);
+                }
+                push_factor(simple, Expr::Number(exponent.clone()), product);
+            }
+        }
+    }
+
+    fn natural(n: i64) -> Expr<'a> {
+        Expr::Number(Number::NaturalNumber(BigInt::from(n)))
+    }
+
+    fn zero() -> Expr<'a> {
+        Expr::natural(0)
+    }
+
+    fn one() -> Expr<'a> {
+        Expr::natural(1)
+    }
+
+    /// Whether the expression calls an impure function, whose value changes at each call
+    ///
+    fn is_impure(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Variable(_) => false,
+            Expr::Unary(_, operand) => operand.is_impure(),
+            Expr::Binary(_, left, right) => left.is_impure() || right.is_impure(),
+            Expr::Function(fun, args) => fun.is_impure() || args.iter().any(Expr::is_impure),
+        }
+    }
+
+    fn is_natural(&self, n: i64) -> bool {
+        matches!(self, Expr::Number(Number::NaturalNumber(v)) if *v == BigInt::from(n))
+    }
+
+    /// How tightly the expression binds, as the priority of its operator for the parser:
+    /// it's bracketed as an operand of an operator that binds tighter
+    ///
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Number(Number::NaturalNumber(n)) if n.is_negative() => 4,
+            Expr::Number(Number::DecimalNumber(v)) if v.is_sign_negative() => 4,
+            Expr::Number(Number::Rational(_)) => 2,
+            Expr::Number(Number::NaturalNumber(_) | Number::DecimalNumber(_)) | Expr::Variable(_) | Expr::Function(..) => 6,
+            Expr::Number(_) => 0,
+            Expr::Unary(Operator::Une, _) => 4,
+            Expr::Unary(..) => 5,
+            Expr::Binary(op, ..) => match op {
+                Operator::Add | Operator::Sub => 1,
+                Operator::Mul | Operator::Div | Operator::ElemMul | Operator::ElemDiv => 2,
+                Operator::Pow | Operator::ElemPow | Operator::PlusMinus => 3,
+                _ => 0,
+            },
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter<'_>, bracketed: bool) -> std::fmt::Result {
+        if bracketed {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+}
+
+impl Display for Expr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let precedence = self.precedence();
+        match self {
+            Expr::Number(Number::DecimalNumber(v)) if v.fract() == 0. => write!(f, "{v:.1}"),
+            Expr::Number(n) => write!(f, "{n}"),
+            Expr::Variable(v) => write!(f, "{v}"),
+            // '-a*b' is '(-a)*b', that has the same value
+            Expr::Unary(Operator::Une, operand) => {
+                write!(f, "-")?;
+                operand.fmt_operand(f, operand.precedence() < precedence && operand.precedence() != 2)
+            }
+            Expr::Unary(op, operand) => {
+                operand.fmt_operand(f, operand.precedence() < precedence)?;
+                write!(f, "{op}")
+            }
+            Expr::Binary(op, left, right) => {
+                let right_associative = matches!(op, Operator::Pow | Operator::ElemPow | Operator::To);
+                left.fmt_operand(f, left.precedence() < precedence || right_associative && left.precedence() == precedence)?;
+                match op {
+                    Operator::Add | Operator::Sub | Operator::Eql | Operator::To | Operator::PlusMinus => write!(f, " {op} ")?,
+                    _ => write!(f, "{op}")?,
+                }
+                right.fmt_operand(f, right.precedence() < precedence || !right_associative && right.precedence() == precedence)
+            }
+            Expr::Function(fun, args) => {
+                write!(f, "{}(", fun.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{}{arg}", if i == 0 { "" } else { ", " })?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn function(fun: MathFunction, u: Expr) -> Expr {
+    Expr::Function(fun, vec![u])
+}
+
+fn binary<'a>(op: Operator, left: Expr<'a>, right: Expr<'a>) -> Expr<'a> {
+    Expr::Binary(op, Box::new(left), Box::new(right))
+}
+
+fn neg(u: Expr) -> Expr {
+    match u {
+        u if u.is_natural(0) => u,
+        Expr::Unary(Operator::Une, operand) => *operand,
+        u => Expr::Unary(Operator::Une, Box::new(u)),
+    }
+}
+
+fn add<'a>(u: Expr<'a>, v: Expr<'a>) -> Expr<'a> {
+    match (u, v) {
+        (u, v) if u.is_natural(0) => v,
+        (u, v) if v.is_natural(0) => u,
+        (u, Expr::Unary(Operator::Une, v)) => binary(Operator::Sub, u, *v),
+        (u, v) => binary(Operator::Add, u, v),
+    }
+}
+
+fn sub<'a>(u: Expr<'a>, v: Expr<'a>) -> Expr<'a> {
+    match (u, v) {
+        (u, v) if v.is_natural(0) => u,
+        (u, v) if u.is_natural(0) => neg(v),
+        (u, Expr::Unary(Operator::Une, v)) => binary(Operator::Add, u, *v),
+        (u, v) => binary(Operator::Sub, u, v),
+    }
+}
+
+fn mul<'a>(u: Expr<'a>, v: Expr<'a>) -> Expr<'a> {
+    match (u, v) {
+        (u, v) if u.is_natural(0) && !v.is_impure() => u,
+        (u, v) if v.is_natural(0) && !u.is_impure() => v,
+        (u, v) if u.is_natural(1) => v,
+        (u, v) if v.is_natural(1) => u,
+        (Expr::Unary(Operator::Une, u), v) => neg(mul(*u, v)),
+        (u, Expr::Unary(Operator::Une, v)) => neg(mul(u, *v)),
+        (u, v) => binary(Operator::Mul, u, v),
+    }
+}
+
+fn div<'a>(u: Expr<'a>, v: Expr<'a>) -> Expr<'a> {
+    match (u, v) {
+        (u, v) if u.is_natural(0) && !v.is_impure() => u,
+        (u, v) if v.is_natural(1) => u,
+        (Expr::Unary(Operator::Une, u), v) => neg(div(*u, v)),
+        (u, v) => binary(Operator::Div, u, v),
+    }
+}
+
+fn pow<'a>(u: Expr<'a>, v: Expr<'a>) -> Expr<'a> {
+    match (u, v) {
+        (u, v) if v.is_natural(0) && !u.is_impure() => Expr::one(),
+        (u, v) if v.is_natural(1) => u,
+        (u, v) => binary(Operator::Pow, u, v),
+    }
+}
+
+/// The exponent less one, worked out when it's a natural number
+///
+fn minus_one(n: Expr) -> Expr {
+    match n {
+        Expr::Number(Number::NaturalNumber(n)) => Expr::Number(Number::NaturalNumber(n - BigInt::one())),
+        n => sub(n, Expr::one()),
+    }
+}
+
+fn natural_number(n: i64) -> Number {
+    Number::NaturalNumber(BigInt::from(n))
+}
+
+fn exact(n: &Number) -> Option<BigRational> {
+    match n {
+        Number::NaturalNumber(v) => Some(BigRational::from_integer(v.clone())),
+        Number::Rational(v) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+fn is_real(n: &Number) -> bool {
+    matches!(n, Number::NaturalNumber(_) | Number::Rational(_) | Number::DecimalNumber(_))
+}
+
+fn is_one(n: &Number) -> bool {
+    n.partial_cmp(&natural_number(1)) == Some(Ordering::Equal)
+}
+
+fn negated(n: &Number) -> Number {
+    fold(Operator::Mul, n, &natural_number(-1)).unwrap_or_else(|| n.clone())
+}
+
+/// The value of an operator between two real numbers: exact on the natural numbers and the fractions,
+/// but for the powers of a fractional exponent, and a float if either is a float. It's [`None`]
+/// when it's not defined or not finite, or when the exact power would be too large.
+///
+fn fold(op: Operator, a: &Number, b: &Number) -> Option<Number> {
+    match (exact(a), exact(b)) {
+        (Some(x), Some(y)) => {
+            let res = match op {
+                Operator::Add => x + y,
+                Operator::Sub => x - y,
+                Operator::Mul => x * y,
+                Operator::Div if !y.is_zero() => x / y,
+                Operator::Pow if y.is_integer() && !(x.is_zero() && y.is_negative()) => {
+                    let exponent = y.to_integer().to_i32()?;
+                    if (x.numer().bits() + x.denom().bits()) * u64::from(exponent.unsigned_abs()) > MAX_FOLDED_BITS {
+                        return None;
+                    }
+                    x.pow(exponent)
+                }
+                _ => return None,
+            };
+            Some(Number::from_rational(res))
+        }
+        _ if is_real(a) && is_real(b) => {
+            let (x, y) = (a.to_f64_lossy(), b.to_f64_lossy());
+            let res = match op {
+                Operator::Add => x + y,
+                Operator::Sub => x - y,
+                Operator::Mul => x * y,
+                Operator::Div => x / y,
+                Operator::Pow => x.powf(y),
+                _ => return None,
+            };
+            res.is_finite().then_some(Number::DecimalNumber(res))
+        }
+        _ => None,
+    }
+}
+
+/// A term times its coefficient
+///
+fn scale(coefficient: Number, term: Expr) -> Expr {
+    match term {
+        term if term.is_natural(1) => Expr::Number(coefficient),
+        term if is_one(&coefficient) => term,
+        term if is_one(&negated(&coefficient)) => neg(term),
+        Expr::Binary(op @ (Operator::Mul | Operator::Div), left, right) => binary(op, scale(coefficient, *left), *right),
+        term => binary(Operator::Mul, Expr::Number(coefficient), term),
+    }
+}
+
+/// The sum of the terms times their coefficients, with the constant term last
+///
+fn sum_of(terms: Vec<(Number, Expr)>) -> Expr {
+    let zero = natural_number(0);
+    let (constants, terms): (Vec<_>, Vec<_>) = terms.into_iter()
+        .filter(|(coefficient, term)| coefficient.partial_cmp(&zero) != Some(Ordering::Equal) || term.is_impure())
+        .partition(|(_, term)| term.is_natural(1));
+    terms.into_iter().chain(constants).fold(None, |sum, (coefficient, term)| {
+        Some(match sum {
+            None => scale(coefficient, term),
+            Some(sum) if coefficient.partial_cmp(&zero) == Some(Ordering::Less) => binary(Operator::Sub, sum, scale(negated(&coefficient), term)),
+            Some(sum) => binary(Operator::Add, sum, scale(coefficient, term)),
+        })
+    }).unwrap_or_else(Expr::zero)
+}
+
+/// Raises a base to a power in a product, adding up the powers of the same base
+///
+fn push_factor<'a>(base: Expr<'a>, power: Expr<'a>, product: &mut Product<'a>) {
+    match product.1.iter_mut().find(|(other, _)| *other == base && !base.is_impure()) {
+        Some((_, total)) => *total = integral(binary(Operator::Add, total.clone(), power).simplify()),
+        None => product.1.push((base, integral(power))),
+    }
+}
+
+/// An exponent that is a float with an integer value, as the '1.0' of '(x^0.5)^2', as a natural number
+///
+fn integral(power: Expr) -> Expr {
+    match power {
+        Expr::Number(Number::DecimalNumber(v)) if v.fract() == 0. => match BigInt::from_f64(v) {
+            Some(n) => Expr::Number(Number::NaturalNumber(n)),
+            None => power,
+        },
+        power => power,
+    }
+}
+
+/// The coefficient times the powers of the product, those with a negative exponent as divisors,
+/// in the order of their bases
+///
+fn product_of((coefficient, mut factors): Product) -> Expr {
+    let zero = natural_number(0);
+    if coefficient.partial_cmp(&zero) == Some(Ordering::Equal) && !factors.iter().any(|(base, _)| base.is_impure()) {
+        return Expr::Number(coefficient);
+    }
+    factors.sort_by_cached_key(|(base, _)| base.to_string());
+    let (mut numerator, mut denominator) = (Expr::one(), Expr::one());
+    for (base, power) in factors {
+        match power {
+            Expr::Number(ref n) if n.partial_cmp(&zero) == Some(Ordering::Less) => {
+                denominator = mul(denominator, pow(base, Expr::Number(negated(n))));
+            }
+            power if base.is_natural(1) || (power.is_natural(0) && !base.is_impure()) => (),
+            power => numerator = mul(numerator, pow(base, power)),
+        }
+    }
+    scale(coefficient, div(numerator, denominator))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::{AngleMode, Session};
+
+    fn derivative(expr: &str, var: &str) -> String {
+        let session = Session::init();
+        let expr = session.process(expr).expression().unwrap();
+        expr.derivative(var).unwrap().to_string()
+    }
+
+    fn infix(expr: &str) -> String {
+        Session::init().process(expr).expression().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_infix() {
+        assert_eq!(infix("1 + 2*3"), "1 + 2*3");
+        assert_eq!(infix("(1 + 2)*3"), "(1 + 2)*3");
+        assert_eq!(infix("a - (b - c)"), "a - (b - c)");
+        assert_eq!(infix("(a - b) - c"), "a - b - c");
+        assert_eq!(infix("a / (b * c)"), "a/(b*c)");
+        assert_eq!(infix("2^3^2"), "2^3^2");
+        assert_eq!(infix("(2^3)^2"), "(2^3)^2");
+        assert_eq!(infix("-(x^2)"), "-(x^2)");
+        assert_eq!(infix("-x^2"), "-x^2");
+        assert_eq!(infix("(x + 1)!"), "(x + 1)!");
+        assert_eq!(infix("max(1, x, sin(y))"), "max(1, x, sin(y))");
+        assert_eq!(infix("atan(1) + 2.5 + 3.0"), "atan(1) + 2.5 + 3.0");
+        assert!(Session::init().process("[1, 2]").expression().is_err());
+        assert!(Session::init().process("sumover(i, 1, 3, i*x)").expression().is_err());
+        assert_eq!(infix("sum(1, x, x)"), "sum(1, x, x)");
+    }
+
+    #[test]
+    fn test_derivative() {
+        assert_eq!(derivative("x^3", "x"), "3*x^2");
+        assert_eq!(derivative("x^2 + 3*x + 1", "x"), "2*x + 3");
+        assert_eq!(derivative("y*x", "x"), "y");
+        assert_eq!(derivative("y*x", "z"), "0");
+        assert_eq!(derivative("sin(x)*cos(x)", "x"), "cos(x)*cos(x) - sin(x)*sin(x)");
+        assert_eq!(derivative("1/x", "x"), "-1/x^2");
+        assert_eq!(derivative("-(x^3)", "x"), "-3*x^2");
+        assert_eq!(derivative("x/2", "x"), "1/2");
+        assert_eq!(derivative("exp(2*x)", "x"), "exp(2*x)*2");
+        assert_eq!(derivative("2^x", "x"), "2^x*ln(2)");
+        assert_eq!(derivative("x^x", "x"), "x^x*(ln(x) + x/x)");
+        assert_eq!(derivative("ln(x^2)", "x"), "1/x^2*(2*x)");
+        assert_eq!(derivative("-x", "x"), "-1");
+        assert_eq!(derivative("sqrt(x)", "x"), "1/(2*sqrt(x))");
+        assert_eq!(derivative("jn(2, x)", "x"), "(jn(2 - 1, x) - jn(2 + 1, x))/2");
+        assert_eq!(derivative("floor(x) + abs(x)", "x"), "sign(x)");
+        assert_eq!(derivative("gamma(y)", "x"), "0");
+
+        let session = Session::init();
+        let fails = |expr: &str| session.process(expr).expression().unwrap().derivative("x").is_err();
+        assert!(fails("gamma(x)"));
+        assert!(fails("x!"));
+        assert!(fails("isprime(x)"));
+        assert!(fails("jn(x, 2)"));
+
+        let derivative_in = |expr: &str, mode| session.process(expr).expression().unwrap().derivative_in("x", mode).unwrap().to_string();
+        assert_eq!(derivative_in("sin(x)", AngleMode::Radians), "cos(x)");
+        assert_eq!(derivative_in("sin(x)", AngleMode::Degrees), "cos(x)*pi/180");
+        assert_eq!(derivative_in("atan(x)", AngleMode::Gradians), "1/(1 + x^2)*200/pi");
+        assert_eq!(derivative_in("x^2", AngleMode::Degrees), "2*x");
+    }
+
+    #[test]
+    fn test_simplify() {
+        let simplify = |expr: &str| Session::init().process(expr).expression().unwrap().simplify().to_string();
+        // constant folding, exact on the fractions
+        assert_eq!(simplify("0*x + 1*y + (2+3)"), "y + 5");
+        assert_eq!(simplify("1/2 + 1/3"), "5/6");
+        assert_eq!(simplify("2^10 - 2*3.5"), "1017.0");
+        assert_eq!(simplify("5%*x"), "1/20*x");
+        assert_eq!(simplify("sin(0*x + x)"), "sin(x)");
+        assert_eq!(simplify("(1/0)*x"), "x/0");
+        // identities and annihilators
+        assert_eq!(simplify("x^1*1 + 0 - 0/y"), "x");
+        assert_eq!(simplify("x^0 + 1^x"), "2");
+        assert_eq!(simplify("-(-x)"), "x");
+        // like terms
+        assert_eq!(simplify("x^2 + 2*x^2 - 3 + 1"), "3*x^2 - 2");
+        assert_eq!(simplify("y*x + x*y - x"), "2*x*y - x");
+        assert_eq!(simplify("x - x"), "0");
+        assert_eq!(simplify("2/x + 3/x"), "5/x");
+        assert_eq!(simplify("a - (b - c)"), "a - b + c");
+        // powers
+        assert_eq!(simplify("x*x^2/x"), "x^2");
+        assert_eq!(simplify("x*y/(y*x)"), "1");
+        assert_eq!(simplify("(x^2)^3*(2*x)^2"), "4*x^8");
+        assert_eq!(simplify("x^y*x^z"), "x^(y + z)");
+        assert_eq!(simplify("(x + 1)^2*(x + 1)"), "(x + 1)^3");
+        assert_eq!(simplify("(x^2)^(1/2)"), "(x^2)^(1/2)");
+        assert_eq!(simplify("x/y/z"), "x/(y*z)");
+        // the calls and the postfix operators are kept, the impure calls aren't collected
+        assert_eq!(simplify("sqrt(2) + sin(0) + 5!"), "sqrt(2) + sin(0) + 5!");
+        assert_eq!(simplify("rand() - rand()"), "rand() - rand()");
+        assert_eq!(simplify("randn()*randn()/randn()"), "randn()*randn()/randn()");
+        assert_eq!(simplify("sin(rand()) + 2*sin(rand())"), "sin(rand()) + 2*sin(rand())");
+        assert_eq!(simplify("0*rand() + x*0"), "0*rand()");
+        assert_eq!(simplify("rand()^0"), "rand()^0");
+        // the exponents with an integer value are natural numbers
+        assert_eq!(simplify("(x^0.5)^2"), "x");
+        assert_eq!(simplify("x^2.5*x^0.5"), "x^3");
+        // the derivatives
+        assert_eq!(simplify(&derivative("x^x", "x")), "(ln(x) + 1)*x^x");
+        assert_eq!(simplify(&derivative("sin(x)*cos(x)", "x")), "cos(x)^2 - sin(x)^2");
+        assert_eq!(simplify(&derivative("ln(x^2)", "x")), "2/x");
+    }
+}
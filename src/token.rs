@@ -1,14 +1,30 @@
 use std::{
+    collections::VecDeque,
     fmt::Display,
     ops::{Add, BitXor, Div, Mul, Sub},
 };
+use num::{complex::Complex64, BigRational, Signed, Zero};
 use num_bigint::BigInt;
 use num_traits::FromPrimitive;
 use log::debug;
-use bigdecimal::ToPrimitive;
+use bigdecimal::{BigDecimal, Context, ToPrimitive};
 
-/// Enum Type [Number]. Either an BigInt integer [`Number::NaturalNumber`]
-/// or a f64 float [`Number::DecimalNumber`]
+use crate::{
+    decimal,
+    error::ConversionError,
+    finance,
+    interval::{self, Interval},
+    matrix::{self, Matrix},
+    parser::Span,
+    uncertainty::{self, Uncertain},
+    units::{self, Quantity},
+};
+
+/// Enum Type [Number]. Either an BigInt integer [`Number::NaturalNumber`],
+/// a f64 float [`Number::DecimalNumber`], an exact fraction [`Number::Rational`],
+/// an arbitrary precision decimal [`Number::BigDecimal`], a [`Number::Complex`] number,
+/// a [`Number::Quantity`] with a unit of measure, a [`Number::Matrix`], an [`Number::Interval`]
+/// or a measured value with an uncertainty [`Number::Uncertain`]
 ///
 #[derive(Debug, PartialEq, Clone)]
 pub enum Number {
@@ -16,6 +32,20 @@ pub enum Number {
     NaturalNumber(BigInt),
     /// a Float [f64]
     DecimalNumber(f64),
+    /// an exact fraction [`BigRational`], never with a denominator of 1
+    Rational(BigRational),
+    /// an arbitrary precision decimal [`BigDecimal`]
+    BigDecimal(BigDecimal),
+    /// a complex number [`Complex64`], never with a zero imaginary part
+    Complex(Complex64),
+    /// a value with a unit of measure [`Quantity`], never dimensionless
+    Quantity(Quantity),
+    /// a matrix or a vector [`Matrix`], never 1x1
+    Matrix(Matrix),
+    /// a guaranteed enclosure of a real number [`Interval`]
+    Interval(Interval),
+    /// a measured value with its uncertainty [`Uncertain`]
+    Uncertain(Uncertain),
 }
 
 /// A binary or unary Math [`Operator`]
@@ -34,10 +64,26 @@ pub enum Operator {
     Pow,
     /// Unary Neg ('-1')
     Une,
-    /// Factorial ('0!')
+    /// Factorial ('0!'), or the gamma function for a non-integer ('0.5!')
     Fac,
+    /// Double factorial ('5!!' is 5*3*1)
+    DoubleFac,
+    /// An angle in degrees ('30°'), whatever the angle mode of the session
+    Degree,
+    /// A percentage ('5%' is 0.05)
+    Percent,
     /// Binary Assignment ('A=1')
     Eql,
+    /// Unit conversion ('3 ft to m')
+    To,
+    /// Element-wise Mul between matrices ('A.*B')
+    ElemMul,
+    /// Element-wise Div between matrices ('A./B')
+    ElemDiv,
+    /// Element-wise Pow of a matrix ('A.^2')
+    ElemPow,
+    /// A measured value with its uncertainty ('9.81 ± 0.02' or '9.81 +- 0.02')
+    PlusMinus,
 }
 
 /// The "associativity" of an operator dictates the direction
@@ -55,14 +101,29 @@ pub enum Associate {
     RightAssociative,
 }
 
-/// Just [`Token::Bracket`]s. They change the order of evaluation of an expression.
+/// Just [`Token::Bracket`]s. They change the order of evaluation of an expression,
+/// or enclose a matrix literal.
 ///
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Bracket {
-    /// either '(' or '['
+    /// '('
     Open,
-    /// either ')' or ']'
+    /// ')'
     Close,
+    /// '[', opening a matrix ('[1, 2; 3, 4]')
+    MatrixOpen,
+    /// ']', closing a matrix
+    MatrixClose,
+}
+
+/// The [`Token::Separator`]s of a list of values
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Separator {
+    /// ',' between the elements of a row, or the arguments of a function
+    Comma,
+    /// ';' between the rows of a matrix
+    Semicolon,
 }
 
 /// The [Token] enum. It represents the smallest chunk of a math expression
@@ -71,8 +132,10 @@ pub enum Bracket {
 /// [`Token::Operand`] as 1,2,3,-4,-5,6.66 ...
 /// [`Token::Operator`] as +,-,*,/ ...
 /// [`Token::Bracket`] as [] or ()
+/// [`Token::Separator`] as , or ;
 /// [`Token::Function`] as sin,cos,tan,ln ...
 /// [`Token::Variable`] as any variable name such as x,y,ab,foo,... whatever
+/// [`Token::MatrixLiteral`], [`Token::Call`] and [`Token::Lambda`], only in a RPN expression
 ///
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token<'a> {
@@ -82,10 +145,21 @@ pub enum Token<'a> {
     Operator(Operator),
     /// ( ) [ ]
     Bracket(Bracket),
+    /// , ;
+    Separator(Separator),
     /// sin cos tan ln log...
     Function(MathFunction),
     /// a b c x y ...
     Variable(&'a str),
+    /// Builds a matrix out of the values of its rows, with the given row lengths.
+    /// It replaces the brackets of a matrix in a RPN expression.
+    MatrixLiteral(Vec<usize>),
+    /// Calls a variadic function with the given number of arguments.
+    /// It replaces the function and its brackets in a RPN expression.
+    Call(MathFunction, usize),
+    /// An expression of a bound variable, left unevaluated for the [`Token::Call`] that follows it,
+    /// as 'x^2 - 2' in 'solve(x^2 - 2, x, 1)'. It replaces the expression and the variable in a RPN expression.
+    Lambda(&'a str, VecDeque<(Token<'a>, Span)>),
 }
 
 /// The [`MathFunction`] enum. It represents a common math function.
@@ -108,16 +182,311 @@ pub enum MathFunction {
     Ln,
     /// logaritm base 10
     Log,
-    /// absolute value
+    /// absolute value, or modulus of a complex number
     Abs,
     /// square root
     Sqrt,
+    /// the largest of any number of values: max(1, 5, 3)
     Max,
+    /// the smallest of any number of values: min(1, 5, 3)
     Min,
+    /// real part of a complex number: re(1+2i)
+    Re,
+    /// imaginary part of a complex number: im(1+2i)
+    Im,
+    /// complex conjugate: conj(1+2i)
+    Conj,
+    /// argument (phase) of a complex number: arg(1+2i)
+    Arg,
+    /// determinant of a square matrix: det([1,2;3,4])
+    Det,
+    /// inverse of a square matrix: inv([1,2;3,4])
+    Inv,
+    /// transpose of a matrix: transpose([1,2])
+    Transpose,
+    /// dot product of two vectors: dot([1,2], [3,4])
+    Dot,
+    /// cross product of two vectors of length 3: cross([1,0,0], [0,1,0])
+    Cross,
+    /// euclidean norm of a vector, or Frobenius norm of a matrix: norm([3,4])
+    Norm,
+    /// converts an angle from radians to degrees: deg(pi)
+    Deg,
+    /// converts an angle from degrees to radians: rad(180)
+    Rad,
+    /// hyperbolic sine: sinh(1)
+    Sinh,
+    /// hyperbolic cosine: cosh(1)
+    Cosh,
+    /// hyperbolic tangent: tanh(1)
+    Tanh,
+    /// inverse hyperbolic sine: asinh(1)
+    ASinh,
+    /// inverse hyperbolic cosine: acosh(2)
+    ACosh,
+    /// inverse hyperbolic tangent: atanh(0.5)
+    ATanh,
+    /// exponential: exp(1)
+    Exp,
+    /// logarithm base 2: log2(8)
+    Log2,
+    /// the largest integer not greater than the argument: floor(2.5)
+    Floor,
+    /// the smallest integer not less than the argument: ceil(2.5)
+    Ceil,
+    /// the nearest integer, rounding half away from zero: round(2.5)
+    Round,
+    /// the integer part: trunc(-2.5)
+    Trunc,
+    /// the fractional part, with the sign of the argument: fract(-2.5)
+    Fract,
+    /// -1, 0 or 1 as the argument is negative, zero or positive: sign(-2)
+    Sign,
+    /// cube root, negative for a negative argument: cbrt(-8)
+    Cbrt,
+    /// n-th root: nroot(32, 5)
+    NRoot,
+    /// secant: sec(0)
+    Sec,
+    /// cosecant: csc(1)
+    Csc,
+    /// cotangent: cot(1)
+    Cot,
+    /// greatest common divisor of two integers: gcd(12, 18)
+    Gcd,
+    /// least common multiple of two integers: lcm(4, 6)
+    Lcm,
+    /// modular exponentiation of integers, base^exponent mod modulus: `mod_pow(4, 13, 497)`
+    ModPow,
+    /// combinations of k out of n: ncr(52, 5)
+    NCr,
+    /// permutations of k out of n: npr(10, 3)
+    NPr,
+    /// sum of any number of values: sum(1, 2, 3)
+    Sum,
+    /// product of any number of values: prod(1, 2, 3)
+    Prod,
+    /// arithmetic mean: avg(1, 2, 3)
+    Avg,
+    /// the middle value, or the mean of the two middle values: median(3, 1, 2)
+    Median,
+    /// the most frequent value, the smallest one on a tie: mode(1, 2, 2, 3)
+    Mode,
+    /// sample variance, over n - 1: var(1, 2, 3, 4)
+    Var,
+    /// sample standard deviation: stddev(1, 2, 3, 4)
+    StdDev,
+    /// the p-th percentile of the values, interpolating between the closest ranks: percentile(90, 1, 2, 3, 4)
+    Percentile,
+    /// 1 if the integer is prime, 0 otherwise: isprime(97)
+    IsPrime,
+    /// the smallest prime greater than the integer: nextprime(100)
+    NextPrime,
+    /// the prime factors of an integer with their exponents, a row for each: factor(360)
+    Factor,
+    /// Euler's totient, the count of the coprimes up to the integer: phi(36)
+    Phi,
+    /// the positive divisors of an integer, as a row: divisors(12)
+    Divisors,
+    /// the inverse of an integer modulo another: modinv(3, 11)
+    ModInv,
+    /// the integer square root, rounded down: isqrt(99)
+    Isqrt,
+    /// the number of decimal digits of an integer: digits(2^100)
+    Digits,
+    /// the gamma function, (n-1)! for a natural number n: gamma(0.5)
+    Gamma,
+    /// the logarithm of the absolute value of the gamma function: lgamma(1000)
+    LGamma,
+    /// the beta function: beta(2, 3)
+    Beta,
+    /// the error function: erf(0.5)
+    Erf,
+    /// the complementary error function, 1 - erf(x): erfc(5)
+    Erfc,
+    /// the Riemann zeta function: zeta(2)
+    Zeta,
+    /// the Bessel function of the first kind of order 0: j0(1)
+    J0,
+    /// the Bessel function of the first kind of order 1: j1(1)
+    J1,
+    /// the Bessel function of the first kind of integer order n: jn(3, 2)
+    Jn,
+    /// the principal branch of the Lambert W function, the solution of w*exp(w) = x: lambertw(1)
+    LambertW,
+    /// the density of the normal distribution of mean mu and standard deviation sigma: normpdf(x, mu, sigma)
+    NormPdf,
+    /// the cumulative distribution of the normal distribution: normcdf(1.96, 0, 1)
+    NormCdf,
+    /// the quantile of the normal distribution, the inverse of normcdf: norminv(0.975, 0, 1)
+    NormInv,
+    /// the probability of k successes out of n trials of probability p: binompdf(k, n, p)
+    BinomPdf,
+    /// the probability of at most k successes out of n trials of probability p: binomcdf(k, n, p)
+    BinomCdf,
+    /// the probability of k events of a Poisson distribution of mean lambda: poissonpdf(k, lambda)
+    PoissonPdf,
+    /// the cumulative distribution of Student's t with nu degrees of freedom: tcdf(t, nu)
+    TCdf,
+    /// the cumulative distribution of the chi-square with k degrees of freedom: chi2cdf(x, k)
+    Chi2Cdf,
+    /// the cumulative distribution of the uniform distribution between a and b: uniform(x, a, b)
+    Uniform,
+    /// a random number uniformly distributed between 0 and 1 excluded: `rand()`
+    Rand,
+    /// a random integer between a and b included: randint(1, 6)
+    RandInt,
+    /// a random number of the standard normal distribution: `randn()`
+    RandN,
+    /// the payment per period of a loan: pmt(rate, nper, pv, fv, type), with fv and type optional
+    Pmt,
+    /// the interest part of the payment of a period: ipmt(rate, per, nper, pv, fv, type)
+    IPmt,
+    /// the principal part of the payment of a period: ppmt(rate, per, nper, pv, fv, type)
+    PPmt,
+    /// the future value of an investment: fv(rate, nper, pmt, pv, type)
+    Fv,
+    /// the present value of an investment: pv(rate, nper, pmt, fv, type)
+    Pv,
+    /// the number of periods of an investment: nper(rate, pmt, pv, fv, type)
+    NPer,
+    /// the interest rate per period of an annuity: rate(nper, pmt, pv, fv, type, guess)
+    Rate,
+    /// the net present value of the cash flows at the end of each period: npv(10%, -100, 60, 60)
+    Npv,
+    /// the internal rate of return of the cash flows: irr(-100, 60, 60)
+    Irr,
+    /// a root of an expression of x, near a guess or between a and b: solve(x^3 - 2*x - 5, x, 2)
+    Solve,
+    /// the definite integral of an expression of x between a and b: integrate(sin(x)^2, x, 0, pi)
+    Integrate,
+    /// the derivative of an expression of x at a point: deriv(x^3, x, 2)
+    Deriv,
+    /// the symbolic derivative of an expression with respect to x, at the value of x: diff(x^3, x)
+    Diff,
+    /// the sum of an expression of i, for each integer i from a to b: sumover(i, 1, 10, i^2)
+    SumOver,
+    /// the product of an expression of i, for each integer i from a to b: prodover(i, 1, 5, i)
+    ProdOver,
+    /// 1 if the argument is not a number, 0 otherwise: isnan(0/0.)
+    IsNan,
+    /// 1 if the argument is infinite, 0 otherwise: isinf(ln(0))
+    IsInf,
+    /// the interval between two bounds, in any mode: interval(1.9, 2.1)
+    Interval,
     /// Nope!
     None,
 }
 
+/// The names of the [`MathFunction`]s in the expressions
+///
+static FUNCTIONS: &[(&str, MathFunction)] = &[
+    ("sin", MathFunction::Sin),
+    ("cos", MathFunction::Cos),
+    ("tan", MathFunction::Tan),
+    ("asin", MathFunction::ASin),
+    ("acos", MathFunction::ACos),
+    ("atan", MathFunction::ATan),
+    ("ln", MathFunction::Ln),
+    ("log", MathFunction::Log),
+    ("abs", MathFunction::Abs),
+    ("sqrt", MathFunction::Sqrt),
+    ("re", MathFunction::Re),
+    ("im", MathFunction::Im),
+    ("conj", MathFunction::Conj),
+    ("arg", MathFunction::Arg),
+    ("det", MathFunction::Det),
+    ("inv", MathFunction::Inv),
+    ("transpose", MathFunction::Transpose),
+    ("dot", MathFunction::Dot),
+    ("cross", MathFunction::Cross),
+    ("norm", MathFunction::Norm),
+    ("sinh", MathFunction::Sinh),
+    ("cosh", MathFunction::Cosh),
+    ("tanh", MathFunction::Tanh),
+    ("asinh", MathFunction::ASinh),
+    ("acosh", MathFunction::ACosh),
+    ("atanh", MathFunction::ATanh),
+    ("exp", MathFunction::Exp),
+    ("log2", MathFunction::Log2),
+    ("floor", MathFunction::Floor),
+    ("ceil", MathFunction::Ceil),
+    ("round", MathFunction::Round),
+    ("trunc", MathFunction::Trunc),
+    ("fract", MathFunction::Fract),
+    ("sign", MathFunction::Sign),
+    ("cbrt", MathFunction::Cbrt),
+    ("nroot", MathFunction::NRoot),
+    ("sec", MathFunction::Sec),
+    ("csc", MathFunction::Csc),
+    ("cot", MathFunction::Cot),
+    ("gcd", MathFunction::Gcd),
+    ("lcm", MathFunction::Lcm),
+    ("mod_pow", MathFunction::ModPow),
+    ("ncr", MathFunction::NCr),
+    ("npr", MathFunction::NPr),
+    ("deg", MathFunction::Deg),
+    ("rad", MathFunction::Rad),
+    ("isnan", MathFunction::IsNan),
+    ("isinf", MathFunction::IsInf),
+    ("sum", MathFunction::Sum),
+    ("prod", MathFunction::Prod),
+    ("avg", MathFunction::Avg),
+    ("median", MathFunction::Median),
+    ("mode", MathFunction::Mode),
+    ("var", MathFunction::Var),
+    ("stddev", MathFunction::StdDev),
+    ("max", MathFunction::Max),
+    ("min", MathFunction::Min),
+    ("percentile", MathFunction::Percentile),
+    ("isprime", MathFunction::IsPrime),
+    ("nextprime", MathFunction::NextPrime),
+    ("factor", MathFunction::Factor),
+    ("phi", MathFunction::Phi),
+    ("divisors", MathFunction::Divisors),
+    ("modinv", MathFunction::ModInv),
+    ("isqrt", MathFunction::Isqrt),
+    ("digits", MathFunction::Digits),
+    ("gamma", MathFunction::Gamma),
+    ("lgamma", MathFunction::LGamma),
+    ("beta", MathFunction::Beta),
+    ("erf", MathFunction::Erf),
+    ("erfc", MathFunction::Erfc),
+    ("zeta", MathFunction::Zeta),
+    ("j0", MathFunction::J0),
+    ("j1", MathFunction::J1),
+    ("jn", MathFunction::Jn),
+    ("lambertw", MathFunction::LambertW),
+    ("normpdf", MathFunction::NormPdf),
+    ("normcdf", MathFunction::NormCdf),
+    ("norminv", MathFunction::NormInv),
+    ("binompdf", MathFunction::BinomPdf),
+    ("binomcdf", MathFunction::BinomCdf),
+    ("poissonpdf", MathFunction::PoissonPdf),
+    ("tcdf", MathFunction::TCdf),
+    ("chi2cdf", MathFunction::Chi2Cdf),
+    ("uniform", MathFunction::Uniform),
+    ("rand", MathFunction::Rand),
+    ("randint", MathFunction::RandInt),
+    ("randn", MathFunction::RandN),
+    ("pmt", MathFunction::Pmt),
+    ("ipmt", MathFunction::IPmt),
+    ("ppmt", MathFunction::PPmt),
+    ("fv", MathFunction::Fv),
+    ("pv", MathFunction::Pv),
+    ("nper", MathFunction::NPer),
+    ("rate", MathFunction::Rate),
+    ("npv", MathFunction::Npv),
+    ("irr", MathFunction::Irr),
+    ("solve", MathFunction::Solve),
+    ("integrate", MathFunction::Integrate),
+    ("deriv", MathFunction::Deriv),
+    ("diff", MathFunction::Diff),
+    ("sumover", MathFunction::SumOver),
+    ("prodover", MathFunction::ProdOver),
+    ("interval", MathFunction::Interval),
+];
+
 impl Token<'_> {
     /// Converts a char to a [`Token::Operator`]
     /// or just returns [`None`] if nothing matches.
@@ -141,8 +510,10 @@ impl Token<'_> {
     ///
     const fn from_bracket(c: char) -> Option<Token<'static>> {
         match c {
-            '(' | '[' => Some(Token::Bracket(Bracket::Open)),
-            ')' | ']' => Some(Token::Bracket(Bracket::Close)),
+            '(' => Some(Token::Bracket(Bracket::Open)),
+            ')' => Some(Token::Bracket(Bracket::Close)),
+            '[' => Some(Token::Bracket(Bracket::MatrixOpen)),
+            ']' => Some(Token::Bracket(Bracket::MatrixClose)),
             _ => None,
         }
     }
@@ -151,21 +522,8 @@ impl Token<'_> {
     /// or just returns [`None`] if nothing matches.
     ///
     fn get_some(fun: &str) -> Option<MathFunction> {
-        match fun.to_lowercase().as_str() {
-            "sin" => Some(MathFunction::Sin),
-            "cos" => Some(MathFunction::Cos),
-            "tan" => Some(MathFunction::Tan),
-            "asin" => Some(MathFunction::ASin),
-            "acos" => Some(MathFunction::ACos),
-            "atan" => Some(MathFunction::ATan),
-            "ln" => Some(MathFunction::Ln),
-            "log" => Some(MathFunction::Log),
-            "abs" => Some(MathFunction::Abs),
-            "sqrt" => Some(MathFunction::Sqrt),
-            //   "max" => MathFunction::Max,
-            //   "min" => MathFunction::Min,
-            &_ => None,
-        }
+        let fun = fun.to_lowercase();
+        FUNCTIONS.iter().find(|(name, _)| *name == fun).map(|(_, f)| *f)
     }
 
     /// Transforms a specific chunk of chars into a specific [Token]. i.e.
@@ -181,15 +539,29 @@ impl Token<'_> {
     pub fn tokenize(t: &str) -> Option<Token> {
         match t.chars().next() {
             Some(s) => match s {
+                '+' | '±' if t == "+-" || t == "±" => return Some(Token::Operator(Operator::PlusMinus)),
+                '!' if t == "!!" => return Some(Token::Operator(Operator::DoubleFac)),
+                '°' => return Some(Token::Operator(Operator::Degree)),
+                '%' => return Some(Token::Operator(Operator::Percent)),
                 c @ ('+' | '-' | '*' | '/' | '^' | '!' | '=') => {
                     return Some(Token::from_operator(c).unwrap())
                 }
                 b @ ('(' | ')' | '[' | ']') => return Some(Token::from_bracket(b).unwrap()),
+                ',' => return Some(Token::Separator(Separator::Comma)),
+                ';' => return Some(Token::Separator(Separator::Semicolon)),
                 _ => (), // continue the flow
             },
             None => return None,
         }
 
+        match t {
+            "to" => return Some(Token::Operator(Operator::To)),
+            ".*" => return Some(Token::Operator(Operator::ElemMul)),
+            "./" => return Some(Token::Operator(Operator::ElemDiv)),
+            ".^" => return Some(Token::Operator(Operator::ElemPow)),
+            _ => (),
+        }
+
         if let Ok(v) = t.parse::<BigInt>() {
             return Some(Token::Operand(Number::NaturalNumber(v)));
         }
@@ -210,11 +582,17 @@ impl Token<'_> {
     fn operator_priority(o: Token) -> (u8, Associate) {
         match o {
             Token::Operator(Operator::Add | Operator::Sub) => (1, Associate::LeftAssociative),
-            Token::Operator(Operator::Mul | Operator::Div) => (2, Associate::LeftAssociative),
-            Token::Operator(Operator::Pow) => (3, Associate::RightAssociative),
+            Token::Operator(Operator::Mul | Operator::Div | Operator::ElemMul | Operator::ElemDiv) => {
+                (2, Associate::LeftAssociative)
+            }
+            Token::Operator(Operator::Pow | Operator::ElemPow) => (3, Associate::RightAssociative),
+            // tighter than '*', so that '2 * 9.81 ± 0.02' doubles the uncertainty too
+            Token::Operator(Operator::PlusMinus) => (3, Associate::LeftAssociative),
             Token::Operator(Operator::Une) => (4, Associate::RightAssociative),
-            Token::Operator(Operator::Fac) => (5, Associate::LeftAssociative),
+            Token::Operator(Operator::Fac | Operator::DoubleFac | Operator::Degree | Operator::Percent) => (5, Associate::LeftAssociative),
             Token::Operator(Operator::Eql) => (0, Associate::LeftAssociative),
+            // right associative, so that 'x = 3 ft to m' assigns the converted quantity
+            Token::Operator(Operator::To) => (0, Associate::RightAssociative),
             _ => panic!("Operator '{o}' not recognised. This must not happen!"),
         }
     }
@@ -236,17 +614,197 @@ impl Token<'_> {
     }
 }
 
-/// Let's display a [`Number::NaturalNumber`] or a [`Number::DecimalNumber`] properly
+impl MathFunction {
+    /// The name of the function in the expressions
+    ///
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        FUNCTIONS.iter().find(|(_, f)| *f == self).map_or("", |(name, _)| name)
+    }
+
+    /// Whether the function takes any number of arguments, such as 'max(1, 5, 3)'
+    ///
+    #[must_use]
+    pub fn is_variadic(self) -> bool {
+        matches!(
+            self,
+            MathFunction::Sum
+                | MathFunction::Prod
+                | MathFunction::Avg
+                | MathFunction::Median
+                | MathFunction::Mode
+                | MathFunction::Var
+                | MathFunction::StdDev
+                | MathFunction::Max
+                | MathFunction::Min
+                | MathFunction::Percentile
+        )
+    }
+
+    /// Whether the function gives a different value at each call, as the random numbers:
+    /// an expression that calls it must not be folded into a constant, nor cached
+    ///
+    #[must_use]
+    pub fn is_impure(self) -> bool {
+        matches!(self, MathFunction::Rand | MathFunction::RandInt | MathFunction::RandN)
+    }
+
+    /// Whether a call to the function records the number of its arguments in a [`Token::Call`]:
+    /// the variadic functions, the impure ones, that may take none, the financial ones,
+    /// whose last arguments are optional, and the ones of an expression
+    ///
+    #[must_use]
+    pub fn counts_arguments(self) -> bool {
+        self.is_variadic() || self.is_impure() || finance::is_financial(self) || self.lambda_arguments().is_some()
+    }
+
+    /// Whether the function always takes an expression of a bound variable, such as 'solve'
+    ///
+    #[must_use]
+    pub fn is_lazy(self) -> bool {
+        matches!(self, MathFunction::Solve | MathFunction::Integrate | MathFunction::Deriv | MathFunction::Diff)
+    }
+
+    /// Whether the function is a comprehension, that evaluates an expression for each integer
+    /// of a range, as 'sumover(i, 1, 10, i^2)'
+    ///
+    #[must_use]
+    pub fn is_comprehension(self) -> bool {
+        matches!(self, MathFunction::SumOver | MathFunction::ProdOver)
+    }
+
+    /// The positions of the expression and of its bound variable among the arguments of a function
+    /// that evaluates an expression lazily, for the values of the variable it chooses
+    ///
+    #[must_use]
+    pub fn lambda_arguments(self) -> Option<(usize, usize)> {
+        match self {
+            _ if self.is_lazy() => Some((0, 1)),
+            _ if self.is_comprehension() => Some((3, 0)),
+            _ => None,
+        }
+    }
+
+    /// The number of arguments of the function, or 1 for a variadic function called
+    /// without brackets: each [`Token::Call`] carries the number of its arguments
+    ///
+    #[must_use]
+    pub fn arity(self) -> usize {
+        match self {
+            MathFunction::Dot
+            | MathFunction::Cross
+            | MathFunction::NRoot
+            | MathFunction::Gcd
+            | MathFunction::Lcm
+            | MathFunction::NCr
+            | MathFunction::NPr
+            | MathFunction::ModInv
+            | MathFunction::Beta
+            | MathFunction::Jn
+            | MathFunction::PoissonPdf
+            | MathFunction::TCdf
+            | MathFunction::Chi2Cdf
+            | MathFunction::Interval => 2,
+            MathFunction::ModPow
+            | MathFunction::NormPdf
+            | MathFunction::NormCdf
+            | MathFunction::NormInv
+            | MathFunction::BinomPdf
+            | MathFunction::BinomCdf
+            | MathFunction::Uniform => 3,
+            _ => 1,
+        }
+    }
+}
+
+impl Number {
+    /// Wraps a [`BigRational`] into a [Number], falling back to a
+    /// [`Number::NaturalNumber`] when the fraction is an integer
+    ///
+    #[must_use]
+    pub fn from_rational(r: BigRational) -> Number {
+        if r.is_integer() {
+            Number::NaturalNumber(r.to_integer())
+        } else {
+            Number::Rational(r)
+        }
+    }
+
+    /// Wraps a [`Complex64`] into a [Number], falling back to a
+    /// [`Number::DecimalNumber`] when the imaginary part is zero
+    ///
+    #[must_use]
+    pub fn from_complex(z: Complex64) -> Number {
+        if z.im == 0. {
+            Number::DecimalNumber(z.re)
+        } else {
+            Number::Complex(z)
+        }
+    }
+
+    /// Wraps a [`Matrix`] into a [Number], falling back to its only
+    /// element when the matrix is 1x1
+    ///
+    #[must_use]
+    pub fn from_matrix(m: Matrix) -> Number {
+        if m.rows() == 1 && m.cols() == 1 {
+            m.elements()[0].clone()
+        } else {
+            Number::Matrix(m)
+        }
+    }
+
+    /// Wraps a [`Quantity`] into a [Number], falling back to a
+    /// [`Number::DecimalNumber`] when the unit is dimensionless (i.e. 'km/m')
+    ///
+    #[must_use]
+    pub fn from_quantity(q: Quantity) -> Number {
+        if q.unit.is_dimensionless() {
+            Number::DecimalNumber(q.to_si())
+        } else {
+            Number::Quantity(q)
+        }
+    }
+}
+
+impl From<Number> for Complex64 {
+    fn from(n: Number) -> Complex64 {
+        match n {
+            Number::Complex(z) => z,
+            n => Complex64::new(n.into(), 0.),
+        }
+    }
+}
+
+/// Let's display a [`Number::NaturalNumber`] or a [`Number::DecimalNumber`] properly.
+///
+/// A [`Number::Rational`] is displayed as a fraction ('5/2'), or as a decimal
+/// number ('2.5') with the alternate flag ('{:#}')
 ///
 impl Display for Number {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Number::NaturalNumber(v) => write!(f, "{v}"),
             Number::DecimalNumber(v) => write!(f, "{v}"),
+            Number::Rational(v) if f.alternate() => write!(f, "{}", rational_to_f64(v)),
+            Number::Rational(v) => write!(f, "{v}"),
+            Number::BigDecimal(v) => write!(f, "{v}"),
+            Number::Complex(z) if z.re == 0. => write!(f, "{}i", z.im),
+            Number::Complex(z) => write!(f, "{}{:+}i", z.re, z.im),
+            Number::Quantity(q) => write!(f, "{q}"),
+            Number::Matrix(m) => write!(f, "{m}"),
+            Number::Interval(x) => write!(f, "{x}"),
+            Number::Uncertain(u) => u.fmt(f),
         }
     }
 }
 
+/// Converts a [`BigRational`] to the nearest f64
+///
+fn rational_to_f64(r: &BigRational) -> f64 {
+    ToPrimitive::to_f64(r).unwrap_or(f64::NAN)
+}
+
 /// The main operational functional closure. It handles 4 different cases:
 ///
 /// 1. Natural (op) Natural returns Natural
@@ -254,21 +812,84 @@ impl Display for Number {
 /// 3. Decimal (op) Decimal returns Decimal
 /// 4. Decimal (op) Natural returns Decimal
 ///
+/// and a Rational combined with a Natural or another Rational returns a Rational,
+/// while a Rational combined with a Decimal returns a Decimal.
+/// A `BigDecimal` combined with anything returns a `BigDecimal`, unless the other side is
+/// not a number or infinity, or the `BigDecimal` closure has no result: then it's a Decimal.
+/// A Complex combined with anything returns a Complex.
+///
 /// (op) can be [Add], [Mul], [Sub], [Div], [BitXor], ...
 ///
-/// We define 2 closures: 1 specialised for Natural Numbers and the other one specialised for Decimals.
+/// We define 5 closures: 1 specialised for Natural Numbers, 1 for Rationals, 1 for Decimals,
+/// 1 for `BigDecimal`s and the last one for Complex numbers.
 ///
-fn apply_functional_token_operation<NF, DF>(ln: Number, rn: Number, nf: NF, df: DF) -> Number
+fn apply_functional_token_operation<NF, RF, DF, BF, CF>(
+    ln: Number,
+    rn: Number,
+    nf: NF,
+    rf: RF,
+    df: DF,
+    bf: BF,
+    cf: CF,
+) -> Number
 where
     NF: Fn(BigInt, BigInt) -> BigInt,
+    RF: Fn(BigRational, BigRational) -> BigRational,
     DF: Fn(f64, f64) -> f64,
+    BF: Fn(BigDecimal, BigDecimal) -> Option<BigDecimal>,
+    CF: Fn(Complex64, Complex64) -> Complex64,
 {
-    match (ln, rn.clone()) {
+    match (ln, rn) {
+        (ln, rn) if matches!(ln, Number::Complex(_)) || matches!(rn, Number::Complex(_)) => {
+            Number::from_complex(cf(ln.into(), rn.into()))
+        }
         (Number::NaturalNumber(v1), Number::NaturalNumber(v2)) => Number::NaturalNumber(nf(v1, v2)),
-        (Number::NaturalNumber(v1), Number::DecimalNumber(v2)) => {
-            Number::DecimalNumber(df(ToPrimitive::to_f64(&v1).expect("Should not happen"), v2))
+        (Number::Rational(v1), Number::Rational(v2)) => Number::from_rational(rf(v1, v2)),
+        (Number::Rational(v1), Number::NaturalNumber(v2)) => Number::from_rational(rf(v1, BigRational::from_integer(v2))),
+        (Number::NaturalNumber(v1), Number::Rational(v2)) => Number::from_rational(rf(BigRational::from_integer(v1), v2)),
+        (ln, rn) if matches!(ln, Number::BigDecimal(_)) || matches!(rn, Number::BigDecimal(_)) => {
+            let ctx = Context::default();
+            match (decimal::from_number(&ln, &ctx), decimal::from_number(&rn, &ctx)) {
+                (Some(v1), Some(v2)) => {
+                    let (f1, f2) = (v1.to_f64().unwrap_or(f64::NAN), v2.to_f64().unwrap_or(f64::NAN));
+                    bf(v1, v2).map_or_else(|| Number::DecimalNumber(df(f1, f2)), Number::BigDecimal)
+                }
+                _ => Number::DecimalNumber(df(ln.into(), rn.into())),
+            }
         }
-        (Number::DecimalNumber(v1), _) => Number::DecimalNumber(df(v1, rn.into())),
+        (ln, rn) => Number::DecimalNumber(df(ln.into(), rn.into())),
+    }
+}
+
+/// A [`Number::Matrix`], an [`Number::Interval`], a [`Number::Uncertain`] or a [`Number::Quantity`]
+/// combined with anything returns a matrix, an interval, a measured value or a quantity,
+/// or not a number when the operands don't match.
+/// Returns [`None`] if neither side is one of them.
+///
+fn apply_compound_operation(op: Operator, ln: &Number, rn: &Number) -> Option<Number> {
+    let either = |f: fn(&Number) -> bool| f(ln) || f(rn);
+    let res = if either(|n| matches!(n, Number::Matrix(_))) {
+        matrix::arithmetic(op, ln.clone(), rn.clone(), &|op, a, b| Ok(scalar_operation(op, a, b)))
+    } else if either(|n| matches!(n, Number::Interval(_))) {
+        interval::arithmetic(op, ln, rn)
+    } else if either(|n| matches!(n, Number::Uncertain(_))) {
+        uncertainty::arithmetic(op, ln, rn)
+    } else if either(|n| matches!(n, Number::Quantity(_))) {
+        units::arithmetic(op, ln, rn)
+    } else {
+        return None;
+    };
+    Some(res.unwrap_or(Number::DecimalNumber(f64::NAN)))
+}
+
+fn scalar_operation(op: Operator, a: Number, b: Number) -> Number {
+    match op {
+        Operator::Add => a + b,
+        Operator::Sub => a - b,
+        Operator::Mul => a * b,
+        Operator::Div => a / b,
+        Operator::Pow => a ^ b,
+        _ => Number::DecimalNumber(f64::NAN),
     }
 }
 
@@ -276,7 +897,10 @@ impl Add for Number {
     type Output = Number;
 
     fn add(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a + b, |a, b| a + b)
+        if let Some(res) = apply_compound_operation(Operator::Add, &self, &rhs) {
+            return res;
+        }
+        apply_functional_token_operation(self, rhs, |a, b| a + b, |a, b| a + b, |a, b| a + b, |a, b| Some(a + b), |a, b| a + b)
     }
 }
 
@@ -284,7 +908,10 @@ impl Sub for Number {
     type Output = Number;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a - b, |a, b| a - b)
+        if let Some(res) = apply_compound_operation(Operator::Sub, &self, &rhs) {
+            return res;
+        }
+        apply_functional_token_operation(self, rhs, |a, b| a - b, |a, b| a - b, |a, b| a - b, |a, b| Some(a - b), |a, b| a - b)
     }
 }
 
@@ -292,7 +919,10 @@ impl Mul for Number {
     type Output = Number;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a * b, |a, b| a * b)
+        if let Some(res) = apply_compound_operation(Operator::Mul, &self, &rhs) {
+            return res;
+        }
+        apply_functional_token_operation(self, rhs, |a, b| a * b, |a, b| a * b, |a, b| a * b, |a, b| Some(a * b), |a, b| a * b)
     }
 }
 
@@ -300,7 +930,18 @@ impl Div for Number {
     type Output = Number;
 
     fn div(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a / b, |a, b| a / b)
+        if let Some(res) = apply_compound_operation(Operator::Div, &self, &rhs) {
+            return res;
+        }
+        apply_functional_token_operation(
+            self,
+            rhs,
+            |a, b| a / b,
+            |a, b| a / b,
+            |a, b| a / b,
+            |a, b| (!b.is_zero()).then(|| a / b),
+            |a, b| a / b,
+        )
     }
 }
 
@@ -308,12 +949,45 @@ impl BitXor for Number {
     type Output = Number;
 
     fn bitxor(self, rhs: Self) -> Self::Output {
+        if let Some(res) = apply_compound_operation(Operator::Pow, &self, &rhs) {
+            return res;
+        }
         debug!("{} {}", self, rhs);
+        let zero = Number::NaturalNumber(BigInt::zero());
+        let fractional = match &rhs {
+            Number::Rational(_) => true,
+            Number::DecimalNumber(v) => v.fract() != 0.,
+            Number::BigDecimal(v) => !v.is_integer(),
+            _ => false,
+        };
+        if fractional && self < zero {
+            // the principal root of a negative number
+            return Number::from_complex(Complex64::from(self).powc(rhs.into()));
+        }
+        let exact_exponent = match (&self, &rhs) {
+            (Number::NaturalNumber(_), Number::NaturalNumber(e)) => ToPrimitive::to_u32(e).is_some(),
+            (Number::Rational(_), Number::NaturalNumber(e)) => ToPrimitive::to_i32(e).is_some(),
+            _ => true,
+        };
+        if !exact_exponent {
+            // a negative exponent of an integer, or one too large for an exact power
+            return Number::DecimalNumber(f64::powf(self.into(), rhs.into()));
+        }
+        if let Number::Rational(_) = rhs {
+            // a fractional exponent is very unlikely to give back a fraction
+            return Number::DecimalNumber(f64::powf(self.into(), rhs.into()));
+        }
         apply_functional_token_operation(
             self,
             rhs,
             |a, b| BigInt::pow(&a, b.try_into().unwrap()),
+            |a, b| a.pow(b.to_integer().try_into().unwrap()),
             f64::powf,
+            |a, b| decimal::pow(&a, &b, &Context::default()),
+            |a, b| match ToPrimitive::to_i32(&b.re) {
+                Some(n) if b.im == 0. && b.re.fract() == 0. => a.powi(n),
+                _ => a.powc(b),
+            },
         )
     }
 }
@@ -323,64 +997,208 @@ impl BitXor for Number {
 impl PartialOrd for Number {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
-            (Number::NaturalNumber(v1), Number::NaturalNumber(v2)) => v1.partial_cmp(&v2),
-            (Number::NaturalNumber(v1), Number::DecimalNumber(v2)) => {
-                ToPrimitive::to_f64(v1).expect("Should not happen").partial_cmp(v2)
+            (Number::NaturalNumber(v1), Number::NaturalNumber(v2)) => v1.partial_cmp(v2),
+            (Number::Rational(v1), Number::Rational(v2)) => v1.partial_cmp(v2),
+            (Number::Rational(v1), Number::NaturalNumber(v2)) => v1.partial_cmp(&BigRational::from_integer(v2.clone())),
+            (Number::NaturalNumber(v1), Number::Rational(v2)) => BigRational::from_integer(v1.clone()).partial_cmp(v2),
+            (Number::Quantity(q1), Number::Quantity(q2)) => {
+                q2.convert(&q1.unit).and_then(|q2| q1.value.partial_cmp(&q2.value))
             }
-            (Number::DecimalNumber(v1), Number::NaturalNumber(v2)) => {
-                v1.partial_cmp(&(ToPrimitive::to_f64(v2).expect("Should not happen")))
+            (Number::Complex(_) | Number::Quantity(_) | Number::Matrix(_), _)
+            | (_, Number::Complex(_) | Number::Quantity(_) | Number::Matrix(_)) => None,
+            (Number::Interval(_), _) | (_, Number::Interval(_)) => interval::compare(self, other),
+            (v1, v2) if matches!(v1, Number::BigDecimal(_)) || matches!(v2, Number::BigDecimal(_)) => {
+                let ctx = Context::default();
+                decimal::from_number(v1, &ctx)?.partial_cmp(&decimal::from_number(v2, &ctx)?)
             }
-            (Number::DecimalNumber(v1), Number::DecimalNumber(v2)) => v1.partial_cmp(&v2),
+            (v1, v2) => f64::from(v1.clone()).partial_cmp(&f64::from(v2.clone())),
         }
     }
 }
 
+/// Converts to the nearest f64. Not a number for a complex number or a matrix.
+///
+/// Prefer [`Number::to_f64_lossy`], that doesn't consume the number,
+/// or `f64::try_from(&number)`, that fails for anything but a real number:
+/// by value, `f64::try_from(number)` is this lossy conversion.
+///
 impl From<Number> for f64 {
     fn from(n: Number) -> f64 {
-        match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_f64(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => v,
-        }
+        n.to_f64_lossy()
     }
 }
 
-#[allow(clippy::cast_possible_truncation)]
-impl From<Number> for BigInt {
-    fn from(n: Number) -> BigInt {
-        match n {
-            Number::NaturalNumber(v) => v,
-            Number::DecimalNumber(v) => BigInt::from_f64(v).expect("Should not happen"),
+/// The checked conversion of an integer [Number] into a primitive integer,
+/// by reference or by value
+///
+macro_rules! try_from_number {
+    ($($t:ty),*) => {$(
+        impl TryFrom<&Number> for $t {
+            type Error = ConversionError;
+
+            fn try_from(n: &Number) -> Result<$t, ConversionError> {
+                let v = BigInt::try_from(n)?;
+                <$t>::try_from(&v).map_err(|_| ConversionError::OutOfRange { value: n.to_string(), target: stringify!($t) })
+            }
         }
-    }
+
+        impl TryFrom<Number> for $t {
+            type Error = ConversionError;
+
+            fn try_from(n: Number) -> Result<$t, ConversionError> {
+                <$t>::try_from(&n)
+            }
+        }
+    )*};
 }
 
-impl From<Number> for i32 {
-    fn from(n: Number) -> i32 {
+try_from_number!(i32, i64, i128, u32, u64, usize);
+
+/// The checked conversion of an integer [Number] into a [`BigInt`]: fails if it has
+/// a fractional part, or if it's not a plain real number
+///
+impl TryFrom<&Number> for BigInt {
+    type Error = ConversionError;
+
+    fn try_from(n: &Number) -> Result<BigInt, ConversionError> {
+        let not_integer = || ConversionError::NotAnInteger { value: n.to_string() };
         match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_i32(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => ToPrimitive::to_i32(&v).expect("Should not happen"), // not good
+            Number::NaturalNumber(v) => Ok(v.clone()),
+            Number::DecimalNumber(v) if v.is_infinite() => Err(ConversionError::OutOfRange { value: n.to_string(), target: "BigInt" }),
+            Number::DecimalNumber(v) if v.fract() == 0. => BigInt::from_f64(*v).ok_or_else(not_integer),
+            Number::BigDecimal(v) if v.is_integer() => Ok(v.with_scale(0).into_bigint_and_exponent().0),
+            Number::DecimalNumber(_) | Number::Rational(_) | Number::BigDecimal(_) => Err(not_integer()),
+            _ => Err(ConversionError::Incompatible { value: n.to_string(), target: "BigInt" }),
         }
     }
 }
 
-impl From<Number> for i64 {
-    fn from(n: Number) -> i64 {
-        match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_i64(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => ToPrimitive::to_i64(&v).expect("Should not happen"), // not good
-        }
+impl TryFrom<Number> for BigInt {
+    type Error = ConversionError;
+
+    fn try_from(n: Number) -> Result<BigInt, ConversionError> {
+        BigInt::try_from(&n)
     }
 }
 
-impl From<Number> for i128 {
-    fn from(n: Number) -> i128 {
+/// The checked conversion of a real [Number] into the nearest f64: fails if it's
+/// too large for a f64, or if it's not a plain real number
+///
+impl TryFrom<&Number> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(n: &Number) -> Result<f64, ConversionError> {
         match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_i128(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => ToPrimitive::to_i128(&v).expect("Should not happen"), // not good
+            Number::DecimalNumber(v) => Ok(*v),
+            Number::NaturalNumber(_) | Number::Rational(_) | Number::BigDecimal(_) => match n.to_f64_lossy() {
+                v if v.is_infinite() => Err(ConversionError::OutOfRange { value: n.to_string(), target: "f64" }),
+                v => Ok(v),
+            },
+            _ => Err(ConversionError::Incompatible { value: n.to_string(), target: "f64" }),
         }
     }
 }
 
+impl Number {
+    /// The nearest f64, that is infinity when it's too large. The real part of a complex number
+    /// is not a number, the value of a quantity or of a measured value is taken without its unit
+    /// or uncertainty, and an interval gives its middle point.
+    ///
+    #[must_use]
+    pub fn to_f64_lossy(&self) -> f64 {
+        match self {
+            Number::NaturalNumber(v) => ToPrimitive::to_f64(v).unwrap_or(f64::NAN),
+            Number::DecimalNumber(v) => *v,
+            Number::Rational(v) => rational_to_f64(v),
+            Number::BigDecimal(v) => ToPrimitive::to_f64(v).unwrap_or(f64::NAN),
+            Number::Complex(_) | Number::Matrix(_) => f64::NAN,
+            Number::Quantity(q) => q.value,
+            Number::Interval(x) => x.mid(),
+            Number::Uncertain(u) => u.value,
+        }
+    }
+
+    /// Whether the number, or any element of a matrix, is not a number (NaN)
+    ///
+    #[must_use]
+    pub fn is_nan(&self) -> bool {
+        match self {
+            Number::DecimalNumber(v) => v.is_nan(),
+            Number::Complex(z) => z.is_nan(),
+            Number::Quantity(q) => q.value.is_nan(),
+            Number::Interval(x) => x.lo().is_nan() || x.hi().is_nan(),
+            Number::Uncertain(u) => u.value.is_nan() || u.error.is_nan(),
+            Number::Matrix(m) => m.elements().iter().any(Number::is_nan),
+            Number::NaturalNumber(_) | Number::Rational(_) | Number::BigDecimal(_) => false,
+        }
+    }
+
+    /// Whether the number, or any element of a matrix, is infinite. An interval is never infinite,
+    /// as its unbounded ends are still a valid enclosure: '1/[-1, 1]' gives '[-inf, inf]'.
+    ///
+    #[must_use]
+    pub fn is_infinite(&self) -> bool {
+        match self {
+            Number::DecimalNumber(v) => v.is_infinite(),
+            Number::Complex(z) => z.is_infinite(),
+            Number::Quantity(q) => q.value.is_infinite(),
+            Number::Uncertain(u) => u.value.is_infinite() || u.error.is_infinite(),
+            Number::Matrix(m) => m.elements().iter().any(Number::is_infinite),
+            Number::NaturalNumber(_) | Number::Rational(_) | Number::BigDecimal(_) | Number::Interval(_) => false,
+        }
+    }
+
+    /// The integer part of a real number, clamped to the range of an i64: '2^100' gives [`i64::MAX`].
+    ///
+    /// # Errors
+    ///
+    /// [`ConversionError::NotAnInteger`] for not a number, or [`ConversionError::Incompatible`]
+    /// if it's not a plain real number
+    ///
+    pub fn to_i64_saturating(&self) -> Result<i64, ConversionError> {
+        let v = self.truncated()?;
+        Ok(ToPrimitive::to_i64(&v).unwrap_or(if v.is_negative() { i64::MIN } else { i64::MAX }))
+    }
+
+    /// A real number rounded to the nearest i64, with the halves away from zero: '2.5' gives 3.
+    ///
+    /// # Errors
+    ///
+    /// [`ConversionError::OutOfRange`] if it doesn't fit into an i64, or the errors of [`Number::to_bigint_rounded`]
+    ///
+    pub fn to_i64_rounded(&self) -> Result<i64, ConversionError> {
+        let v = self.to_bigint_rounded()?;
+        ToPrimitive::to_i64(&v).ok_or_else(|| ConversionError::OutOfRange { value: self.to_string(), target: "i64" })
+    }
+
+    /// A real number rounded to the nearest integer, with the halves away from zero: '-2.5' gives -3.
+    ///
+    /// # Errors
+    ///
+    /// [`ConversionError::NotAnInteger`] for not a number or infinity, or [`ConversionError::Incompatible`]
+    /// if it's not a plain real number
+    ///
+    pub fn to_bigint_rounded(&self) -> Result<BigInt, ConversionError> {
+        match self {
+            Number::Rational(v) => Ok(v.round().to_integer()),
+            Number::BigDecimal(v) => Ok(v.with_scale_round(0, bigdecimal::RoundingMode::HalfUp).into_bigint_and_exponent().0),
+            Number::DecimalNumber(v) => BigInt::try_from(&Number::DecimalNumber(v.round())),
+            n => BigInt::try_from(n),
+        }
+    }
+
+    /// The integer part of a real number, towards zero
+    ///
+    fn truncated(&self) -> Result<BigInt, ConversionError> {
+        match self {
+            Number::Rational(v) => Ok(v.trunc().to_integer()),
+            Number::BigDecimal(v) => Ok(v.with_scale_round(0, bigdecimal::RoundingMode::Down).into_bigint_and_exponent().0),
+            Number::DecimalNumber(v) if v.is_infinite() => Ok(BigInt::from_f64(v.signum() * f64::MAX).expect("Should not happen")),
+            Number::DecimalNumber(v) => BigInt::try_from(&Number::DecimalNumber(v.trunc())),
+            n => BigInt::try_from(n),
+        }
+    }
+}
 
 impl Display for Operator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -392,7 +1210,15 @@ impl Display for Operator {
             Operator::Pow => write!(f, "^"),
             Operator::Une => write!(f, "#"),
             Operator::Fac => write!(f, "!"),
+            Operator::DoubleFac => write!(f, "!!"),
+            Operator::Degree => write!(f, "°"),
+            Operator::Percent => write!(f, "%"),
             Operator::Eql => write!(f, "="),
+            Operator::To => write!(f, "to"),
+            Operator::ElemMul => write!(f, ".*"),
+            Operator::ElemDiv => write!(f, "./"),
+            Operator::ElemPow => write!(f, ".^"),
+            Operator::PlusMinus => write!(f, "±"),
         }
     }
 }
@@ -402,13 +1228,29 @@ impl Display for Bracket {
         match *self {
             Self::Open => write!(f, "("),
             Self::Close => write!(f, ")"),
+            Self::MatrixOpen => write!(f, "["),
+            Self::MatrixClose => write!(f, "]"),
+        }
+    }
+}
+
+impl Display for Separator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Comma => write!(f, ","),
+            Self::Semicolon => write!(f, ";"),
         }
     }
 }
 
+/// The name of the function as it's typed, as 'nroot', so that the error messages match the expressions
+///
 impl Display for MathFunction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", *self)
+        match self.name() {
+            "" => write!(f, "{:?}", *self),
+            name => f.write_str(name),
+        }
     }
 }
 
@@ -420,6 +1262,14 @@ impl Display for Token<'_> {
             Token::Bracket(v) => write!(f, "({v})"),
             Token::Function(v) => write!(f, "({v})"),
             Token::Variable(v) => write!(f, "({v})"),
+            Token::Separator(v) => write!(f, "({v})"),
+            Token::MatrixLiteral(v) => write!(f, "([{v:?}])"),
+            Token::Call(fun, n) => write!(f, "({fun}/{n})"),
+            Token::Lambda(v, body) => {
+                write!(f, "({v} ->")?;
+                body.iter().try_for_each(|(t, _)| write!(f, " {t}"))?;
+                write!(f, ")")
+            }
         }
     }
 }
@@ -503,6 +1353,89 @@ mod tests {
         assert_eq!(Token::tokenize("("), Some(Token::Bracket(Bracket::Open)));
     }
 
+    #[test]
+    fn test_rational_operations() {
+        let third = Number::Rational(BigRational::new(BigInt::from(1), BigInt::from(3)));
+
+        assert_eq!(third.clone() * Number::NaturalNumber(BigInt::from(3)), Number::NaturalNumber(One::one()));
+        assert_eq!(
+            third.clone() + third.clone(),
+            Number::Rational(BigRational::new(BigInt::from(2), BigInt::from(3)))
+        );
+        assert_eq!(third.clone() * Number::DecimalNumber(1.5), Number::DecimalNumber(0.5));
+        assert!(third.clone() < Number::DecimalNumber(0.5));
+        assert_eq!(format!("{third} {third:#}"), "1/3 0.3333333333333333");
+    }
+
+    #[test]
+    fn test_big_decimal_operations() {
+        let tenth = Number::BigDecimal(BigDecimal::new(BigInt::from(1), 1));
+
+        assert_eq!(
+            tenth.clone() + Number::DecimalNumber(0.2),
+            Number::BigDecimal(BigDecimal::new(BigInt::from(3), 1))
+        );
+        assert_eq!(
+            tenth.clone() * Number::NaturalNumber(BigInt::from(5)),
+            Number::BigDecimal(BigDecimal::new(BigInt::from(5), 1))
+        );
+        assert_eq!(tenth.clone() / Number::NaturalNumber(BigInt::from(0)), Number::DecimalNumber(f64::INFINITY));
+        assert!(tenth < Number::DecimalNumber(0.2));
+    }
+
+    #[test]
+    fn test_complex_operations() {
+        let i = Number::Complex(Complex64::new(0., 1.));
+
+        assert_eq!(i.clone() * i.clone(), Number::DecimalNumber(-1.));
+        assert_eq!(i.clone() ^ Number::NaturalNumber(BigInt::from(3)), Number::Complex(Complex64::new(0., -1.)));
+        assert_eq!(
+            Number::NaturalNumber(BigInt::from(2)) + i.clone(),
+            Number::Complex(Complex64::new(2., 1.))
+        );
+        assert_eq!(
+            Number::NaturalNumber(BigInt::from(-4)) ^ Number::DecimalNumber(0.5),
+            Number::Complex(Complex64::new(1.2246467991473532e-16, 2.))
+        );
+        assert_eq!(i.partial_cmp(&Number::NaturalNumber(BigInt::zero())), None);
+        assert_eq!(format!("{} {i}", Number::Complex(Complex64::new(1.5, -2.))), "1.5-2i 1i");
+    }
+
+    #[test]
+    fn test_conversions() {
+        let big = Number::NaturalNumber(BigInt::from(2).pow(100));
+        let nan = Number::DecimalNumber(f64::NAN);
+        let half = Number::Rational(BigRational::new(BigInt::from(-5), BigInt::from(2)));
+
+        assert_eq!(i32::try_from(&Number::DecimalNumber(42.)), Ok(42));
+        assert!(matches!(i32::try_from(&big), Err(ConversionError::OutOfRange { target: "i32", .. })));
+        assert!(matches!(BigInt::try_from(&nan), Err(ConversionError::NotAnInteger { .. })));
+        assert!(matches!(u64::try_from(&half), Err(ConversionError::NotAnInteger { .. })));
+        assert!(matches!(
+            f64::try_from(&Number::Complex(Complex64::i())),
+            Err(ConversionError::Incompatible { target: "f64", .. })
+        ));
+        assert!(f64::try_from(&Number::NaturalNumber(BigInt::from(2).pow(2000))).is_err());
+
+        assert_eq!(big.to_i64_saturating(), Ok(i64::MAX));
+        assert_eq!(Number::DecimalNumber(f64::NEG_INFINITY).to_i64_saturating(), Ok(i64::MIN));
+        assert_eq!(half.to_i64_saturating(), Ok(-2));
+        assert_eq!(half.to_i64_rounded(), Ok(-3));
+        assert_eq!(Number::DecimalNumber(2.5).to_i64_rounded(), Ok(3));
+        assert!(nan.to_i64_rounded().is_err());
+        assert_eq!(half.to_f64_lossy(), -2.5);
+    }
+
+    #[test]
+    fn test_owned_into_integer() {
+        // by value, the conversions are as checked as by reference
+        let large = Number::NaturalNumber(BigInt::from(2).pow(100));
+        assert!(matches!(i32::try_from(large.clone()), Err(ConversionError::OutOfRange { target: "i32", .. })));
+        assert_eq!(BigInt::try_from(large), Ok(BigInt::from(2).pow(100)));
+        assert!(matches!(i64::try_from(Number::Complex(Complex64::i())), Err(ConversionError::Incompatible { .. })));
+        assert_eq!(u32::try_from(Number::DecimalNumber(7.)), Ok(7));
+    }
+
     #[test]
     fn test_operator_priority() {
         assert_eq!(
@@ -0,0 +1,245 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::anyhow;
+use num::{BigInt, ToPrimitive, Zero};
+
+use crate::{
+    error::MathError,
+    token::{MathFunction, Number, Operator},
+};
+
+static OPERAND_ERR: &str = "Runtime error: A value with an uncertainty can only be combined with a real number.";
+
+/// The identifier of the next independent source of error
+///
+static NEXT_SOURCE: AtomicU64 = AtomicU64::new(0);
+
+/// A measured value with its standard uncertainty, such as `9.81 ± 0.02`.
+///
+/// The uncertainty is propagated to first order through the operators and the functions,
+/// with the partial derivatives of the result. Every measured value is an independent source
+/// of error, and a result keeps the share of its error that comes from each source, so that
+/// the errors of the same value cancel out: with 'x = 1 ± 0.1', 'x - x' is '0 ± 0'.
+///
+#[derive(Debug, Clone)]
+pub struct Uncertain {
+    /// the measured value
+    pub value: f64,
+    /// the standard uncertainty, never negative
+    pub error: f64,
+    /// the error that comes from each independent source, as its standard uncertainty
+    /// times the partial derivative of the value in it
+    sources: Vec<(u64, f64)>,
+}
+
+impl Uncertain {
+    /// Builds a measured value with the absolute value of the given uncertainty,
+    /// as a new source of error, independent of all the others
+    ///
+    #[must_use]
+    pub fn new(value: f64, error: f64) -> Uncertain {
+        let source = NEXT_SOURCE.fetch_add(1, Ordering::Relaxed);
+        Uncertain::with_sources(value, vec![(source, error.abs())])
+    }
+
+    /// A value with no uncertainty
+    ///
+    fn exact(value: f64) -> Uncertain {
+        Uncertain::with_sources(value, Vec::new())
+    }
+
+    /// A value with the errors of its sources, that add up in quadrature
+    ///
+    fn with_sources(value: f64, sources: Vec<(u64, f64)>) -> Uncertain {
+        let error = sources.iter().fold(0., |error: f64, (_, e)| error.hypot(*e));
+        Uncertain { value, error, sources }
+    }
+
+    /// Applies a function, whose derivative in the value is `slope`
+    ///
+    fn map(&self, value: f64, slope: f64) -> Uncertain {
+        Uncertain::with_sources(value, self.sources.iter().map(|&(source, e)| (source, slope * e)).collect())
+    }
+}
+
+/// Two measured values are equal when their values and their uncertainties are,
+/// whatever the sources of their errors
+///
+impl PartialEq for Uncertain {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value && self.error == other.error
+    }
+}
+
+/// Rounds the uncertainty to two significant digits, and the value to the same decimal place:
+/// '19.620000000000005 ± 0.04000000000000001' is displayed as '19.620 ± 0.040'.
+/// The alternate flag ('{:#}') displays all the digits.
+///
+impl Display for Uncertain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() || self.error == 0. || !self.error.is_finite() || !self.value.is_finite() {
+            return write!(f, "{} ± {}", self.value, self.error);
+        }
+        let decimals = 1. - self.error.log10().floor();
+        if let Some(decimals) = decimals.to_usize() {
+            write!(f, "{:.*} ± {:.*}", decimals, self.value, decimals, self.error)
+        } else {
+            let unit = 10f64.powf(-decimals);
+            write!(f, "{} ± {}", (self.value / unit).round() * unit, (self.error / unit).round() * unit)
+        }
+    }
+}
+
+/// Sees a real [Number] as a value with no uncertainty
+///
+fn as_uncertain(n: &Number) -> anyhow::Result<Uncertain> {
+    match n {
+        Number::Uncertain(u) => Ok(u.clone()),
+        Number::Complex(_) | Number::Quantity(_) | Number::Matrix(_) | Number::Interval(_) => Err(anyhow!(OPERAND_ERR)),
+        n => Ok(Uncertain::exact(n.clone().into())),
+    }
+}
+
+/// Evaluates a binary [Operator] between two numbers, at least one of which is a [`Number::Uncertain`],
+/// or builds one with the [`Operator::PlusMinus`]: '9.81 ± 0.02'
+///
+pub(crate) fn arithmetic(op: Operator, left: &Number, right: &Number) -> anyhow::Result<Number> {
+    let a = as_uncertain(left)?;
+    let b = as_uncertain(right)?;
+
+    // the errors of the result out of its partial derivatives in a and b, adding up
+    // the ones that come from the same source
+    let propagate = |value: f64, da: f64, db: f64| {
+        let mut sources: Vec<(u64, f64)> = Vec::new();
+        for (&(source, e), slope) in a.sources.iter().map(|s| (s, da)).chain(b.sources.iter().map(|s| (s, db))) {
+            let e = if e == 0. { 0. } else { slope * e };
+            match sources.iter_mut().find(|(other, _)| *other == source) {
+                Some((_, total)) => *total += e,
+                None => sources.push((source, e)),
+            }
+        }
+        Uncertain::with_sources(value, sources)
+    };
+    let res = match op {
+        Operator::PlusMinus => {
+            let error = Uncertain::new(a.value, b.value);
+            Uncertain::with_sources(a.value, [a.sources.as_slice(), error.sources.as_slice()].concat())
+        }
+        Operator::Add => propagate(a.value + b.value, 1., 1.),
+        Operator::Sub => propagate(a.value - b.value, 1., -1.),
+        Operator::Mul => propagate(a.value * b.value, b.value, a.value),
+        Operator::Div => propagate(a.value / b.value, 1. / b.value, -a.value / (b.value * b.value)),
+        Operator::Pow => {
+            let value = a.value.powf(b.value);
+            propagate(value, b.value * a.value.powf(b.value - 1.), value * a.value.ln())
+        }
+        _ => return Err(anyhow!("Runtime error: Operator '{op}' can't be applied to a value with an uncertainty.")),
+    };
+    Ok(Number::Uncertain(res))
+}
+
+/// Evaluates a [`MathFunction`] over a measured value: the uncertainty of the result
+/// is the uncertainty of the argument times the derivative of the function.
+/// Fails with a [`MathError::Domain`] when the value is outside the real domain of the function.
+///
+pub(crate) fn function(fun: MathFunction, u: &Uncertain) -> anyhow::Result<Number> {
+    let x = u.value;
+    let res = match fun {
+        MathFunction::Sin => u.map(x.sin(), x.cos()),
+        MathFunction::Cos => u.map(x.cos(), -x.sin()),
+        MathFunction::Tan => u.map(x.tan(), 1. / (x.cos() * x.cos())),
+        MathFunction::ASin => u.map(x.asin(), 1. / (1. - x * x).sqrt()),
+        MathFunction::ACos => u.map(x.acos(), -1. / (1. - x * x).sqrt()),
+        MathFunction::ATan => u.map(x.atan(), 1. / (1. + x * x)),
+        MathFunction::Ln => u.map(x.ln(), 1. / x),
+        MathFunction::Log => u.map(x.log10(), 1. / (x * std::f64::consts::LN_10)),
+        MathFunction::Sqrt => u.map(x.sqrt(), 1. / (2. * x.sqrt())),
+        MathFunction::Abs => u.map(x.abs(), x.signum()),
+        MathFunction::Sinh => u.map(x.sinh(), x.cosh()),
+        MathFunction::Cosh => u.map(x.cosh(), x.sinh()),
+        MathFunction::Tanh => u.map(x.tanh(), 1. / (x.cosh() * x.cosh())),
+        MathFunction::ASinh => u.map(x.asinh(), 1. / (x * x + 1.).sqrt()),
+        MathFunction::ACosh => u.map(x.acosh(), 1. / (x * x - 1.).sqrt()),
+        MathFunction::ATanh => u.map(x.atanh(), 1. / (1. - x * x)),
+        MathFunction::Exp => u.map(x.exp(), x.exp()),
+        MathFunction::Log2 => u.map(x.log2(), 1. / (x * std::f64::consts::LN_2)),
+        MathFunction::Cbrt => u.map(x.cbrt(), 1. / (3. * x.cbrt() * x.cbrt())),
+        MathFunction::Re | MathFunction::Conj => u.clone(),
+        MathFunction::Arg if x < 0. => return Ok(Number::DecimalNumber(std::f64::consts::PI)),
+        MathFunction::Im | MathFunction::Arg => return Ok(Number::NaturalNumber(BigInt::zero())),
+        _ => return Err(anyhow!("Runtime error: Function '{fun}' can't be applied to a value with an uncertainty.")),
+    };
+    if res.value.is_nan() || res.error.is_nan() {
+        return Err(MathError::domain(fun, u).into());
+    }
+    Ok(Number::Uncertain(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measured(value: f64, error: f64) -> Number {
+        Number::Uncertain(Uncertain::new(value, error))
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let g = arithmetic(Operator::PlusMinus, &Number::DecimalNumber(9.81), &Number::DecimalNumber(0.02)).unwrap();
+        assert_eq!(g, measured(9.81, 0.02));
+
+        let res = arithmetic(Operator::Add, &measured(1., 0.3), &measured(2., 0.4)).unwrap();
+        assert_eq!(res, measured(3., 0.5));
+
+        let res = arithmetic(Operator::Mul, &measured(2., 0.1), &Number::NaturalNumber(BigInt::from(3))).unwrap();
+        assert_eq!(res, measured(6., 0.30000000000000004));
+
+        let res = arithmetic(Operator::Pow, &measured(3., 0.1), &Number::NaturalNumber(BigInt::from(2))).unwrap();
+        assert_eq!(res, measured(9., 0.6000000000000001));
+
+        assert!(arithmetic(Operator::Add, &measured(1., 0.1), &Number::Complex(num::complex::Complex64::i())).is_err());
+    }
+
+    #[test]
+    fn test_correlation() {
+        let x = measured(1., 0.1);
+        let y = measured(2., 0.2);
+        assert_eq!(arithmetic(Operator::Sub, &x, &x).unwrap(), measured(0., 0.));
+        assert_eq!(arithmetic(Operator::Add, &x, &x).unwrap(), measured(2., 0.2));
+        assert_eq!(arithmetic(Operator::Div, &x, &x).unwrap(), measured(1., 0.));
+        let sum = arithmetic(Operator::Add, &x, &y).unwrap();
+        assert_eq!(arithmetic(Operator::Sub, &sum, &x).unwrap(), measured(2., 0.2));
+        // two distinct measures are independent
+        assert_eq!(arithmetic(Operator::Sub, &x, &measured(1., 0.1)).unwrap(), measured(0., 0.1f64.hypot(0.1)));
+
+        let Number::Uncertain(u) = x else { unreachable!() };
+        let (Number::Uncertain(sin), Number::Uncertain(cos)) = (function(MathFunction::Sin, &u).unwrap(), function(MathFunction::Cos, &u).unwrap()) else {
+            unreachable!()
+        };
+        let sin2 = arithmetic(Operator::Mul, &Number::Uncertain(sin.clone()), &Number::Uncertain(sin)).unwrap();
+        let cos2 = arithmetic(Operator::Mul, &Number::Uncertain(cos.clone()), &Number::Uncertain(cos)).unwrap();
+        let Number::Uncertain(one) = arithmetic(Operator::Add, &sin2, &cos2).unwrap() else { unreachable!() };
+        assert!(one.error < 1e-15);
+    }
+
+    #[test]
+    fn test_function() {
+        let res = function(MathFunction::Sin, &Uncertain::new(0., 0.1)).unwrap();
+        assert_eq!(res, measured(0., 0.1));
+        let res = function(MathFunction::Sqrt, &Uncertain::new(4., 0.4)).unwrap();
+        assert_eq!(res, measured(2., 0.1));
+        let err = function(MathFunction::Sqrt, &Uncertain::new(-1., 0.1)).unwrap_err();
+        assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+        assert!(function(MathFunction::ASin, &Uncertain::new(2., 0.1)).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Uncertain::new(19.620000000000005, 0.04000000000000001).to_string(), "19.620 ± 0.040");
+        assert_eq!(Uncertain::new(12345.6, 230.).to_string(), "12350 ± 230");
+        assert_eq!(format!("{:#}", Uncertain::new(1.25, 0.5)), "1.25 ± 0.5");
+    }
+}
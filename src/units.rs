@@ -0,0 +1,391 @@
+use std::{collections::BTreeMap, fmt::Display, ops::Mul};
+
+use anyhow::anyhow;
+use num::ToPrimitive;
+
+use crate::token::{MathFunction, Number, Operator};
+
+static DIMENSION_ERR: &str = "Runtime error: Dimension mismatch between";
+static FRACTIONAL_POWER_ERR: &str = "Runtime error: The unit can't be raised to that power:";
+static DIMENSIONLESS_ERR: &str = "Runtime error: The argument of the function must be dimensionless:";
+static COMPLEX_QUANTITY_ERR: &str = "Runtime error: A quantity can't be a complex number.";
+
+/// The exponents of the 7 SI base dimensions: length, mass, time, electric current,
+/// thermodynamic temperature, amount of substance and luminous intensity
+///
+pub type Dimension = [i32; 7];
+
+/// A unit of measure that can be named in an expression
+///
+struct UnitDef {
+    symbol: &'static str,
+    /// how many SI base units are in one of this unit
+    factor: f64,
+    dimension: Dimension,
+    /// whether it accepts a SI prefix, such as the 'k' of 'km'
+    prefixable: bool,
+}
+
+const fn unit(symbol: &'static str, factor: f64, dimension: Dimension, prefixable: bool) -> UnitDef {
+    UnitDef { symbol, factor, dimension, prefixable }
+}
+
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0];
+const AREA: Dimension = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0];
+
+/// The SI base units, the SI derived units and some common non-SI units
+///
+static UNITS: &[UnitDef] = &[
+    // SI base units. The kilogram is the prefixed gram.
+    unit("m", 1., LENGTH, true),
+    unit("g", 1e-3, MASS, true),
+    unit("s", 1., TIME, true),
+    unit("A", 1., [0, 0, 0, 1, 0, 0, 0], true),
+    unit("K", 1., [0, 0, 0, 0, 1, 0, 0], true),
+    unit("mol", 1., [0, 0, 0, 0, 0, 1, 0], true),
+    unit("cd", 1., [0, 0, 0, 0, 0, 0, 1], true),
+    // SI derived units
+    unit("Hz", 1., [0, 0, -1, 0, 0, 0, 0], true),
+    unit("N", 1., [1, 1, -2, 0, 0, 0, 0], true),
+    unit("Pa", 1., PRESSURE, true),
+    unit("J", 1., ENERGY, true),
+    unit("W", 1., [2, 1, -3, 0, 0, 0, 0], true),
+    unit("C", 1., [0, 0, 1, 1, 0, 0, 0], true),
+    unit("V", 1., [2, 1, -3, -1, 0, 0, 0], true),
+    unit("Ohm", 1., [2, 1, -3, -2, 0, 0, 0], true),
+    unit("S", 1., [-2, -1, 3, 2, 0, 0, 0], true),
+    unit("F", 1., [-2, -1, 4, 2, 0, 0, 0], true),
+    unit("Wb", 1., [2, 1, -2, -1, 0, 0, 0], true),
+    unit("T", 1., [0, 1, -2, -1, 0, 0, 0], true),
+    unit("H", 1., [2, 1, -2, -2, 0, 0, 0], true),
+    // non-SI units
+    unit("L", 1e-3, VOLUME, true),
+    unit("min", 60., TIME, false),
+    unit("h", 3600., TIME, false),
+    unit("day", 86400., TIME, false),
+    unit("in", 0.0254, LENGTH, false),
+    unit("ft", 0.3048, LENGTH, false),
+    unit("yd", 0.9144, LENGTH, false),
+    unit("mi", 1609.344, LENGTH, false),
+    unit("nmi", 1852., LENGTH, false),
+    unit("ha", 1e4, AREA, false),
+    unit("gal", 3.785_411_784e-3, VOLUME, false),
+    unit("lb", 0.453_592_37, MASS, false),
+    unit("oz", 0.028_349_523_125, MASS, false),
+    unit("eV", 1.602_176_634e-19, ENERGY, true),
+    unit("cal", 4.184, ENERGY, true),
+    unit("Wh", 3600., ENERGY, true),
+    unit("bar", 1e5, PRESSURE, true),
+    unit("atm", 101_325., PRESSURE, false),
+    unit("psi", 6_894.757_293_168_361, PRESSURE, false),
+];
+
+/// The SI prefixes. 'u' stands for micro.
+///
+static PREFIXES: &[(&str, f64)] = &[
+    ("da", 1e1),
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+    ("z", 1e-21),
+    ("y", 1e-24),
+];
+
+/// Finds out the factor and the dimension of a unit symbol, possibly with a SI prefix
+///
+fn lookup(symbol: &str) -> Option<(f64, Dimension)> {
+    if let Some(def) = UNITS.iter().find(|u| u.symbol == symbol) {
+        return Some((def.factor, def.dimension));
+    }
+    PREFIXES.iter().find_map(|(prefix, scale)| {
+        let def = UNITS.iter().find(|u| u.prefixable && symbol.strip_prefix(prefix) == Some(u.symbol))?;
+        Some((scale * def.factor, def.dimension))
+    })
+}
+
+/// A product of named units raised to integer powers, i.e. 'km/h' or 'kg*m/s^2'.
+/// The empty product is a dimensionless unit.
+///
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Unit(BTreeMap<String, i32>);
+
+impl Unit {
+    /// Builds a [Unit] from a single symbol, such as 'm', 'km' or 'h',
+    /// or returns [`None`] if the symbol is not known
+    ///
+    #[must_use]
+    pub fn parse(symbol: &str) -> Option<Unit> {
+        lookup(symbol).map(|_| Unit(BTreeMap::from([(symbol.to_string(), 1)])))
+    }
+
+    /// How many SI base units are in one of this unit
+    ///
+    #[must_use]
+    pub fn factor(&self) -> f64 {
+        let power = |sign: i32| -> f64 {
+            self.0
+                .iter()
+                .filter(|(_, exp)| exp.signum() == sign)
+                .filter_map(|(symbol, exp)| lookup(symbol).map(|(factor, _)| factor.powi(exp.abs())))
+                .product()
+        };
+        // dividing, rather than multiplying by the inverse, keeps 'km/h' closer to 1000/3600
+        power(1) / power(-1)
+    }
+
+    /// The exponents of the SI base dimensions of this unit
+    ///
+    #[must_use]
+    pub fn dimension(&self) -> Dimension {
+        let mut dimension = Dimension::default();
+        for (symbol, exp) in &self.0 {
+            if let Some((_, dim)) = lookup(symbol) {
+                dimension.iter_mut().zip(dim).for_each(|(d, x)| *d += x * exp);
+            }
+        }
+        dimension
+    }
+
+    /// Checks if the unit has no dimension at all, such as 'm/km'
+    ///
+    #[must_use]
+    pub fn is_dimensionless(&self) -> bool {
+        self.dimension() == Dimension::default()
+    }
+
+    /// Raises the unit to an integer power
+    ///
+    #[must_use]
+    pub fn powi(&self, n: i32) -> Unit {
+        Unit(self.0.iter().map(|(s, exp)| (s.clone(), exp * n)).filter(|(_, exp)| *exp != 0).collect())
+    }
+
+    /// Raises the unit to a power, as long as every exponent stays an integer
+    ///
+    fn powf(&self, p: f64) -> Option<Unit> {
+        let mut unit = BTreeMap::new();
+        for (symbol, exp) in &self.0 {
+            let e = f64::from(*exp) * p;
+            if e.fract() != 0. {
+                return None;
+            }
+            unit.insert(symbol.clone(), e.to_i32()?);
+        }
+        unit.retain(|_, exp| *exp != 0);
+        Some(Unit(unit))
+    }
+}
+
+#[allow(clippy::suspicious_arithmetic_impl)] // multiplying units adds up their exponents
+impl Mul for Unit {
+    type Output = Unit;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut product = self.0;
+        for (symbol, exp) in rhs.0 {
+            *product.entry(symbol).or_default() += exp;
+        }
+        product.retain(|_, exp| *exp != 0);
+        Unit(product)
+    }
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let power = |(s, exp): (&String, i32)| if exp == 1 { s.clone() } else { format!("{s}^{exp}") };
+        let num: Vec<String> = self.0.iter().filter(|(_, e)| **e > 0).map(|(s, e)| power((s, *e))).collect();
+        let den: Vec<String> = self.0.iter().filter(|(_, e)| **e < 0).map(|(s, e)| power((s, -e))).collect();
+
+        match (num.is_empty(), den.len()) {
+            (_, 0) => write!(f, "{}", num.join("*")),
+            (true, _) => write!(f, "1/{}", den.join("/")),
+            (false, _) => write!(f, "{}/{}", num.join("*"), den.join("/")),
+        }
+    }
+}
+
+/// A value tagged with a [Unit] of measure, i.e. '3 km/h'
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Quantity {
+    /// the magnitude, in the given unit
+    pub value: f64,
+    /// the unit of measure
+    pub unit: Unit,
+}
+
+impl Quantity {
+    /// Tags a value with a unit of measure
+    ///
+    #[must_use]
+    pub fn new(value: f64, unit: Unit) -> Quantity {
+        Quantity { value, unit }
+    }
+
+    /// The value of the quantity in SI base units
+    ///
+    #[must_use]
+    pub fn to_si(&self) -> f64 {
+        self.value * self.unit.factor()
+    }
+
+    /// Converts the quantity into another unit with the same dimension,
+    /// or returns [`None`] if the dimensions differ
+    ///
+    #[must_use]
+    pub fn convert(&self, unit: &Unit) -> Option<Quantity> {
+        let ratio = self.unit.clone() * unit.powi(-1);
+        ratio
+            .is_dimensionless()
+            .then(|| Quantity::new(self.value * ratio.factor(), unit.clone()))
+    }
+}
+
+impl Display for Quantity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+/// Sees any [Number] as a [Quantity]: a plain number is a dimensionless one
+///
+fn as_quantity(n: &Number) -> anyhow::Result<Quantity> {
+    match n {
+        Number::Quantity(q) => Ok(q.clone()),
+        Number::Complex(_) => Err(anyhow!(COMPLEX_QUANTITY_ERR)),
+        n => Ok(Quantity::new(n.clone().into(), Unit::default())),
+    }
+}
+
+/// Describes the unit of a quantity in an error message
+///
+fn describe(q: &Quantity) -> String {
+    if q.unit.0.is_empty() {
+        "a dimensionless number".to_string()
+    } else {
+        format!("'{}'", q.unit)
+    }
+}
+
+/// Evaluates a binary [Operator] between two numbers, at least one of which is a
+/// [`Number::Quantity`]. The right side of '+' and '-' is converted into the unit
+/// of the left side, and must have its same dimension.
+///
+pub(crate) fn arithmetic(op: Operator, left: &Number, right: &Number) -> anyhow::Result<Number> {
+    let a = as_quantity(left)?;
+    let b = as_quantity(right)?;
+
+    let q = match op {
+        Operator::Add | Operator::Sub => {
+            let b = b
+                .convert(&a.unit)
+                .ok_or_else(|| anyhow!("{} {} and {}.", DIMENSION_ERR, describe(&a), describe(&b)))?;
+            let value = if op == Operator::Add { a.value + b.value } else { a.value - b.value };
+            Quantity::new(value, a.unit)
+        }
+        Operator::Mul => Quantity::new(a.value * b.value, a.unit * b.unit),
+        Operator::Div => Quantity::new(a.value / b.value, a.unit * b.unit.powi(-1)),
+        Operator::Pow if b.unit.0.is_empty() => pow(&a, b.value)?,
+        Operator::Pow => return Err(anyhow!("{} {}.", FRACTIONAL_POWER_ERR, describe(&b))),
+        Operator::To => convert(&a, &b)?,
+        _ => return Err(anyhow!("Runtime error: Operator '{op}' can't be applied to a quantity.")),
+    };
+    Ok(Number::from_quantity(q))
+}
+
+/// Converts a quantity into the unit of another one: '3 ft to m'
+///
+fn convert(q: &Quantity, to: &Quantity) -> anyhow::Result<Quantity> {
+    q.convert(&to.unit)
+        .ok_or_else(|| anyhow!("{} {} and {}.", DIMENSION_ERR, describe(q), describe(to)))
+}
+
+fn pow(q: &Quantity, p: f64) -> anyhow::Result<Quantity> {
+    let unit = q.unit.powf(p).ok_or_else(|| anyhow!("{FRACTIONAL_POWER_ERR} {p}."))?;
+    Ok(Quantity::new(q.value.powf(p), unit))
+}
+
+/// Evaluates a [`MathFunction`] over a quantity. Only 'abs' and 'sqrt' keep the unit:
+/// every other function needs a dimensionless argument.
+///
+pub(crate) fn function(fun: MathFunction, q: &Quantity) -> anyhow::Result<Number> {
+    match fun {
+        MathFunction::Abs => Ok(Number::Quantity(Quantity::new(q.value.abs(), q.unit.clone()))),
+        MathFunction::Sqrt => pow(q, 0.5).map(Number::from_quantity),
+        _ => Err(anyhow!("{} {}.", DIMENSIONLESS_ERR, describe(q))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::BigInt;
+
+    use super::*;
+
+    fn quantity(value: f64, unit: &str) -> Quantity {
+        Quantity::new(value, Unit::parse(unit).unwrap())
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("km"), Some((1e3, LENGTH)));
+        assert_eq!(lookup("kg"), Some((1., MASS)));
+        assert_eq!(lookup("min"), Some((60., TIME)));
+        assert_eq!(lookup("kmin"), None);
+        assert_eq!(lookup("x"), None);
+    }
+
+    #[test]
+    fn test_unit_display() {
+        let n = Unit::parse("kg").unwrap() * Unit::parse("m").unwrap() * Unit::parse("s").unwrap().powi(-2);
+        assert_eq!(n.to_string(), "kg*m/s^2");
+        assert_eq!(n.dimension(), [1, 1, -2, 0, 0, 0, 0]);
+        assert_eq!(Unit::parse("Hz").unwrap().powi(-1).to_string(), "1/Hz");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let five_m = Number::Quantity(quantity(5., "m"));
+        let km = Number::Quantity(quantity(1., "km"));
+
+        let sum = arithmetic(Operator::Add, &five_m, &km).unwrap();
+        assert_eq!(sum, Number::Quantity(quantity(1005., "m")));
+
+        let ratio = arithmetic(Operator::Div, &km, &five_m).unwrap();
+        assert_eq!(ratio, Number::DecimalNumber(200.));
+
+        let two = Number::NaturalNumber(BigInt::from(2));
+        assert!(arithmetic(Operator::Add, &five_m, &two).is_err());
+        assert_eq!(
+            arithmetic(Operator::Pow, &five_m, &two).unwrap().to_string(),
+            "25 m^2"
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let feet = quantity(3., "ft");
+        let meters = feet.convert(&Unit::parse("m").unwrap()).unwrap();
+        assert!((meters.value - 0.9144).abs() < 1e-12);
+        assert_eq!(feet.convert(&Unit::parse("s").unwrap()), None);
+    }
+}
@@ -1,6 +1,12 @@
-use num::BigInt;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use num::{complex::Complex64, BigInt};
+use yarer::error::MathError;
+use yarer::interval::Interval;
 use yarer::rpn_resolver::*;
-use yarer::session::Session;
+use yarer::uncertainty::Uncertain;
+use yarer::session::{AngleMode, FloatPolicy, Limits, NumericMode, RoundingMode, Session};
 use yarer::token::*;
 
 macro_rules! resolve {
@@ -11,6 +17,37 @@ macro_rules! resolve {
     }};
 }
 
+/// The value of an expression in a session, which must resolve
+///
+fn eval(session: &Session, expr: &str) -> Number {
+    session.process(expr).resolve().unwrap()
+}
+
+/// The value of an expression in a session, or the error it gives
+///
+fn try_eval(session: &Session, expr: &str) -> anyhow::Result<Number> {
+    session.process(expr).resolve()
+}
+
+/// The value of an expression in a session, as a float
+///
+fn eval_f64(session: &Session, expr: &str) -> f64 {
+    f64::try_from(&eval(session, expr)).unwrap()
+}
+
+/// The bounds of an expression that resolves to an interval
+///
+fn bounds(session: &Session, expr: &str) -> (f64, f64) {
+    match eval(session, expr) {
+        Number::Interval(x) => (x.lo(), x.hi()),
+        n => panic!("{n} is not an interval"),
+    }
+}
+
+fn natural(n: i64) -> Number {
+    Number::NaturalNumber(BigInt::from(n))
+}
+
 #[test]
 fn test_expressions() {
     resolve!(
@@ -34,7 +71,8 @@ fn test_expressions() {
         "tan(pi / 4) * cos(pi / 6)",
         Number::DecimalNumber(0.8660254037844386)
     ); // Approximately sqrt(3)/2
-    resolve!("ln(e) + log10(100)", Number::DecimalNumber(1.0));
+    resolve!("ln(e) + log(100)", Number::DecimalNumber(3.0));
+    resolve!("SIN(1) + Cos(0)", Number::DecimalNumber(1.8414709848078965));
     //resolve!("3 * 2^3! - 2 * 3 + 6 / (2 + 1)", Number::NaturalNumber(230));
     resolve!(
         "cos(sin(0.5) * pi / 2)",
@@ -47,7 +85,7 @@ fn test_expressions() {
         )
     );
     resolve!("2 ^ 3 ^ 2", Number::NaturalNumber(BigInt::from(512)));
-    resolve!("ln(e^2) - log10(1000)", Number::DecimalNumber(2.));
+    resolve!("ln(e^2) - log(1000)", Number::DecimalNumber(-1.));
     resolve!(
         "pi^2 - e^2",
         Number::DecimalNumber(
@@ -71,7 +109,7 @@ fn test_expressions() {
         "(2 + 3) * (4 - 5) + (6 - 7) * (8 + 9)",
         Number::NaturalNumber(BigInt::from(-22))
     );
-    resolve!("ln(e^3) / log10(1000)", Number::DecimalNumber(3.));
+    resolve!("ln(e^3) / log(1000)", Number::DecimalNumber(1.));
     resolve!("(2^2 + 3^2) * (4^2 + 5^2)", Number::NaturalNumber(BigInt::from(533)));
     resolve!(
         "pi*e*(pi-e)",
@@ -115,7 +153,7 @@ fn test_sharing_session() {
     if let (Ok(a), Ok(b)) = (res.resolve(), res2.resolve()) {
         assert!(a == Number::NaturalNumber(BigInt::from(100)));
 
-        let b : i64 = b.into(); 
+        let b = i64::try_from(b).unwrap();
         assert!(b == 3265920i64);
     }
 }
@@ -127,3 +165,674 @@ fn test_session_set() {
     let mut resolver: RpnResolver = session.process("x+2*3/(4-5)");
     assert_eq!(resolver.resolve().unwrap(), Number::DecimalNumber(-2.0));
 }
+
+#[test]
+fn test_symbols() {
+    let session = Session::init();
+    let resolver = session.process("area = pi * r^2 + ln(h)");
+    let symbols = resolver.symbols();
+
+    assert_eq!(symbols.free_variables.keys().collect::<Vec<_>>(), vec!["h", "r"]);
+    assert_eq!(symbols.assigned_variables.keys().collect::<Vec<_>>(), vec!["area"]);
+    assert_eq!(symbols.functions.keys().collect::<Vec<_>>(), vec!["ln"]);
+}
+
+#[test]
+fn test_rational_mode() {
+    let session = Session::init();
+    session.set_numeric_mode(NumericMode::Rational);
+
+    let mut resolver = session.process("10/4 + 2^-2");
+    assert_eq!(resolver.resolve().unwrap().to_string(), "11/4");
+
+    let mut resolver = session.process("(1/3 + 1/6) * 2");
+    assert_eq!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(1)));
+
+    let mut resolver = session.process("1/4 + 0.5");
+    assert_eq!(resolver.resolve().unwrap(), Number::DecimalNumber(0.75));
+}
+
+#[test]
+fn test_decimal_mode() {
+    let session = Session::init();
+    session.set_numeric_mode(NumericMode::Decimal { precision: 50, rounding: RoundingMode::HalfEven });
+
+    let decimal = |s: &str| Number::BigDecimal(BigDecimal::from_str(s).unwrap());
+
+    let mut resolver = session.process("0.1 + 0.2");
+    assert_eq!(resolver.resolve().unwrap(), decimal("0.3"));
+
+    let mut resolver = session.process("2/3");
+    assert_eq!(resolver.resolve().unwrap(), decimal("0.66666666666666666666666666666666666666666666666667"));
+
+    let mut resolver = session.process("sqrt(2) - 2^0.5");
+    assert_eq!(resolver.resolve().unwrap(), decimal("0"));
+
+    let mut resolver = session.process("ln(e^3) + cos(pi)");
+    assert_eq!(resolver.resolve().unwrap(), decimal("2"));
+
+    session.set_numeric_mode(NumericMode::Decimal { precision: 5, rounding: RoundingMode::Down });
+    let mut resolver = session.process("2/3");
+    assert_eq!(resolver.resolve().unwrap(), decimal("0.66666"));
+}
+
+#[test]
+fn test_complex_numbers() {
+    resolve!("sqrt(-4)", Number::Complex(Complex64::new(0., 2.)));
+    resolve!("i^2", Number::DecimalNumber(-1.));
+    resolve!("(3 + 4*i) * conj(3 + 4*i)", Number::DecimalNumber(25.));
+    resolve!("abs(3 + 4*i)", Number::DecimalNumber(5.));
+    resolve!("re(3 - 4*i) + im(3 - 4*i)", Number::DecimalNumber(-1.));
+    resolve!("arg(-1)", Number::DecimalNumber(std::f64::consts::PI));
+    resolve!("ln(-1)", Number::Complex(Complex64::new(0., std::f64::consts::PI)));
+    resolve!("(1 + i) / (1 - i)", Number::Complex(Complex64::new(0., 1.)));
+
+    let session = Session::init();
+    let mut resolver = session.process("e^(i*pi) + 1");
+    let Number::Complex(z) = resolver.resolve().unwrap() else { panic!("Expected a complex number") };
+    assert!(z.norm() < 1e-15);
+
+    // the constant can be assigned, as a variable again
+    session.set("i", 0);
+    assert_eq!(session.process("i + 1").resolve().unwrap(), Number::NaturalNumber(BigInt::from(1)));
+}
+
+#[test]
+fn test_units() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "5 m / 2 s + 3 km/h").to_string(), "3.3333333333333335 m/s");
+    assert_eq!(eval(&session, "60 mi/h to km/h").to_string(), "96.56064 km/h");
+    assert_eq!(eval(&session, "2 kg*m/s^2 to N").to_string(), "2 N");
+    assert_eq!(eval(&session, "1 kWh to J").to_string(), "3600000 J");
+    assert_eq!(eval(&session, "(3 m)^2 / 9 m^2").to_string(), "1");
+    assert_eq!(eval(&session, "sqrt(16 m^2)").to_string(), "4 m");
+    // a unit after a variable or a constant multiplies it, and after anything else is an error
+    session.set("x", 5);
+    assert_eq!(eval(&session, "x m").to_string(), "5 m");
+    assert_eq!(eval(&session, "2*x km/h to m/s").to_string(), "2.7777777777777777 m/s");
+    assert_eq!(eval(&session, "pi m").to_string(), "3.141592653589793 m");
+    assert!(try_eval(&session, "(1 + 1) m").is_err());
+    assert!(try_eval(&session, "sqrt(4) m").is_err());
+    // 'min' is the unit after a number, and the function when it's called
+    assert_eq!(eval(&session, "5 min to s").to_string(), "300 s");
+    assert_eq!(eval(&session, "60 m/min to m/s").to_string(), "1 m/s");
+    assert_eq!(eval(&session, "5*min(2, 3)").to_string(), "10");
+
+    assert!(try_eval(&session, "5 m + 2 s").unwrap_err().to_string().contains("Dimension mismatch"));
+    assert!(try_eval(&session, "3 ft to kg").is_err());
+    assert!(try_eval(&session, "ln(2 m)").is_err());
+
+    let mut resolver = session.process("d = 3 ft to m");
+    resolver.resolve().unwrap();
+    let mut resolver = session.process("d + 1 m");
+    assert_eq!(resolver.resolve().unwrap().to_string(), "1.9144 m");
+}
+
+#[test]
+fn test_matrices() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "[1,2;3,4] * [5;6]").to_string(), "[17; 39]");
+    assert_eq!(eval(&session, "det([1,2;3,4])").to_string(), "-2");
+    assert_eq!(eval(&session, "inv([1,2;3,4])").to_string(), "[-2, 1; 1.5, -0.5]");
+    assert_eq!(eval(&session, "transpose([1,2;3,4]) - [1,3;2,4]").to_string(), "[0, 0; 0, 0]");
+    assert_eq!(eval(&session, "[1,2;3,4] .* [5,6;7,8]").to_string(), "[5, 12; 21, 32]");
+    assert_eq!(eval(&session, "[1,2;3,4]^2").to_string(), "[7, 10; 15, 22]");
+    assert_eq!(eval(&session, "dot([1,2,3], [4,5,6])").to_string(), "32");
+    assert_eq!(eval(&session, "cross([1,0,0], [0,1,0])").to_string(), "[0, 0, 1]");
+    assert_eq!(eval(&session, "norm([3,4])").to_string(), "5");
+    assert_eq!(eval(&session, "[-1, 2*3] * 2").to_string(), "[-2, 12]");
+    assert_eq!(eval(&session, "[1+2] * 3").to_string(), "9");
+
+    assert!(try_eval(&session, "[1,2] + [1,2,3]").unwrap_err().to_string().contains("1x2 and 1x3"));
+    assert!(try_eval(&session, "[1,2;3,4] * [1,2]").is_err());
+    assert!(try_eval(&session, "[1,2;3]").is_err());
+    assert!(try_eval(&session, "inv([1,2;2,4])").is_err());
+    assert!(try_eval(&session, "det([1,2,3])").is_err());
+
+    session.set_numeric_mode(NumericMode::Rational);
+    let mut resolver = session.process("inv([1,2;3,4])");
+    assert_eq!(resolver.resolve().unwrap().to_string(), "[-2, 1; 3/2, -1/2]");
+}
+
+#[test]
+fn test_intervals() {
+    let session = Session::init();
+    session.set_interval_mode(Some(0.01));
+
+    let (lo, hi) = bounds(&session, "[1.9, 2.1] * [2.9, 3.1]");
+    assert!(lo <= 5.51 && lo > 5.5099);
+    assert!(hi >= 6.51 && hi < 6.5101);
+
+    session.set("r", 10);
+    let (lo, hi) = bounds(&session, "pi * r^2");
+    assert!(lo <= std::f64::consts::PI * 9.9 * 9.9 && hi >= std::f64::consts::PI * 10.1 * 10.1);
+
+    assert_eq!(bounds(&session, "[1, 2] / [-2, -1]"), (-2., -0.5));
+    assert_eq!(bounds(&session, "abs([-3, 2])"), (0., 3.));
+    assert_eq!(bounds(&session, "sin([0, 4])").1, 1.);
+    assert_eq!(bounds(&session, "sqrt([-1, 4])"), (0., 2.0000000000000004));
+
+    let res = session.process("[2, 1]").resolve();
+    assert!(res.unwrap_err().to_string().contains("lower one first"));
+    assert!(session.process("ln([-2, -1])").resolve().is_err());
+    assert!(session.process("[1, 2] + 3 m").resolve().is_err());
+
+    session.set_interval("x", Interval::new(1., 2.).unwrap());
+    assert_eq!(bounds(&session, "x * 3"), (3., 6.));
+
+    session.set_interval_mode(None);
+    assert_eq!(
+        session.process("[1, 2] * 2").resolve().unwrap().to_string(),
+        "[2, 4]"
+    );
+
+    // out of interval mode, the intervals are built with interval(lo, hi)
+    let session = Session::init();
+    let (lo, hi) = bounds(&session, "interval(1.9, 2.1) * interval(2.9, 3.1)");
+    assert!(lo <= 1.9 * 2.9 && lo > 5.5 && hi >= 2.1 * 3.1 && hi < 6.52);
+    assert_eq!(bounds(&session, "interval(1, 2) + 1"), (2., 3.));
+    assert!(try_eval(&session, "interval(3, 1)").is_err());
+    assert!(try_eval(&session, "[1.9, 2.1] * [2.9, 3.1]").is_err());
+}
+
+#[test]
+fn test_uncertainty() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "g = 9.81 ± 0.02").to_string(), "9.810 ± 0.020");
+    assert_eq!(eval(&session, "2 * g").to_string(), "19.620 ± 0.040");
+    assert_eq!(eval(&session, "(3 +- 0.3) + (4 +- 0.4)").to_string(), "7.00 ± 0.50");
+    assert_eq!(eval(&session, "sqrt(4 ± 0.4)").to_string(), "2.00 ± 0.10");
+    assert_eq!(eval(&session, "1 + -2").to_string(), "-1");
+
+    session.process("t = 1.5 ± 0.1").resolve().unwrap();
+    let Number::Uncertain(h) = session.process("g * t^2 / 2").resolve().unwrap() else {
+        panic!("not a measured value")
+    };
+    assert!((h.value - 11.03625).abs() < 1e-12);
+    assert!((h.error - 1.4717).abs() < 1e-4);
+    assert_eq!(format!("{:#}", Number::Uncertain(Uncertain::new(1.25, 0.5))), "1.25 ± 0.5");
+
+    // the errors of the same measured value cancel out, the ones of distinct values don't
+    session.process("x = 1 ± 0.1").resolve().unwrap();
+    assert_eq!(eval(&session, "x - x").to_string(), "0 ± 0");
+    assert_eq!(eval(&session, "x + x").to_string(), "2.00 ± 0.20");
+    assert_eq!(eval(&session, "(1 ± 0.1) - (1 ± 0.1)").to_string(), "0.00 ± 0.14");
+    assert_eq!(eval(&session, "(x + g) - g").to_string(), "1.00 ± 0.10");
+
+    assert!(try_eval(&session, "(1 ± 0.1) * i").is_err());
+    assert!(try_eval(&session, "(1 ± 0.1) * 2 m").is_err());
+
+    session.set_float_policy(FloatPolicy::ErrorOnNaN);
+    let err = session.process("sqrt(-1 ± 0.1)").resolve().unwrap_err();
+    assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+}
+
+#[test]
+fn test_factorial() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "5!"), Number::NaturalNumber(BigInt::from(120)));
+    assert_eq!(eval(&session, "(3!)!"), Number::NaturalNumber(BigInt::from(720)));
+    assert_eq!(eval(&session, "7!!"), Number::NaturalNumber(BigInt::from(105)));
+    assert_eq!(eval(&session, "8!!"), Number::NaturalNumber(BigInt::from(384)));
+    assert_eq!(eval(&session, "5.0!"), Number::DecimalNumber(120.));
+
+    let half = eval_f64(&session, "0.5!");
+    assert!((half - std::f64::consts::PI.sqrt() / 2.).abs() < 1e-14);
+
+    let big = eval(&session, "3000!").to_string();
+    assert_eq!(big.len(), 9131);
+    assert!(big.starts_with("41493596034378540855568670930866"));
+
+    let err = try_eval(&session, "(-3)!").unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<MathError>(),
+        Some(&MathError::Domain { operation: "!".to_string(), value: "-3".to_string() })
+    );
+    assert!(try_eval(&session, "(-2)!!").is_err());
+    assert!(try_eval(&session, "2.5!!").is_err());
+}
+
+#[test]
+fn test_limits() {
+    let session = Session::init();
+    session.set_limits(Limits {
+        max_tokens: Some(20),
+        max_depth: Some(3),
+        max_bits: Some(4096),
+        max_steps: Some(30),
+        max_time: Some(std::time::Duration::from_secs(5)),
+    });
+    let limit = |expr: &str| match session.process(expr).resolve().unwrap_err().downcast::<MathError>() {
+        Ok(MathError::LimitExceeded { limit, .. }) => limit,
+        other => panic!("{other:?}"),
+    };
+
+    assert_eq!(limit("9^9^9^9"), "size of an integer");
+    assert_eq!(limit("10000000!"), "size of an integer");
+    assert_eq!(limit("2^4000 * 2^4000"), "size of an integer");
+    assert_eq!(limit("(-3)^9000"), "size of an integer");
+    assert_eq!(limit("((((1))))"), "nesting depth");
+    assert_eq!(limit("1+1+1+1+1+1+1+1+1+1+1"), "number of tokens");
+
+    assert_eq!(session.process("2^4000").resolve().unwrap().to_string().len(), 1205);
+    assert_eq!(session.process("400!").resolve().unwrap().to_string().len(), 869);
+    assert_eq!(session.process("1^(10^30)").resolve().unwrap(), Number::DecimalNumber(1.));
+
+    session.set_limits(Limits { max_steps: Some(10), ..Limits::default() });
+    assert_eq!(limit("1+1+1+1+1+1"), "number of evaluation steps");
+    session.set_limits(Limits::default());
+    assert_eq!(session.process("2^(2^40)").resolve().unwrap(), Number::DecimalNumber(f64::INFINITY));
+}
+
+#[test]
+fn test_float_policy() {
+    let session = Session::init();
+    let nan = session.process("0/0.").resolve().unwrap();
+    assert!(nan.is_nan());
+    assert_eq!(session.process("ln(0)").resolve().unwrap(), Number::DecimalNumber(f64::NEG_INFINITY));
+    assert_eq!(session.process("isnan(0/0.)").resolve().unwrap(), Number::NaturalNumber(BigInt::from(1)));
+    assert_eq!(session.process("isinf(ln(0))").resolve().unwrap(), Number::NaturalNumber(BigInt::from(1)));
+    assert_eq!(session.process("isinf(2) + isnan(2)").resolve().unwrap(), Number::NaturalNumber(BigInt::from(0)));
+
+    session.set_float_policy(FloatPolicy::ErrorOnNonFinite);
+    let err = session.process("2 * ln(0)").resolve().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<MathError>(),
+        Some(&MathError::NonFinite { operation: "ln".to_string(), value: "-inf".to_string() })
+    );
+    assert!(session.process("0/0.").resolve().is_err());
+    assert!(session.process("10.^400").resolve().is_err());
+    assert_eq!(session.process("sqrt(-4)").resolve().unwrap(), Number::Complex(Complex64::new(0., 2.)));
+
+    session.set_float_policy(FloatPolicy::ErrorOnNaN);
+    assert_eq!(session.process("10.^400").resolve().unwrap(), Number::DecimalNumber(f64::INFINITY));
+    let err = session.process("ln(0) - ln(0)").resolve().unwrap_err();
+    assert_eq!(err.to_string(), "Runtime error: '-' gave NaN.");
+
+    // the functions are named as they're typed
+    let err = session.process("mod_pow(2, 3, 0)").resolve().unwrap_err();
+    assert_eq!(err.to_string(), "Runtime error: 'mod_pow' is not defined for 0.");
+}
+
+#[test]
+fn test_angle_mode() {
+    let session = Session::init();
+    assert_eq!(eval(&session, "deg(pi)"), Number::DecimalNumber(180.));
+    assert_eq!(eval(&session, "rad(180)"), Number::DecimalNumber(std::f64::consts::PI));
+    assert_eq!(eval(&session, "90°"), Number::DecimalNumber(std::f64::consts::FRAC_PI_2));
+    assert_eq!(eval(&session, "sin(90°)"), Number::DecimalNumber(1.));
+
+    session.set_angle_mode(AngleMode::Degrees);
+    assert_eq!(eval(&session, "sin(30)"), Number::DecimalNumber(0.5));
+    assert_eq!(eval(&session, "cos(60°)"), Number::DecimalNumber(0.5));
+    assert_eq!(eval(&session, "tan(-45)"), Number::DecimalNumber(-1.));
+    assert_eq!(eval(&session, "sin(180) + cos(270)"), Number::DecimalNumber(0.));
+    assert_eq!(eval(&session, "asin(0.5)"), Number::DecimalNumber(30.));
+    assert_eq!(eval(&session, "atan(1)"), Number::DecimalNumber(45.));
+    assert_eq!(eval(&session, "acos(-1)"), Number::DecimalNumber(180.));
+    assert_eq!(eval(&session, "deg(pi)"), Number::DecimalNumber(180.));
+    assert_eq!(eval(&session, "sin([0, 90])"), eval(&session, "[0., 1.]"));
+
+    session.set_angle_mode(AngleMode::Gradians);
+    assert_eq!(eval(&session, "cos(200)"), Number::DecimalNumber(-1.));
+    assert_eq!(eval(&session, "asin(1)"), Number::DecimalNumber(100.));
+
+    session.set_angle_mode(AngleMode::Degrees);
+    session.set_numeric_mode(NumericMode::Decimal { precision: 30, rounding: RoundingMode::HalfEven });
+    assert_eq!(eval(&session, "sin(30)"), Number::BigDecimal(BigDecimal::from_str("0.5").unwrap()));
+    assert_eq!(eval(&session, "atan(1)"), Number::BigDecimal(BigDecimal::from(45)));
+}
+
+#[test]
+fn test_math_library() {
+    let session = Session::init();
+    let close = |expr: &str, expected: f64| {
+        let value: f64 = f64::try_from(&eval(&session, expr)).unwrap();
+        assert!((value - expected).abs() < 1e-12, "{expr} = {value}");
+    };
+
+    close("sinh(1)", 1.1752011936438014);
+    close("cosh(1) - sinh(1)", (-1f64).exp());
+    close("tanh(0.5)", 0.46211715726000974);
+    close("asinh(sinh(2))", 2.);
+    close("acosh(cosh(2))", 2.);
+    close("atanh(0.5)", 0.5493061443340549);
+    close("exp(1)", std::f64::consts::E);
+    close("log2(1024)", 10.);
+    close("sec(0) + csc(pi/2) + cot(pi/4)", 3.);
+    close("nroot(2, 2)", std::f64::consts::SQRT_2);
+    assert!(matches!(eval(&session, "acosh(0.5)"), Number::Complex(_)));
+
+    assert_eq!(eval(&session, "floor(-2.5)"), natural(-3));
+    assert_eq!(eval(&session, "ceil(2.1)"), natural(3));
+    assert_eq!(eval(&session, "round(2.5)"), natural(3));
+    assert_eq!(eval(&session, "trunc(-2.7)"), natural(-2));
+    assert_eq!(eval(&session, "fract(2.5)"), Number::DecimalNumber(0.5));
+    assert_eq!(eval(&session, "sign(-7)"), natural(-1));
+    assert_eq!(eval(&session, "cbrt(-27)"), natural(-3));
+    assert_eq!(eval(&session, "nroot(10^40, 4)"), natural(10_000_000_000));
+    assert_eq!(eval(&session, "nroot(-32, 5)"), natural(-2));
+    assert_eq!(eval(&session, "gcd(2^40, 6^20)"), natural(1 << 20));
+    assert_eq!(eval(&session, "lcm(4, 6)"), natural(12));
+    assert_eq!(eval(&session, "mod_pow(4, 13, 497)"), natural(445));
+    assert_eq!(eval(&session, "mod_pow(3, 10^30, 1000)"), natural(1));
+    assert_eq!(eval(&session, "ncr(52, 5)"), natural(2_598_960));
+    assert_eq!(eval(&session, "npr(10, 3)"), natural(720));
+    assert_eq!(eval(&session, "ncr(100, 50)").to_string(), "100891344545564193334812497256");
+
+    let err = session.process("gcd(1.5, 2)").resolve().unwrap_err();
+    assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+    assert!(session.process("mod_pow(2, 10, 0)").resolve().is_err());
+    assert!(session.process("nroot(8, 0)").resolve().is_err());
+    // a function given more or fewer arguments than it takes, or values left over, are errors
+    assert_eq!(try_eval(&session, "sin(1, 2)").unwrap_err().to_string(), "Runtime error: Function 'sin' takes one argument, not 2.");
+    assert!(try_eval(&session, "nroot(8, 3, 1)").is_err());
+    assert!(try_eval(&session, "sin()").is_err());
+    assert!(try_eval(&session, "(1, 2)").is_err());
+    assert!(try_eval(&session, "2 (3)").is_err());
+
+    session.set_numeric_mode(NumericMode::Rational);
+    assert_eq!(eval(&session, "floor(7/2) + fract(7/2)"), eval(&session, "7/2"));
+    assert_eq!(eval(&session, "cbrt(27/8)"), eval(&session, "3/2"));
+}
+
+#[test]
+fn test_statistics() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "sum(1, 2, 3, 4)"), natural(10));
+    assert_eq!(eval(&session, "sum()"), natural(0));
+    // the quantities keep their unit
+    assert_eq!(eval(&session, "sum(1 m, 2 m)").to_string(), "3 m");
+    assert_eq!(eval(&session, "avg(1 m, 3 m)").to_string(), "2 m");
+    assert_eq!(eval(&session, "var(1 m, 3 m)").to_string(), "2 m^2");
+    assert!(try_eval(&session, "sum(1 m, 2 s)").is_err());
+    assert_eq!(eval(&session, "sum(2^70, 1) - 2^70"), natural(1));
+    assert_eq!(eval(&session, "max(3, 9, -1) + 1"), natural(10));
+    assert_eq!(eval(&session, "2 * min(4, 2, 8)^2"), natural(8));
+    assert_eq!(eval(&session, "max([1, 5; 7, 3])"), natural(7));
+    assert_eq!(eval(&session, "max(1, sin(0), 2*3)"), natural(6));
+    assert_eq!(eval(&session, "avg(1, 2)"), Number::DecimalNumber(1.5));
+    assert_eq!(eval(&session, "median(5, 1, 3)"), natural(3));
+    assert_eq!(eval(&session, "median(4; 1; 3; 2)"), Number::DecimalNumber(2.5));
+    assert_eq!(eval(&session, "mode(3, 1, 3, 1, 2)"), natural(1));
+    assert_eq!(eval(&session, "stddev(2, 4, 4, 4, 5, 5, 7, 9)"), Number::DecimalNumber((32f64 / 7.).sqrt()));
+    assert_eq!(eval(&session, "percentile(90, 1, 2, 3, 4)"), Number::DecimalNumber(3.7));
+    assert_eq!(eval(&session, "percentile(50, 1, 2, 3)"), natural(2));
+    assert_eq!(eval(&session, "gcd(avg(4, 8), 4)"), natural(2));
+
+    assert!(session.process("avg()").resolve().is_err());
+    assert!(session.process("var(1)").resolve().is_err());
+    assert!(session.process("max(1, 2*i)").resolve().is_err());
+    assert!(session.process("percentile(101, 1, 2)").resolve().is_err());
+
+    session.set_numeric_mode(NumericMode::Rational);
+    assert_eq!(eval(&session, "avg(1, 2)"), eval(&session, "3/2"));
+    assert_eq!(eval(&session, "var(1, 2, 3, 4)"), eval(&session, "5/3"));
+
+    let symbols = session.process("max(x, y) + mod_pow(2, 3, 5)").symbols();
+    assert_eq!(symbols.functions["max"], vec![0..3]);
+    assert!(symbols.functions.contains_key("mod_pow"));
+}
+
+#[test]
+fn test_number_theory() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "isprime(97)"), natural(1));
+    assert_eq!(eval(&session, "isprime(2^89 - 1)"), natural(1));
+    assert_eq!(eval(&session, "isprime(561)"), natural(0));
+    assert_eq!(eval(&session, "nextprime(10^12)"), natural(1_000_000_000_039));
+    assert_eq!(eval(&session, "factor(360)").to_string(), "[2, 3; 3, 2; 5, 1]");
+    assert_eq!(eval(&session, "factor(2^64 + 1)").to_string(), "[274177, 1; 67280421310721, 1]");
+    assert_eq!(eval(&session, "phi(36)"), natural(12));
+    assert_eq!(eval(&session, "divisors(28)").to_string(), "[1, 2, 4, 7, 14, 28]");
+    assert_eq!(eval(&session, "sum(divisors(28)) - 28"), natural(28));
+    assert_eq!(eval(&session, "modinv(3, 11)"), natural(4));
+    assert_eq!(eval(&session, "isqrt(10^40 + 5)"), eval(&session, "10^20"));
+    assert_eq!(eval(&session, "digits(100!)"), natural(158));
+
+    let err = session.process("isprime(2.5)").resolve().unwrap_err();
+    assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+    assert!(session.process("modinv(6, 9)").resolve().is_err());
+    assert!(session.process("factor(0)").resolve().is_err());
+    let err = session.process("factor(1)").resolve().unwrap_err();
+    assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+    assert_eq!(eval(&session, "phi(1)"), natural(1));
+    assert_eq!(eval(&session, "divisors(1)").to_string(), "[1]");
+}
+
+#[test]
+fn test_special_functions() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "gamma(21)"), eval(&session, "20!"));
+    assert_eq!(eval(&session, "gamma(101)").to_string().len(), 158);
+    assert_eq!(eval(&session, "erf([0, 1]) + erfc([0, 1])").to_string(), "[1, 1]");
+    assert_eq!(eval(&session, "jn(0, 1) - j0(1)"), Number::DecimalNumber(0.));
+    assert_eq!(eval(&session, "lambertw(1) * exp(lambertw(1))"), Number::DecimalNumber(1.));
+
+    let err = session.process("zeta(1)").resolve().unwrap_err();
+    assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+}
+
+#[test]
+fn test_random() {
+    let session = Session::init();
+    let draw = |expr: &str| session.process(expr).resolve().unwrap();
+
+    session.seed(7);
+    let first: Vec<Number> = (0..5).map(|_| draw("[rand(), randn(), randint(1, 6)]")).collect();
+    session.seed(7);
+    let again: Vec<Number> = (0..5).map(|_| draw("[rand(), randn(), randint(1, 6)]")).collect();
+    assert_eq!(first, again);
+    assert_ne!(first[0], first[1]);
+
+    for _ in 0..100 {
+        let x = draw("rand()");
+        assert!(x >= Number::DecimalNumber(0.) && x < Number::DecimalNumber(1.));
+        let n = draw("randint(-3, 3)");
+        assert!(matches!(n, Number::NaturalNumber(_)) && n >= draw("-3") && n <= draw("3"));
+    }
+    assert_eq!(draw("randint(10^30, 10^30)"), draw("10^30"));
+
+    // a binary operator after a call without arguments
+    for _ in 0..100 {
+        let x = draw("rand() + 1");
+        assert!(x >= Number::DecimalNumber(1.) && x < Number::DecimalNumber(2.));
+        let x = draw("2*rand() - 1");
+        assert!(x >= Number::DecimalNumber(-1.) && x < Number::DecimalNumber(1.));
+    }
+    assert_ne!(draw("randn() - randn()"), draw("randn() - randn()"));
+
+    assert!(!session.process("1 + randint(1, 6)").is_pure());
+    assert!(session.process("1 + sin(x)").is_pure());
+    assert!(session.process("randint(6, 1)").resolve().is_err());
+    assert!(session.process("rand(1)").resolve().is_err());
+}
+
+#[test]
+fn test_finance() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "5%").to_string(), "0.05");
+    assert_eq!(eval(&session, "200 * 15% + 1").to_string(), "31");
+    assert_eq!(eval(&session, "round(pmt(5%/12, 360, 200000) * 100)").to_string(), "-107364");
+    assert_eq!(eval(&session, "round(irr([-100, 60, 60]) * 10^6)").to_string(), "130662");
+    assert_eq!(eval(&session, "round(npv(10%, -10000, 3000, 4200, 6800) * 100)").to_string(), "118844");
+    assert_eq!(eval(&session, "round(nper(1%, -100, -1000, 10000, 1) * 100)").to_string(), "5967");
+
+    session.set_numeric_mode(NumericMode::Rational);
+    assert_eq!(eval(&session, "5%").to_string(), "1/20");
+    assert_eq!(eval(&session, "pmt(1%, 2, 100)").to_string(), "-10201/201");
+    assert_eq!(eval(&session, "ipmt(1%, 1, 2, 100) + ppmt(1%, 1, 2, 100)").to_string(), eval(&session, "pmt(1%, 2, 100)").to_string());
+
+    session.set_numeric_mode(NumericMode::Decimal { precision: 30, rounding: RoundingMode::HalfEven });
+    assert_eq!(eval(&session, "fv(1%, 12, -100)").to_string(), "1268.25030131969720661201");
+    // (sqrt(69) - 7) / 10
+    assert_eq!(eval(&session, "round(irr(-100, 60, 60) * 10^25)").to_string(), "1306623862918074852584263");
+
+    assert!(session.process("pmt(5%, 10)").resolve().is_err());
+    assert!(session.process("pmt(5%, 10, 1000, 0, 2)").resolve().is_err());
+    assert!(session.process("irr(100, 60)").resolve().is_err());
+}
+
+#[test]
+fn test_distributions() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "normcdf(0, 0, 1)"), Number::DecimalNumber(0.5));
+    assert_eq!(eval(&session, "uniform(1, 0, 4)"), Number::DecimalNumber(0.25));
+    assert_eq!(eval(&session, "uniform(7, 0, 4)"), Number::DecimalNumber(1.));
+    assert_eq!(eval(&session, "round(norminv(normcdf(1.5, 0, 1), 0, 1) * 10^6)"), eval(&session, "1500000"));
+    assert_eq!(eval(&session, "round(binomcdf(3, 3, 0.5) * 10^6)"), eval(&session, "1000000"));
+
+    let err = session.process("binompdf(1.5, 10, 0.5)").resolve().unwrap_err();
+    assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::Domain { .. })));
+}
+
+#[test]
+fn test_solve() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "round(solve(x^3 - 2*x - 5, x, 2) * 10^12)"), eval(&session, "2094551481542"));
+    assert_eq!(eval(&session, "round(solve(x^3 - 2*x - 5, x, 0, 3) * 10^12)"), eval(&session, "2094551481542"));
+    assert_eq!(eval(&session, "round(solve(cos(t), t, 1) * 10^12)"), eval(&session, "round(pi/2 * 10^12)"));
+
+    // x is bound to the solve, and the heap is left untouched
+    let resolver = session.process("solve(x^2 - a, x, 1)");
+    assert!(!resolver.symbols().free_variables.contains_key("x"));
+    assert_eq!(resolver.symbols().free_variables["a"], vec![12..13]);
+    session.process("x = 7").resolve().unwrap();
+    session.process("a = 2").resolve().unwrap();
+    assert_eq!(eval(&session, "round(solve(x^2 - a, x, 1) * 10^12)"), eval(&session, "1414213562373"));
+    assert_eq!(eval(&session, "x"), eval(&session, "7"));
+    // a solve within a solve, whose expression depends on the outer variable
+    assert_eq!(eval(&session, "round(solve(solve(y^2 - b, y, 1) - 3, b, 1) * 10^9)"), eval(&session, "9000000000"));
+
+    assert!(session.process("solve(x^2 + 1, x, 1)").resolve().is_err());
+    assert!(session.process("solve(x^2 + 1, x, -1, 1)").resolve().is_err());
+    assert!(session.process("solve(x^2 - 1, 2, 1)").resolve().is_err());
+    assert!(session.process("solve(x^2 - 1, x)").resolve().is_err());
+}
+
+#[test]
+fn test_calculus() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "round(integrate(sin(x)^2, x, 0, pi) * 10^12)"), eval(&session, "round(pi/2 * 10^12)"));
+    assert_eq!(eval(&session, "round(integrate(t^2, t, 0, 3) * 10^12)"), eval(&session, "9 * 10^12"));
+    assert_eq!(eval(&session, "round(deriv(x^3, x, 2) * 10^9)"), eval(&session, "12 * 10^9"));
+    // the second derivative, and the derivative of an integral
+    assert_eq!(eval(&session, "round(deriv(deriv(x^3, x, y), y, 2) * 10^6)"), eval(&session, "12 * 10^6"));
+    assert_eq!(eval(&session, "round(deriv(integrate(exp(t), t, 0, x), x, 1) * 10^9)"), eval(&session, "round(e * 10^9)"));
+
+    session.set_limits(Limits { max_steps: Some(50), ..Limits::default() });
+    let err = session.process("integrate(sin(x)^2, x, 0, pi)").resolve().unwrap_err();
+    assert!(matches!(err.downcast_ref::<MathError>(), Some(MathError::LimitExceeded { .. })));
+    session.set_limits(Limits::default());
+
+    assert!(session.process("integrate(x, x, 0, 1/0.)").resolve().is_err());
+    assert!(session.process("deriv(x^2, x)").resolve().is_err());
+}
+
+#[test]
+fn test_comprehensions() {
+    let session = Session::init();
+
+    assert_eq!(eval(&session, "prodover(i, 1, 50, i)"), eval(&session, "50!"));
+    assert_eq!(eval(&session, "sumover(i, 1, 100, i)"), eval(&session, "5050"));
+    assert_eq!(eval(&session, "sumover(i, 1, 3, sumover(j, 1, i, j))"), eval(&session, "10"));
+    assert_eq!(eval(&session, "sumover(i, 5, 1, i)"), eval(&session, "0"));
+    assert_eq!(eval(&session, "prodover(i, 5, 1, i)"), eval(&session, "1"));
+    assert_eq!(eval(&session, "sumover(k, 0, 20, 2^k)"), eval(&session, "2^21 - 1"));
+
+    // sum and prod stay the sum and the product of their arguments, even of plain variables
+    session.process("a = 1").resolve().unwrap();
+    session.process("b = 2").resolve().unwrap();
+    session.process("c = 3").resolve().unwrap();
+    session.process("d = 4").resolve().unwrap();
+    assert_eq!(eval(&session, "sum(a, b, c, d)"), eval(&session, "10"));
+    assert_eq!(eval(&session, "prod(a, b, c, d)"), eval(&session, "24"));
+
+    // the index hides a variable of the session, that is left untouched
+    session.process("k = 7").resolve().unwrap();
+    assert_eq!(eval(&session, "sumover(k, 1, 4, k)"), eval(&session, "10"));
+    assert_eq!(eval(&session, "k"), eval(&session, "7"));
+
+    session.set_numeric_mode(NumericMode::Rational);
+    assert_eq!(eval(&session, "sumover(k, 1, 10, 1/k)").to_string(), "7381/2520");
+
+    assert!(session.process("sumover(i, 1, 2.5, i)").resolve().is_err());
+    assert!(session.process("sumover(i, 1, 2, 3, i)").resolve().is_err());
+    assert!(session.process("sumover(1, 1, 2, 3)").resolve().is_err());
+}
+
+#[test]
+fn test_symbolic_derivative() {
+    let session = Session::init();
+
+    session.setf("x", 0.7);
+    session.setf("y", 1.3);
+    // the derivatives printed in infix, evaluated again, agree with the numeric ones
+    for expr in [
+        "x^3 - 2*x", "sin(x)*cos(x)", "tan(x) + sec(x) - csc(x) + cot(x)", "asin(x) + acos(x/2) + atan(x)",
+        "sinh(x) + cosh(x) + tanh(x) + asinh(x) + atanh(x) + acosh(x + 1)", "exp(-x^2)/ln(x + 2)",
+        "log(x) + log2(x) + sqrt(x) + cbrt(x) + nroot(x, 5)", "x^x", "2^x", "y/x", "abs(x - 1) + floor(x)",
+        "erf(x) + erfc(x/2)", "j0(x) + j1(x) + jn(3, x)", "lambertw(x)", "normpdf(x, 1, 2) + normcdf(x, 1, 2)",
+        "sum(x, x^2, y) + avg(x, 2*x)", "-(x^2)*y", "30%*x + x°",
+    ] {
+        let derivative = session.process(expr).expression().unwrap().derivative("x").unwrap().to_string();
+        let symbolic = eval_f64(&session, &derivative);
+        let numeric = eval_f64(&session, &format!("deriv({expr}, x, 0.7)"));
+        assert!((symbolic - numeric).abs() < 1e-9 * numeric.abs().max(1.), "{expr}: {derivative} = {symbolic} != {numeric}");
+        assert_eq!(eval_f64(&session, &format!("diff({expr}, x)")), symbolic);
+    }
+
+    // the variable of diff is free, and the derivative can be derived again
+    let resolver = session.process("diff(diff(x^4, x), x)");
+    assert!(resolver.symbols().free_variables.contains_key("x"));
+    session.set("x", 2);
+    assert_eq!(eval_f64(&session, "diff(diff(x^4, x), x)"), 48.);
+    assert_eq!(eval_f64(&session, "solve(diff(x^2 - 4*x, x), x, 0)"), 2.);
+
+    // in degrees and gradians, the trigonometric derivatives carry the chain factor of the mode
+    session.set("x", 30);
+    session.set_angle_mode(AngleMode::Degrees);
+    assert!((eval_f64(&session, "diff(sin(x), x)") - 30f64.to_radians().cos() * std::f64::consts::PI / 180.).abs() < 1e-15);
+    assert!((eval_f64(&session, "diff(asin(x/60), x)") - 180. / std::f64::consts::PI / 60. / 0.75f64.sqrt()).abs() < 1e-12);
+    assert_eq!(eval_f64(&session, "diff(diff(x^4, x), x)"), 10800.);
+    session.set_angle_mode(AngleMode::Gradians);
+    let numeric = eval_f64(&session, "deriv(cos(x), x, 30)");
+    assert!((eval_f64(&session, "diff(cos(x), x)") - numeric).abs() < 1e-9);
+    session.set_angle_mode(AngleMode::Radians);
+    assert_eq!(eval_f64(&session, "diff(sin(x), x)"), 30f64.cos());
+
+    assert!(session.process("diff(gamma(x), x)").resolve().is_err());
+    assert!(session.process("diff(x^2, 2)").resolve().is_err());
+    assert!(session.process("solve(x, x, 1)").expression().is_err());
+}
+
+#[test]
+fn test_simplify() {
+    let session = Session::init();
+
+    session.setf("x", 0.7);
+    session.setf("y", -1.3);
+    for expr in [
+        "0*x + 1*y + (2+3)", "x*x^2/x - 3*x^2 + x^2", "(2*x*y)^3/(x*y^2)", "x^y*x^2*x", "-(x - y) - (y - x)",
+        "sin(x)^2 + 2*sin(x)^2 - cos(x)", "2^10/4^2*x", "(x + 1)*(x + 1)/(x + 1)", "5%*x + x°", "exp(x)*exp(-x)",
+    ] {
+        let simplified = session.process(expr).expression().unwrap().simplify();
+        let (original, simple) = (eval_f64(&session, expr), eval_f64(&session, &simplified.to_string()));
+        assert!((original - simple).abs() < 1e-12 * original.abs().max(1.), "{expr}: {simplified} = {simple} != {original}");
+        assert_eq!(simplified.simplify(), simplified);
+    }
+
+    let gradient = session.process("x^3*y").expression().unwrap().derivative("x").unwrap().simplify();
+    assert_eq!(gradient.to_string(), "3*x^2*y");
+}
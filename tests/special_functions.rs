@@ -0,0 +1,181 @@
+//! Reference values of the special functions and of the probability distributions,
+//! computed with 40 digits by mpmath, against the accuracy that the documentation
+//! of each function promises
+
+use yarer::session::Session;
+
+use Tolerance::{Absolute, Relative};
+
+/// The greatest error allowed, relative to the expected value or absolute
+///
+#[derive(Clone, Copy)]
+enum Tolerance {
+    Relative(f64),
+    Absolute(f64),
+}
+
+static REFERENCE: &[(&str, f64, Tolerance)] = &[
+    ("gamma(0.5)", 1.772453850905516, Relative(1e-14)),
+    ("gamma(1.5)", 0.88622692545275801, Relative(1e-14)),
+    ("gamma(2.5)", 1.329340388179137, Relative(1e-14)),
+    ("gamma(10)", 362880.0, Relative(1e-14)),
+    ("gamma(0.1)", 9.5135076986687318, Relative(1e-14)),
+    ("gamma(-0.5)", -3.5449077018110321, Relative(1e-14)),
+    ("gamma(-1.5)", 2.3632718012073547, Relative(1e-14)),
+    ("gamma(-2.5)", -0.94530872048294188, Relative(1e-14)),
+    ("gamma(30.2)", 1.7410094445911354e31, Relative(2e-13)),
+    ("gamma(100.5)", 9.3209631040827166e156, Relative(2e-13)),
+    ("gamma(170.5)", 5.5620924145599996e305, Relative(2e-13)),
+    ("lgamma(0.5)", 0.57236494292470009, Relative(1e-14)),
+    ("lgamma(3)", 0.69314718055994531, Relative(1e-14)),
+    ("lgamma(10)", 12.80182748008147, Relative(1e-14)),
+    ("lgamma(100)", 359.1342053695754, Relative(1e-14)),
+    ("lgamma(1000)", 5905.2204232091812, Relative(1e-14)),
+    ("lgamma(10^6)", 12815504.569147612, Relative(1e-14)),
+    ("lgamma(-0.5)", 1.2655121234846454, Relative(1e-14)),
+    ("lgamma(-2.5)", -0.056243716497674051, Absolute(1e-14)),
+    ("lgamma(0.1)", 2.252712651734206, Relative(1e-14)),
+    ("beta(2, 3)", 0.083333333333333333, Relative(1e-13)),
+    ("beta(0.5, 0.5)", 3.1415926535897932, Relative(1e-13)),
+    ("beta(1.5, 2.5)", 0.19634954084936208, Relative(1e-13)),
+    ("beta(10, 20)", 4.9925087406346777e-9, Relative(1e-13)),
+    ("beta(200, 300)", 1.6485491608664746e-147, Relative(1e-12)),
+    ("beta(-0.5, 2)", -4.0, Relative(1e-13)),
+    ("erf(0.1)", 0.11246291601828489, Relative(1e-15)),
+    ("erf(0.5)", 0.52049987781304654, Relative(1e-15)),
+    ("erf(1)", 0.84270079294971487, Relative(1e-15)),
+    ("erf(2)", 0.99532226501895273, Relative(1e-15)),
+    ("erf(2.9)", 0.99995890212190054, Relative(1e-15)),
+    ("erf(3)", 0.99997790950300141, Relative(1e-15)),
+    ("erf(4)", 0.9999999845827421, Relative(1e-15)),
+    ("erf(-1)", -0.84270079294971487, Relative(1e-15)),
+    ("erf(-3.5)", -0.99999925690162766, Relative(1e-15)),
+    ("erf(6)", 0.99999999999999998, Relative(1e-15)),
+    ("erfc(0.1)", 0.88753708398171511, Relative(1e-13)),
+    ("erfc(0.5)", 0.47950012218695346, Relative(1e-13)),
+    ("erfc(1)", 0.15729920705028513, Relative(1e-13)),
+    ("erfc(1.9)", 0.0072095707647425301, Relative(1e-13)),
+    ("erfc(2)", 0.0046777349810472658, Relative(1e-13)),
+    ("erfc(3)", 2.2090496998585441e-5, Relative(1e-13)),
+    ("erfc(5)", 1.5374597944280349e-12, Relative(1e-13)),
+    ("erfc(10)", 2.0884875837625448e-45, Relative(1e-13)),
+    ("erfc(20)", 5.3958656116079009e-176, Relative(1e-13)),
+    ("erfc(-1)", 1.8427007929497149, Relative(1e-13)),
+    ("zeta(2)", 1.6449340668482264, Relative(1e-12)),
+    ("zeta(3)", 1.2020569031595943, Relative(1e-12)),
+    ("zeta(4)", 1.0823232337111382, Relative(1e-12)),
+    ("zeta(0.5)", -1.4603545088095868, Relative(1e-12)),
+    ("zeta(0.75)", -3.4412853869452229, Relative(1e-12)),
+    ("zeta(1.5)", 2.6123753486854883, Relative(1e-12)),
+    ("zeta(1.001)", 1000.5772884759015, Relative(1e-12)),
+    ("zeta(10)", 1.0009945751278181, Relative(1e-12)),
+    ("zeta(50)", 1.0000000000000009, Relative(1e-12)),
+    ("zeta(0)", -0.5, Relative(1e-12)),
+    ("zeta(-1)", -0.083333333333333333, Relative(1e-12)),
+    ("zeta(-3)", 0.0083333333333333333, Relative(1e-12)),
+    ("zeta(-2.5)", 0.0085169287778503305, Relative(1e-12)),
+    ("zeta(-10.5)", 0.011146122473942814, Relative(1e-12)),
+    ("j0(0.5)", 0.9384698072408129, Absolute(1e-13)),
+    ("j0(1)", 0.76519768655796655, Absolute(1e-13)),
+    ("j0(2.404825557695773)", -1.2011950073676861e-16, Absolute(1e-13)),
+    ("j0(5)", -0.1775967713143383, Absolute(1e-13)),
+    ("j0(10)", -0.24593576445134834, Absolute(1e-13)),
+    ("j0(25)", 0.096266783275958116, Absolute(1e-13)),
+    ("j0(100)", 0.019985850304223122, Absolute(1e-13)),
+    ("j0(500)", -0.034100556880731998, Absolute(1e-13)),
+    ("j0(2000)", 0.0070983418331996168, Absolute(1e-13)),
+    ("j0(-3)", -0.26005195490193344, Absolute(1e-13)),
+    ("j1(0.5)", 0.24226845767487389, Absolute(1e-13)),
+    ("j1(1)", 0.44005058574493352, Absolute(1e-13)),
+    ("j1(3.8317)", 2.4045590431462726e-6, Absolute(1e-13)),
+    ("j1(10)", 0.043472746168861437, Absolute(1e-13)),
+    ("j1(100)", -0.077145352014112158, Absolute(1e-13)),
+    ("j1(2000)", 0.016370141522854217, Absolute(1e-13)),
+    ("j1(-2)", -0.57672480775687339, Absolute(1e-13)),
+    ("jn(2, 1)", 0.11490348493190048, Absolute(1e-13)),
+    ("jn(5, 1)", 0.00024975773021123443, Absolute(1e-13)),
+    ("jn(10, 3)", 1.2928351645715884e-5, Absolute(1e-13)),
+    ("jn(30, 10)", 1.551096078257467e-12, Absolute(1e-13)),
+    ("jn(3, 50)", 0.092734804061634432, Absolute(1e-13)),
+    ("jn(-3, 2)", -0.12894324947440205, Absolute(1e-13)),
+    ("jn(4, -2)", 0.033995719807568434, Absolute(1e-13)),
+    ("jn(20, 1000.5)", 0.015919264154598918, Absolute(1e-13)),
+    ("jn(2, 5000)", 0.0066453372891628894, Absolute(1e-13)),
+    ("lambertw(1)", 0.56714329040978387, Relative(1e-14)),
+    ("lambertw(-0.3)", -0.48940222718021497, Relative(1e-14)),
+    ("lambertw(-0.36)", -0.80608431597081778, Relative(1e-14)),
+    ("lambertw(0.01)", 0.0099014738435950119, Relative(1e-14)),
+    ("lambertw(2)", 0.85260550201372549, Relative(1e-14)),
+    ("lambertw(10)", 1.7455280027406994, Relative(1e-14)),
+    ("lambertw(100)", 3.3856301402900502, Relative(1e-14)),
+    ("lambertw(10^10)", 20.028685413304951, Relative(1e-14)),
+    ("lambertw(10^300)", 684.24720862976085, Relative(1e-14)),
+    ("lambertw(-1/e)", -1.0, Relative(1e-14)),
+    ("normpdf(1, 0, 1)", 0.24197072451914335, Relative(1e-13)),
+    ("normpdf(130, 100, 15)", 0.0035993977675458701, Relative(1e-13)),
+    ("normcdf(1.96, 0, 1)", 0.97500210485177957, Relative(1e-13)),
+    ("normcdf(-5, 0, 1)", 2.8665157187919391e-7, Relative(1e-13)),
+    ("normcdf(-30, 0, 1)", 4.9067139271481871e-198, Relative(1e-12)),
+    ("normcdf(115, 100, 15)", 0.84134474606854295, Relative(1e-13)),
+    ("norminv(0.5, 0, 1)", 0.0, Relative(1e-13)),
+    ("norminv(0.975, 0, 1)", 1.9599639845400542, Relative(1e-13)),
+    ("norminv(0.01, 0, 1)", -2.3263478740408411, Relative(1e-13)),
+    ("norminv(0.999999, 0, 1)", 4.7534243088228989, Relative(1e-12)),
+    ("norminv(10^-100, 0, 1)", -21.273453560965324, Relative(1e-13)),
+    ("binompdf(3, 10, 0.5)", 0.1171875, Relative(1e-13)),
+    ("binompdf(50, 100, 0.3)", 1.3026227131445356e-5, Relative(1e-12)),
+    ("binompdf(500, 1000, 0.5)", 0.025225018178360802, Relative(1e-12)),
+    ("binomcdf(3, 10, 0.5)", 0.171875, Relative(1e-13)),
+    ("binomcdf(20, 100, 0.3)", 0.016462853241869475, Relative(1e-12)),
+    ("binomcdf(450, 1000, 0.5)", 0.0008652680424881588, Relative(1e-12)),
+    ("poissonpdf(0, 2)", 0.13533528323661269, Relative(1e-13)),
+    ("poissonpdf(5, 2.5)", 0.066800942890542639, Relative(1e-13)),
+    ("poissonpdf(100, 80)", 0.0039394581591992597, Relative(1e-12)),
+    ("tcdf(2, 5)", 0.94903026058507082, Relative(1e-13)),
+    ("tcdf(-1.5, 1)", 0.18716704181099882, Relative(1e-13)),
+    ("tcdf(2.5, 30)", 0.99094217546596665, Relative(1e-13)),
+    ("tcdf(-8, 10)", 5.8874713948330799e-6, Relative(1e-13)),
+    ("tcdf(1, 1000)", 0.84122379095766385, Relative(1e-12)),
+    ("chi2cdf(3.84, 1)", 0.9499564787512949, Relative(1e-13)),
+    ("chi2cdf(1, 10)", 0.00017211562995584078, Relative(1e-13)),
+    ("chi2cdf(30, 10)", 0.9991433587892247, Relative(1e-13)),
+    ("chi2cdf(0.5, 3)", 0.081108588345324141, Relative(1e-13)),
+    ("chi2cdf(150, 100)", 0.99909606795764599, Relative(1e-13)),
+    ("uniform(0.3, 0, 2)", 0.15, Relative(1e-13)),
+];
+
+#[test]
+fn test_reference_values() {
+    let session = Session::init();
+    for &(expr, expected, tolerance) in REFERENCE {
+        let value = f64::try_from(&session.process(expr).resolve().unwrap()).unwrap();
+        let error = (value - expected).abs();
+        let allowed = match tolerance {
+            Relative(t) => t * expected.abs(),
+            Absolute(t) => t,
+        };
+        assert!(error <= allowed, "{expr} = {value}, expected {expected}");
+    }
+}
+
+#[test]
+fn test_domain_errors() {
+    let session = Session::init();
+    for expr in ["gamma(0)", "gamma(-3)", "lgamma(-1)", "beta(-2, 1)", "zeta(1)", "jn(1.5, 2)", "lambertw(-1)", "erf(2i)"] {
+        assert!(session.process(expr).resolve().is_err(), "{expr}");
+    }
+    for expr in [
+        "normpdf(0, 0, -1)",
+        "norminv(1.5, 0, 1)",
+        "binompdf(2.5, 10, 0.5)",
+        "binomcdf(2, -10, 0.5)",
+        "binomcdf(2, 10, 1.1)",
+        "poissonpdf(3, 0)",
+        "tcdf(1, 0)",
+        "chi2cdf(1, -2)",
+        "uniform(1, 3, 2)",
+        "normcdf(0/0., 0, 1)",
+    ] {
+        assert!(session.process(expr).resolve().is_err(), "{expr}");
+    }
+}